@@ -0,0 +1,568 @@
+use crate::{
+    Result,
+    paging::{self, phys_to_virt},
+};
+use bit_field::BitField;
+use core::mem::size_of;
+
+static mut RSDP_ADDRESS: u64 = 0;
+static mut TABLES: Tables = Tables::new();
+
+struct Tables {
+    fadt: Option<&'static Fadt>,
+    madt: Option<&'static Madt>,
+    mcfg: Option<&'static Mcfg>,
+    hpet: Option<&'static Hpet>,
+}
+
+impl Tables {
+    const fn new() -> Self {
+        Self {
+            fadt: None,
+            madt: None,
+            mcfg: None,
+            hpet: None,
+        }
+    }
+}
+
+fn tables() -> &'static mut Tables {
+    #[allow(static_mut_refs)]
+    unsafe {
+        &mut TABLES
+    }
+}
+
+/// Records the physical address of the RSDP handed over by the loader.
+pub fn save_rsdp(address: u64) {
+    unsafe { RSDP_ADDRESS = address };
+}
+
+fn sum_bytes(phys: u64, len: usize) -> u8 {
+    let bytes = unsafe { core::slice::from_raw_parts(phys_to_virt(phys) as *const u8, len) };
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/// Most firmware places the tables in ACPI reclaim/NVS memory, which is already in the direct
+/// map. Anything outside the memory map gets mapped uncached on demand.
+fn ensure_mapped(phys: u64, len: u64) -> Result<()> {
+    let start = phys & !0xfff;
+    let end = phys + len;
+    let mut page = start;
+    while page < end {
+        if paging::translate(phys_to_virt(page)).is_none() {
+            paging::map_mmio(page, 0x1000)?;
+        }
+        page += 0x1000;
+    }
+    Ok(())
+}
+
+/// [5.2.5.3 Root System Description Pointer (RSDP) Structure](https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html#root-system-description-pointer-rsdp-structure)
+#[repr(C, packed)]
+pub struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+impl Rsdp {
+    fn validate(phys: u64) -> Result<&'static Self> {
+        ensure_mapped(phys, size_of::<Self>() as u64)?;
+        let rsdp = unsafe { &*(phys_to_virt(phys) as *const Self) };
+        if &rsdp.signature != b"RSD PTR " {
+            return Err("invalid RSDP signature.");
+        }
+        if sum_bytes(phys, 20) != 0 {
+            return Err("invalid RSDP checksum.");
+        }
+        if rsdp.revision >= 2 && sum_bytes(phys, rsdp.length as usize) != 0 {
+            return Err("invalid RSDP extended checksum.");
+        }
+        Ok(rsdp)
+    }
+}
+
+/// [5.2.6 System Description Table Header](https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html#system-description-table-header)
+#[repr(C, packed)]
+pub struct DescriptionHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+impl DescriptionHeader {
+    fn at(phys: u64) -> Result<&'static Self> {
+        ensure_mapped(phys, size_of::<Self>() as u64)?;
+        let header = unsafe { &*(phys_to_virt(phys) as *const Self) };
+        if header.length() < size_of::<Self>() {
+            return Err("ACPI table is shorter than its header.");
+        }
+        ensure_mapped(phys, header.length as u64)?;
+        if sum_bytes(phys, header.length as usize) != 0 {
+            return Err("invalid ACPI table checksum.");
+        }
+        Ok(header)
+    }
+
+    pub fn signature(&self) -> &[u8; 4] {
+        &self.signature
+    }
+
+    pub fn length(&self) -> usize {
+        self.length as usize
+    }
+
+    fn addr(&self) -> u64 {
+        self as *const Self as u64
+    }
+
+    /// Bytes following the header.
+    fn body(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(
+                (self.addr() as *const u8).add(size_of::<Self>()),
+                self.length() - size_of::<Self>(),
+            )
+        }
+    }
+
+    /// Reinterprets the table as `T` after checking its signature and length.
+    fn cast<T: Table>(&'static self) -> Result<&'static T> {
+        if self.signature() != T::SIGNATURE {
+            return Err("unexpected ACPI table signature.");
+        }
+        if self.length() < size_of::<T>() {
+            return Err("ACPI table is too short.");
+        }
+        Ok(unsafe { &*(self.addr() as *const T) })
+    }
+}
+
+trait Table {
+    const SIGNATURE: &'static [u8; 4];
+}
+
+/// [5.2.8 Extended System Description Table (XSDT)](https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html#extended-system-description-table-xsdt)
+/// and its 32-bit predecessor, the RSDT.
+struct RootTable {
+    header: &'static DescriptionHeader,
+    entry_size: usize,
+}
+
+impl RootTable {
+    fn entries(&self) -> impl Iterator<Item = u64> + '_ {
+        self.header
+            .body()
+            .chunks_exact(self.entry_size)
+            .map(|entry| match entry.len() {
+                8 => u64::from_le_bytes(entry.try_into().unwrap()),
+                _ => u32::from_le_bytes(entry.try_into().unwrap()) as u64,
+            })
+    }
+
+    fn find(&self, signature: &[u8; 4]) -> Option<&'static DescriptionHeader> {
+        self.entries()
+            .filter_map(|phys| DescriptionHeader::at(phys).ok())
+            .find(|header| header.signature() == signature)
+    }
+}
+
+/// [5.2.9 Fixed ACPI Description Table (FADT)](https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html#fixed-acpi-description-table-fadt)
+#[repr(C, packed)]
+pub struct Fadt {
+    header: DescriptionHeader,
+    firmware_ctrl: u32,
+    dsdt: u32,
+    reserved: u8,
+    preferred_pm_profile: u8,
+    sci_int: u16,
+    smi_cmd: u32,
+    acpi_enable: u8,
+    acpi_disable: u8,
+    s4bios_req: u8,
+    pstate_cnt: u8,
+    pm1a_evt_blk: u32,
+    pm1b_evt_blk: u32,
+    pm1a_cnt_blk: u32,
+    pm1b_cnt_blk: u32,
+    pm2_cnt_blk: u32,
+    pm_tmr_blk: u32,
+    gpe0_blk: u32,
+    gpe1_blk: u32,
+    pm1_evt_len: u8,
+    pm1_cnt_len: u8,
+    pm2_cnt_len: u8,
+    pm_tmr_len: u8,
+    gpe0_blk_len: u8,
+    gpe1_blk_len: u8,
+    gpe1_base: u8,
+    cst_cnt: u8,
+    p_lvl2_lat: u16,
+    p_lvl3_lat: u16,
+    flush_size: u16,
+    flush_stride: u16,
+    duty_offset: u8,
+    duty_width: u8,
+    day_alrm: u8,
+    mon_alrm: u8,
+    century: u8,
+    iapc_boot_arch: u16,
+    reserved2: u8,
+    flags: u32,
+}
+
+impl Table for Fadt {
+    const SIGNATURE: &'static [u8; 4] = b"FACP";
+}
+
+#[allow(dead_code)]
+impl Fadt {
+    pub fn sci_interrupt(&self) -> u16 {
+        self.sci_int
+    }
+
+    /// I/O port of the ACPI power management timer, which runs at 3.579545 MHz.
+    pub fn pm_timer_port(&self) -> u16 {
+        self.pm_tmr_blk as u16
+    }
+
+    /// Whether the PM timer counter is 32 bits wide instead of 24 (TMR_VAL_EXT).
+    pub fn pm_timer_is_32bit(&self) -> bool {
+        { self.flags }.get_bit(8)
+    }
+
+    /// CMOS RTC index of the century register, or 0 if there is none.
+    pub fn century_register(&self) -> u8 {
+        self.century
+    }
+
+    /// Whether an i8042 keyboard controller is present (IA-PC boot architecture flag 1).
+    pub fn has_8042(&self) -> bool {
+        // ACPI 1.0 tables leave this field reserved; assume a legacy controller exists.
+        self.header.revision < 2 || { self.iapc_boot_arch }.get_bit(1)
+    }
+}
+
+/// [5.2.12 Multiple APIC Description Table (MADT)](https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html#multiple-apic-description-table-madt)
+#[repr(C, packed)]
+pub struct Madt {
+    header: DescriptionHeader,
+    local_apic_address: u32,
+    flags: u32,
+}
+
+impl Table for Madt {
+    const SIGNATURE: &'static [u8; 4] = b"APIC";
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub enum MadtEntry {
+    LocalApic(Processor),
+    IoApic(IoApic),
+    InterruptSourceOverride(InterruptSourceOverride),
+    LocalApicNmi {
+        processor_id: u8,
+        flags: u16,
+        lint: u8,
+    },
+    LocalApicAddressOverride(u64),
+    LocalX2Apic(Processor),
+    Unknown(u8),
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+    pub processor_id: u32,
+    pub apic_id: u32,
+    pub enabled: bool,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: u32,
+    pub gsi_base: u32,
+}
+
+/// [5.2.12.5 Interrupt Source Override Structure](https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html#interrupt-source-override-structure)
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptSourceOverride {
+    pub bus: u8,
+    pub source: u8,
+    pub gsi: u32,
+    pub flags: u16,
+}
+
+#[allow(dead_code)]
+impl InterruptSourceOverride {
+    /// `None` means "conforms to the bus specification".
+    pub fn active_low(&self) -> Option<bool> {
+        match self.flags.get_bits(0..2) {
+            0b01 => Some(false),
+            0b11 => Some(true),
+            _ => None,
+        }
+    }
+
+    /// `None` means "conforms to the bus specification".
+    pub fn level_triggered(&self) -> Option<bool> {
+        match self.flags.get_bits(2..4) {
+            0b01 => Some(false),
+            0b11 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+pub struct MadtEntries<'a> {
+    body: &'a [u8],
+}
+
+impl Iterator for MadtEntries<'_> {
+    type Item = MadtEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&typ, rest) = self.body.split_first()?;
+        let len = *rest.first()? as usize;
+        if len < 2 || len > self.body.len() {
+            return None;
+        }
+        let (entry, rest) = self.body.split_at(len);
+        self.body = rest;
+
+        let u16_at = |i: usize| u16::from_le_bytes([entry[i], entry[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(entry[i..i + 4].try_into().unwrap());
+        let entry = match (typ, len) {
+            (0, 8..) => MadtEntry::LocalApic(Processor {
+                processor_id: entry[2] as u32,
+                apic_id: entry[3] as u32,
+                enabled: u32_at(4).get_bit(0),
+            }),
+            (1, 12..) => MadtEntry::IoApic(IoApic {
+                id: entry[2],
+                address: u32_at(4),
+                gsi_base: u32_at(8),
+            }),
+            (2, 10..) => MadtEntry::InterruptSourceOverride(InterruptSourceOverride {
+                bus: entry[2],
+                source: entry[3],
+                gsi: u32_at(4),
+                flags: u16_at(8),
+            }),
+            (4, 6..) => MadtEntry::LocalApicNmi {
+                processor_id: entry[2],
+                flags: u16_at(3),
+                lint: entry[5],
+            },
+            (5, 12..) => MadtEntry::LocalApicAddressOverride(u64::from_le_bytes(
+                entry[4..12].try_into().unwrap(),
+            )),
+            (9, 16..) => MadtEntry::LocalX2Apic(Processor {
+                processor_id: u32_at(12),
+                apic_id: u32_at(4),
+                enabled: u32_at(8).get_bit(0),
+            }),
+            _ => MadtEntry::Unknown(typ),
+        };
+        Some(entry)
+    }
+}
+
+#[allow(dead_code)]
+impl Madt {
+    pub fn entries(&self) -> MadtEntries<'_> {
+        MadtEntries {
+            body: &self.header.body()[size_of::<Self>() - size_of::<DescriptionHeader>()..],
+        }
+    }
+
+    /// Physical address of the local APIC, taking an address override entry into account.
+    pub fn local_apic_address(&self) -> u64 {
+        self.entries()
+            .find_map(|entry| match entry {
+                MadtEntry::LocalApicAddressOverride(address) => Some(address),
+                _ => None,
+            })
+            .unwrap_or(self.local_apic_address as u64)
+    }
+
+    /// Whether the system also has dual 8259 PICs that must be masked (PCAT_COMPAT).
+    pub fn has_8259(&self) -> bool {
+        { self.flags }.get_bit(0)
+    }
+
+    pub fn processors(&self) -> impl Iterator<Item = Processor> + '_ {
+        self.entries().filter_map(|entry| match entry {
+            MadtEntry::LocalApic(p) | MadtEntry::LocalX2Apic(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn io_apics(&self) -> impl Iterator<Item = IoApic> + '_ {
+        self.entries().filter_map(|entry| match entry {
+            MadtEntry::IoApic(io_apic) => Some(io_apic),
+            _ => None,
+        })
+    }
+
+    pub fn interrupt_source_overrides(&self) -> impl Iterator<Item = InterruptSourceOverride> + '_ {
+        self.entries().filter_map(|entry| match entry {
+            MadtEntry::InterruptSourceOverride(iso) => Some(iso),
+            _ => None,
+        })
+    }
+}
+
+/// [PCI Firmware Specification 3.2, 4.1.2 MCFG Table Description](https://pcisig.com/specifications)
+#[repr(C, packed)]
+pub struct Mcfg {
+    header: DescriptionHeader,
+    reserved: u64,
+}
+
+impl Table for Mcfg {
+    const SIGNATURE: &'static [u8; 4] = b"MCFG";
+}
+
+/// One enhanced configuration access mechanism (ECAM) region.
+#[derive(Debug, Clone, Copy)]
+pub struct McfgEntry {
+    pub base_address: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl Mcfg {
+    pub fn entries(&self) -> impl Iterator<Item = McfgEntry> + '_ {
+        self.header.body()[size_of::<u64>()..]
+            .chunks_exact(16)
+            .map(|entry| McfgEntry {
+                base_address: u64::from_le_bytes(entry[0..8].try_into().unwrap()),
+                segment: u16::from_le_bytes([entry[8], entry[9]]),
+                start_bus: entry[10],
+                end_bus: entry[11],
+            })
+    }
+}
+
+/// [IA-PC HPET Specification 1.0a, 3.2.4 The ACPI 2.0 HPET Description Table (HPET)](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/software-developers-hpet-spec-1-0a.pdf)
+#[repr(C, packed)]
+pub struct Hpet {
+    header: DescriptionHeader,
+    event_timer_block_id: u32,
+    address_space_id: u8,
+    register_bit_width: u8,
+    register_bit_offset: u8,
+    reserved: u8,
+    base_address: u64,
+    hpet_number: u8,
+    minimum_tick: u16,
+    page_protection: u8,
+}
+
+impl Table for Hpet {
+    const SIGNATURE: &'static [u8; 4] = b"HPET";
+}
+
+#[allow(dead_code)]
+impl Hpet {
+    /// Physical address of the event timer block. Only system memory is supported.
+    pub fn base_address(&self) -> Option<u64> {
+        (self.address_space_id == 0).then_some(self.base_address)
+    }
+
+    pub fn hpet_number(&self) -> u8 {
+        self.hpet_number
+    }
+
+    pub fn minimum_tick(&self) -> u16 {
+        self.minimum_tick
+    }
+
+    pub fn comparators(&self) -> u8 {
+        { self.event_timer_block_id }.get_bits(8..13) as u8 + 1
+    }
+}
+
+/// Validates the RSDP saved by [`save_rsdp`] and locates the tables other subsystems need.
+/// Only the MADT is mandatory.
+pub fn init() -> Result<()> {
+    let rsdp_address = unsafe { RSDP_ADDRESS };
+    if rsdp_address == 0 {
+        return Err("no RSDP.");
+    }
+    let rsdp = Rsdp::validate(rsdp_address)?;
+
+    let root = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        let header = DescriptionHeader::at(rsdp.xsdt_address)?;
+        if header.signature() != b"XSDT" {
+            return Err("invalid XSDT signature.");
+        }
+        RootTable {
+            header,
+            entry_size: size_of::<u64>(),
+        }
+    } else {
+        let header = DescriptionHeader::at(rsdp.rsdt_address as u64)?;
+        if header.signature() != b"RSDT" {
+            return Err("invalid RSDT signature.");
+        }
+        RootTable {
+            header,
+            entry_size: size_of::<u32>(),
+        }
+    };
+
+    let tables = tables();
+    tables.fadt = find(&root)?;
+    tables.madt = find(&root)?;
+    tables.mcfg = find(&root)?;
+    tables.hpet = find(&root)?;
+    if tables.madt.is_none() {
+        return Err("no MADT.");
+    }
+
+    Ok(())
+}
+
+fn find<T: Table>(root: &RootTable) -> Result<Option<&'static T>> {
+    root.find(T::SIGNATURE)
+        .map(|header| header.cast::<T>())
+        .transpose()
+}
+
+#[allow(dead_code)]
+pub fn fadt() -> Option<&'static Fadt> {
+    tables().fadt
+}
+
+pub fn madt() -> &'static Madt {
+    tables().madt.expect("acpi::init has not been called.")
+}
+
+pub fn mcfg() -> Option<&'static Mcfg> {
+    tables().mcfg
+}
+
+#[allow(dead_code)]
+pub fn hpet() -> Option<&'static Hpet> {
+    tables().hpet
+}
@@ -0,0 +1,157 @@
+use crate::{
+    Result,
+    memory_manager::{BYTES_PER_FRAME, memory_manager},
+    slab, x86,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr,
+};
+
+/// Number of frames requested from the frame manager whenever the heap runs dry.
+const HEAP_GROW_FRAMES: usize = 256;
+const BLOCK_ALIGN: usize = size_of::<FreeBlock>();
+
+#[global_allocator]
+static ALLOCATOR: KernelAllocator = KernelAllocator;
+
+static mut HEAP: LinkedListHeap = LinkedListHeap::new();
+fn heap() -> &'static mut LinkedListHeap {
+    #[allow(static_mut_refs)]
+    unsafe {
+        &mut HEAP
+    }
+}
+
+#[repr(C, align(16))]
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+/// First-fit free list kept sorted by address so that neighbouring blocks can be merged on free.
+/// Every block boundary is a multiple of `BLOCK_ALIGN`, so a split never leaves a fragment
+/// too small to hold a `FreeBlock`.
+struct LinkedListHeap {
+    head: *mut FreeBlock,
+}
+
+impl LinkedListHeap {
+    const fn new() -> Self {
+        Self {
+            head: ptr::null_mut(),
+        }
+    }
+
+    fn grow(&mut self, min_bytes: usize) -> Result<()> {
+        let num_frames = min_bytes.div_ceil(BYTES_PER_FRAME).max(HEAP_GROW_FRAMES);
+        let mut frame = memory_manager().allocate(num_frames)?;
+        let start = frame.frame().as_mut_ptr();
+        unsafe { self.insert(start, num_frames * BYTES_PER_FRAME) };
+        Ok(())
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let size = block_size(&layout);
+        let align = layout.align().max(BLOCK_ALIGN);
+
+        if let Some(p) = self.allocate_first_fit(size, align) {
+            return p;
+        }
+
+        if self.grow(size + align).is_err() {
+            return ptr::null_mut();
+        }
+        self.allocate_first_fit(size, align)
+            .unwrap_or(ptr::null_mut())
+    }
+
+    fn allocate_first_fit(&mut self, size: usize, align: usize) -> Option<*mut u8> {
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut current = self.head;
+        while !current.is_null() {
+            let block = unsafe { &mut *current };
+            let start = current.addr();
+            let end = start + block.size;
+            let alloc_start = start.next_multiple_of(align);
+            let alloc_end = alloc_start + size;
+
+            if alloc_end <= end {
+                let next = block.next;
+                if prev.is_null() {
+                    self.head = next;
+                } else {
+                    unsafe { (*prev).next = next };
+                }
+
+                unsafe {
+                    if alloc_start > start {
+                        self.insert(current as *mut u8, alloc_start - start);
+                    }
+                    if end > alloc_end {
+                        self.insert(current.with_addr(alloc_end) as *mut u8, end - alloc_end);
+                    }
+                }
+                return Some(current.with_addr(alloc_start) as *mut u8);
+            }
+
+            prev = current;
+            current = block.next;
+        }
+        None
+    }
+
+    /// # Safety
+    /// `[start, start + size)` must be unused memory aligned to `BLOCK_ALIGN`.
+    unsafe fn insert(&mut self, start: *mut u8, size: usize) {
+        let new = start as *mut FreeBlock;
+
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut next = self.head;
+        while !next.is_null() && next.addr() < new.addr() {
+            prev = next;
+            next = unsafe { (*next).next };
+        }
+
+        unsafe {
+            new.write(FreeBlock { size, next });
+            if !next.is_null() && new.addr() + size == next.addr() {
+                (*new).size += (*next).size;
+                (*new).next = (*next).next;
+            }
+
+            if prev.is_null() {
+                self.head = new;
+            } else if prev.addr() + (*prev).size == new.addr() {
+                (*prev).size += (*new).size;
+                (*prev).next = (*new).next;
+            } else {
+                (*prev).next = new;
+            }
+        }
+    }
+}
+
+fn block_size(layout: &Layout) -> usize {
+    layout.size().max(1).next_multiple_of(BLOCK_ALIGN)
+}
+
+/// Small objects are served from the slab size-class caches, everything else from the free list.
+pub struct KernelAllocator;
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if let Some(id) = slab::size_class(&layout) {
+            return slab::allocate(id).unwrap_or(ptr::null_mut());
+        }
+        x86::without_interrupts(|| heap().allocate(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if slab::size_class(&layout).is_some() {
+            unsafe { slab::free(ptr) };
+            return;
+        }
+        x86::without_interrupts(|| unsafe { heap().insert(ptr, block_size(&layout)) });
+    }
+}
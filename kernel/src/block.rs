@@ -0,0 +1,75 @@
+use crate::Result;
+use alloc::{boxed::Box, vec::Vec};
+
+/// Storage read and written in fixed-size blocks, whichever bus it sits on.
+pub trait BlockDevice {
+    fn name(&self) -> &str;
+
+    /// Bytes per block.
+    fn block_size(&self) -> usize;
+
+    fn num_blocks(&self) -> u64;
+
+    /// Reads `buffer.len() / block_size()` blocks starting at block `lba`. The length of
+    /// `buffer` must be a multiple of the block size.
+    fn read(&mut self, lba: u64, buffer: &mut [u8]) -> Result<()>;
+
+    /// Writes `buffer.len() / block_size()` blocks starting at block `lba`.
+    #[allow(dead_code)]
+    fn write(&mut self, lba: u64, buffer: &[u8]) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockDeviceId(usize);
+
+/// A device is taken out of its entry while [`with_device`] runs on it.
+static mut DEVICES: Vec<(BlockDeviceId, Option<Box<dyn BlockDevice>>)> = Vec::new();
+static mut NEXT_ID: usize = 0;
+
+fn devices_mut() -> &'static mut Vec<(BlockDeviceId, Option<Box<dyn BlockDevice>>)> {
+    #[allow(static_mut_refs)]
+    unsafe {
+        &mut DEVICES
+    }
+}
+
+/// Called by drivers once a device is ready for I/O.
+pub fn register(device: Box<dyn BlockDevice>) -> BlockDeviceId {
+    let id = unsafe {
+        NEXT_ID += 1;
+        BlockDeviceId(NEXT_ID)
+    };
+    devices_mut().push((id, Some(device)));
+    id
+}
+
+/// Called by drivers when a device goes away.
+pub fn unregister(id: BlockDeviceId) {
+    devices_mut().retain(|(device, _)| *device != id);
+}
+
+/// Registered devices, in the order they were registered.
+pub fn ids() -> Vec<BlockDeviceId> {
+    devices_mut().iter().map(|(id, _)| *id).collect()
+}
+
+/// Runs `f` on the device registered as `id`. The device is taken out of the registry
+/// meanwhile, so that `f` may register and unregister devices, and it is dropped afterwards
+/// if it was unregistered.
+pub fn with_device<R>(
+    id: BlockDeviceId,
+    f: impl FnOnce(&mut dyn BlockDevice) -> Result<R>,
+) -> Result<R> {
+    let mut device = devices_mut()
+        .iter_mut()
+        .find(|(device, _)| *device == id)
+        .ok_or("no such block device.")?
+        .1
+        .take()
+        .ok_or("block device is in use.")?;
+    let result = f(device.as_mut());
+    if let Some((_, slot)) = devices_mut().iter_mut().find(|(device, _)| *device == id) {
+        *slot = Some(device);
+    }
+    result
+}
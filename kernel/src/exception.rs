@@ -0,0 +1,355 @@
+use crate::{
+    DescriptorType, console,
+    interrupt::{IDT, InterruptFrame, make_idt_attr, set_idt_entry},
+    segment::{IST_DOUBLE_FAULT, IST_MACHINE_CHECK, IST_NMI},
+    serial, stack, x86,
+};
+use bit_field::BitField;
+use core::{
+    arch::naked_asm,
+    fmt::{self, Write},
+};
+
+const EXCEPTION_NAMES: [&str; 32] = [
+    "#DE Divide Error",
+    "#DB Debug",
+    "NMI Non-Maskable Interrupt",
+    "#BP Breakpoint",
+    "#OF Overflow",
+    "#BR BOUND Range Exceeded",
+    "#UD Invalid Opcode",
+    "#NM Device Not Available",
+    "#DF Double Fault",
+    "Coprocessor Segment Overrun",
+    "#TS Invalid TSS",
+    "#NP Segment Not Present",
+    "#SS Stack-Segment Fault",
+    "#GP General Protection",
+    "#PF Page Fault",
+    "Reserved",
+    "#MF x87 Floating-Point Error",
+    "#AC Alignment Check",
+    "#MC Machine Check",
+    "#XM SIMD Floating-Point Exception",
+    "#VE Virtualization Exception",
+    "#CP Control Protection Exception",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "#HV Hypervisor Injection Exception",
+    "#VC VMM Communication Exception",
+    "#SX Security Exception",
+    "Reserved",
+];
+
+const NMI: usize = 2;
+const DOUBLE_FAULT: usize = 8;
+const PAGE_FAULT: usize = 14;
+const MACHINE_CHECK: usize = 18;
+/// #TS, #NP, #SS and #GP, whose error codes are segment selectors. The error codes of the
+/// other vectors are only printed raw.
+const SELECTOR_ERROR_CODES: core::ops::RangeInclusive<usize> = 10..=13;
+
+/// Entry stubs save the general-purpose registers before anything can clobber them, so they
+/// are naked. Vectors without an error code push a zero in its place to keep one stack layout.
+macro_rules! exception_handler {
+    ($name:ident, $vector:expr) => {
+        exception_handler!(@stub $name, $vector, false, "push 0");
+    };
+    ($name:ident, $vector:expr, error_code) => {
+        exception_handler!(@stub $name, $vector, true);
+    };
+    (@stub $name:ident, $vector:expr, $has_error_code:expr $(, $push_error_code:literal)?) => {
+        #[naked]
+        extern "C" fn $name() -> ! {
+            unsafe {
+                naked_asm!(
+                    $($push_error_code,)?
+                    "push rax",
+                    "push rbx",
+                    "push rcx",
+                    "push rdx",
+                    "push rsi",
+                    "push rdi",
+                    "push rbp",
+                    "push r8",
+                    "push r9",
+                    "push r10",
+                    "push r11",
+                    "push r12",
+                    "push r13",
+                    "push r14",
+                    "push r15",
+                    "mov rdi, rsp",
+                    "mov esi, {vector}",
+                    "mov edx, {has_error_code}",
+                    // The CPU aligned the stack before pushing the frame; 21 pushes later it is
+                    // off by 8.
+                    "sub rsp, 8",
+                    "cld",
+                    "call {entry}",
+                    "ud2",
+                    vector = const $vector,
+                    has_error_code = const $has_error_code as u32,
+                    entry = sym fault_entry,
+                )
+            }
+        }
+    };
+}
+
+exception_handler!(handler_0, 0);
+exception_handler!(handler_1, 1);
+exception_handler!(handler_2, 2);
+exception_handler!(handler_3, 3);
+exception_handler!(handler_4, 4);
+exception_handler!(handler_5, 5);
+exception_handler!(handler_6, 6);
+exception_handler!(handler_7, 7);
+exception_handler!(handler_8, 8, error_code);
+exception_handler!(handler_9, 9);
+exception_handler!(handler_10, 10, error_code);
+exception_handler!(handler_11, 11, error_code);
+exception_handler!(handler_12, 12, error_code);
+exception_handler!(handler_13, 13, error_code);
+exception_handler!(handler_14, 14, error_code);
+exception_handler!(handler_15, 15);
+exception_handler!(handler_16, 16);
+exception_handler!(handler_17, 17, error_code);
+exception_handler!(handler_18, 18);
+exception_handler!(handler_19, 19);
+exception_handler!(handler_20, 20);
+exception_handler!(handler_21, 21, error_code);
+exception_handler!(handler_22, 22);
+exception_handler!(handler_23, 23);
+exception_handler!(handler_24, 24);
+exception_handler!(handler_25, 25);
+exception_handler!(handler_26, 26);
+exception_handler!(handler_27, 27);
+exception_handler!(handler_28, 28);
+exception_handler!(handler_29, 29, error_code);
+exception_handler!(handler_30, 30, error_code);
+exception_handler!(handler_31, 31);
+
+pub fn init() {
+    let handlers: [u64; 32] = [
+        handler_0 as usize as u64,
+        handler_1 as usize as u64,
+        handler_2 as usize as u64,
+        handler_3 as usize as u64,
+        handler_4 as usize as u64,
+        handler_5 as usize as u64,
+        handler_6 as usize as u64,
+        handler_7 as usize as u64,
+        handler_8 as usize as u64,
+        handler_9 as usize as u64,
+        handler_10 as usize as u64,
+        handler_11 as usize as u64,
+        handler_12 as usize as u64,
+        handler_13 as usize as u64,
+        handler_14 as usize as u64,
+        handler_15 as usize as u64,
+        handler_16 as usize as u64,
+        handler_17 as usize as u64,
+        handler_18 as usize as u64,
+        handler_19 as usize as u64,
+        handler_20 as usize as u64,
+        handler_21 as usize as u64,
+        handler_22 as usize as u64,
+        handler_23 as usize as u64,
+        handler_24 as usize as u64,
+        handler_25 as usize as u64,
+        handler_26 as usize as u64,
+        handler_27 as usize as u64,
+        handler_28 as usize as u64,
+        handler_29 as usize as u64,
+        handler_30 as usize as u64,
+        handler_31 as usize as u64,
+    ];
+
+    let cs = x86::get_cs();
+    handlers.iter().enumerate().for_each(|(vector, handler)| {
+        let interrupt_stack_table = match vector {
+            DOUBLE_FAULT => IST_DOUBLE_FAULT,
+            NMI => IST_NMI,
+            MACHINE_CHECK => IST_MACHINE_CHECK,
+            _ => 0,
+        };
+        let attr = make_idt_attr(
+            DescriptorType::InterruptGate(),
+            0,
+            true,
+            interrupt_stack_table,
+        );
+        set_idt_entry(unsafe { &mut IDT[vector] }, attr, *handler, cs);
+    });
+}
+
+/// Writes to the console and, when one was detected, to the serial port.
+fn report(args: fmt::Arguments) {
+    #[allow(static_mut_refs)]
+    if let Some(console) = unsafe { console::CONSOLE.as_mut() } {
+        _ = console.write_fmt(args);
+    }
+    if let Some(serial) = serial::serial() {
+        _ = serial.write_fmt(args);
+    }
+}
+
+/// What an entry stub leaves on the stack, lowest address first.
+#[repr(C)]
+struct ExceptionStack {
+    registers: Registers,
+    error_code: u64,
+    frame: InterruptFrame,
+}
+
+/// General-purpose registers at the time of the exception, in reverse order of the pushes.
+#[repr(C)]
+struct Registers {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rbp: u64,
+    rdi: u64,
+    rsi: u64,
+    rdx: u64,
+    rcx: u64,
+    rbx: u64,
+    rax: u64,
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let registers = [
+            ("RAX", self.rax),
+            ("RBX", self.rbx),
+            ("RCX", self.rcx),
+            ("RDX", self.rdx),
+            ("RSI", self.rsi),
+            ("RDI", self.rdi),
+            ("RBP", self.rbp),
+            ("R8", self.r8),
+            ("R9", self.r9),
+            ("R10", self.r10),
+            ("R11", self.r11),
+            ("R12", self.r12),
+            ("R13", self.r13),
+            ("R14", self.r14),
+            ("R15", self.r15),
+        ];
+        for (i, (name, value)) in registers.iter().enumerate() {
+            let separator = if i % 3 == 2 { "\n" } else { "  " };
+            write!(f, "{:<3}: 0x{:016x}{}", name, value, separator)?;
+        }
+        Ok(())
+    }
+}
+
+extern "C" fn fault_entry(stack: &ExceptionStack, vector: usize, has_error_code: bool) -> ! {
+    let error_code = has_error_code.then_some(stack.error_code);
+    fault(vector, error_code, &stack.frame, &stack.registers)
+}
+
+fn fault(
+    vector: usize,
+    error_code: Option<u64>,
+    frame: &InterruptFrame,
+    registers: &Registers,
+) -> ! {
+    report(format_args!(
+        "\nEXCEPTION: {} (vector {})\n",
+        EXCEPTION_NAMES[vector], vector
+    ));
+
+    if let Some(error_code) = error_code {
+        report(format_args!("error code: 0x{:016x}", error_code));
+        if vector == PAGE_FAULT {
+            report(format_args!(" ({})", PageFaultErrorCode(error_code)));
+        } else if SELECTOR_ERROR_CODES.contains(&vector) && error_code != 0 {
+            report(format_args!(" ({})", SelectorErrorCode(error_code)));
+        }
+        report(format_args!("\n"));
+    }
+
+    if vector == PAGE_FAULT {
+        report(format_args!("CR2: 0x{:016x}\n", x86::get_cr2()));
+    }
+
+    report(format_args!("{}\n", frame));
+    report(format_args!("{}", registers));
+
+    // An overflow runs into the guard page, and when the page fault cannot be delivered on the
+    // exhausted stack it escalates to a double fault, which runs on its own IST stack. In both
+    // cases CR2 still holds the faulting address.
+    if vector == PAGE_FAULT || vector == DOUBLE_FAULT {
+        if let Some(owner) = stack::guard_page_owner(x86::get_cr2()) {
+            panic!("kernel stack overflow in {}", owner);
+        }
+    }
+
+    loop {
+        x86::cli();
+        x86::halt();
+    }
+}
+
+struct PageFaultErrorCode(u64);
+
+impl fmt::Display for PageFaultErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} in {} mode",
+            if self.0.get_bit(0) {
+                "protection violation"
+            } else {
+                "not-present page"
+            },
+            if self.0.get_bit(4) {
+                "on instruction fetch"
+            } else if self.0.get_bit(1) {
+                "on write"
+            } else {
+                "on read"
+            },
+            if self.0.get_bit(2) { "user" } else { "kernel" },
+        )?;
+        if self.0.get_bit(3) {
+            write!(f, ", reserved bit set")?;
+        }
+        if self.0.get_bit(5) {
+            write!(f, ", protection key")?;
+        }
+        if self.0.get_bit(6) {
+            write!(f, ", shadow stack")?;
+        }
+        Ok(())
+    }
+}
+
+/// Error code pushed by #TS, #NP, #SS and #GP referring to a segment selector or IDT entry.
+struct SelectorErrorCode(u64);
+
+impl fmt::Display for SelectorErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let table = match self.0.get_bits(1..3) {
+            0 => "GDT",
+            2 => "LDT",
+            _ => "IDT",
+        };
+        write!(f, "{} index {}", table, self.0.get_bits(3..16))?;
+        if self.0.get_bit(0) {
+            write!(f, ", external")?;
+        }
+        Ok(())
+    }
+}
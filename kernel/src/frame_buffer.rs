@@ -1,9 +1,12 @@
-use crate::Result;
+use crate::{Result, paging};
 pub use share::frame_buffer::{FrameBufferConfig, PixelFormat};
 
 pub static mut PIXEL_WRITER: Option<BGRPixelWriter> = None;
 
+/// The loader hands over the physical frame buffer address. It is accessed through the direct map.
 pub fn init(frame_buffer_config: &'static mut FrameBufferConfig) {
+    frame_buffer_config.frame_buffer =
+        paging::phys_to_virt(frame_buffer_config.frame_buffer.addr() as u64) as *mut u8;
     frame_buffer_config.frame_buffer().fill(0);
     let writer = match frame_buffer_config.pixel_format {
         PixelFormat::BGRR => BGRPixelWriter::new(*frame_buffer_config),
@@ -22,6 +25,9 @@ pub fn pixel_writer() -> &'static mut BGRPixelWriter {
 pub trait PixelWriter {
     fn pixel_at(&mut self, x: u32, y: u32) -> Option<&mut [u8]>;
     fn write(&mut self, x: u32, y: u32, rgb: Rgb) -> Result<()>;
+    fn read(&mut self, x: u32, y: u32) -> Result<Rgb>;
+    fn horizontal_resolution(&self) -> u32;
+    fn vertical_resolution(&self) -> u32;
 }
 
 pub struct BGRPixelWriter(FrameBufferConfig);
@@ -30,6 +36,10 @@ impl BGRPixelWriter {
     pub fn new(frame_buffer_config: FrameBufferConfig) -> Self {
         Self(frame_buffer_config)
     }
+
+    pub fn config(&self) -> &FrameBufferConfig {
+        &self.0
+    }
 }
 
 impl PixelWriter for BGRPixelWriter {
@@ -45,6 +55,19 @@ impl PixelWriter for BGRPixelWriter {
         pixel[2] = rgb.r;
         Ok(())
     }
+
+    fn read(&mut self, x: u32, y: u32) -> Result<Rgb> {
+        let pixel = self.pixel_at(x, y).ok_or("out of buffer")?;
+        Ok(Rgb::new(pixel[2], pixel[1], pixel[0]))
+    }
+
+    fn horizontal_resolution(&self) -> u32 {
+        self.0.horizontal_resolution
+    }
+
+    fn vertical_resolution(&self) -> u32 {
+        self.0.vertical_resolution
+    }
 }
 
 #[derive(Debug, Default, Clone, Copy)]
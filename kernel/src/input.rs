@@ -0,0 +1,129 @@
+use crate::keyboard;
+use alloc::{boxed::Box, vec::Vec};
+
+/// Everything an input device can report, whichever bus it sits on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    RelativeMotion {
+        dx: i32,
+        dy: i32,
+    },
+    /// Position on a device such as a tablet, scaled by the device's logical range.
+    AbsoluteMotion {
+        x: u32,
+        y: u32,
+        max_x: u32,
+        max_y: u32,
+    },
+    ButtonDown(MouseButton),
+    ButtonUp(MouseButton),
+    /// Positive values scroll away from the user.
+    Wheel(i32),
+    Key(KeyEvent),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    Other(u8),
+}
+
+impl MouseButton {
+    /// Bit `n` of a HID or PS/2 button byte.
+    fn from_bit(n: u8) -> Self {
+        match n {
+            0 => Self::Left,
+            1 => Self::Right,
+            2 => Self::Middle,
+            n => Self::Other(n),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub modifier: u8,
+    /// HID usage ID.
+    pub keycode: u8,
+    /// Character under the current keyboard layout, or 0.
+    pub ascii: u8,
+    pub press: bool,
+}
+
+impl KeyEvent {
+    pub fn new(modifier: u8, keycode: u8, press: bool) -> Self {
+        Self {
+            modifier,
+            keycode,
+            ascii: keyboard::keycode_to_ascii(modifier, keycode),
+            press,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubscriptionId(usize);
+
+type Subscriber = Box<dyn FnMut(&InputEvent)>;
+
+static mut SUBSCRIBERS: Vec<(SubscriptionId, Subscriber)> = Vec::new();
+static mut NEXT_ID: usize = 0;
+static mut SINK: Option<fn(InputEvent)> = None;
+
+fn subscribers() -> &'static mut Vec<(SubscriptionId, Subscriber)> {
+    #[allow(static_mut_refs)]
+    unsafe {
+        &mut SUBSCRIBERS
+    }
+}
+
+/// Sets where [`report`] sends events, typically a queue that is drained by calling
+/// [`dispatch`] outside of interrupt context.
+pub fn set_sink(sink: fn(InputEvent)) {
+    unsafe { SINK = Some(sink) };
+}
+
+/// Called by drivers for every event they decode.
+pub fn report(event: InputEvent) {
+    match unsafe { SINK } {
+        Some(sink) => sink(event),
+        None => dispatch(&event),
+    }
+}
+
+/// Reports `ButtonDown` and `ButtonUp` for each bit that differs between two button bytes.
+pub fn report_buttons(previous: u8, current: u8) {
+    (0..8)
+        .filter(|n| (previous ^ current) & (1 << n) != 0)
+        .for_each(|n| {
+            let button = MouseButton::from_bit(n);
+            report(if current & (1 << n) != 0 {
+                InputEvent::ButtonDown(button)
+            } else {
+                InputEvent::ButtonUp(button)
+            });
+        });
+}
+
+/// Hands `event` to every subscriber, in the order they subscribed.
+pub fn dispatch(event: &InputEvent) {
+    subscribers()
+        .iter_mut()
+        .for_each(|(_, subscriber)| subscriber(event));
+}
+
+pub fn subscribe(subscriber: impl FnMut(&InputEvent) + 'static) -> SubscriptionId {
+    let id = unsafe {
+        NEXT_ID += 1;
+        SubscriptionId(NEXT_ID)
+    };
+    subscribers().push((id, Box::new(subscriber)));
+    id
+}
+
+#[allow(dead_code)]
+pub fn unsubscribe(id: SubscriptionId) {
+    subscribers().retain(|(subscription, _)| *subscription != id);
+}
@@ -1,5 +1,7 @@
-use crate::DescriptorType;
+use crate::{DescriptorType, Result, local_apic, x86};
+use alloc::boxed::Box;
 use bit_field::BitField;
+use core::fmt;
 
 pub static mut IDT: [InterruptDescriptor; 256] = unsafe { core::mem::zeroed() };
 
@@ -14,16 +16,14 @@ pub struct InterruptDescriptor {
     _reserved: u32,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct InterruptVector(usize);
 
 impl InterruptVector {
-    const XHCI: usize = 0x40;
-
-    #[allow(non_snake_case)]
-    pub fn Xhci() -> Self {
-        Self(Self::XHCI)
-    }
+    /// The 8259 PICs are remapped below this before being masked; keep these free so a
+    /// spurious PIC interrupt never reaches a driver.
+    const LEGACY_PIC_END: usize = 0x30;
+    const SPURIOUS: usize = 0xff;
 }
 
 impl From<usize> for InterruptVector {
@@ -52,9 +52,16 @@ pub struct InterruptFrame {
     ss: u64,
 }
 
+impl fmt::Display for InterruptFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "RIP:    0x{:016x}  CS: 0x{:04x}", self.rip, self.cs)?;
+        writeln!(f, "RSP:    0x{:016x}  SS: 0x{:04x}", self.rsp, self.ss)?;
+        write!(f, "RFLAGS: 0x{:016x}", self.rflags)
+    }
+}
+
 pub fn notify_end_of_interrupt() {
-    let p = 0xfee000b0 as *mut u32;
-    unsafe { p.write_volatile(0) };
+    local_apic::end_of_interrupt();
 }
 
 pub fn set_idt_entry(
@@ -84,3 +91,111 @@ pub fn make_idt_attr(
         .set_bits(0..3, interrupt_stack_table);
     InterruptDescriptorAttribute(field)
 }
+
+type Handler = Box<dyn FnMut()>;
+
+static mut HANDLERS: [Option<Handler>; 256] = [const { None }; 256];
+static mut ALLOCATED: [bool; 256] = [false; 256];
+
+extern "x86-interrupt" fn stub<const VECTOR: usize>(_: InterruptFrame) {
+    dispatch(VECTOR);
+}
+
+macro_rules! stubs {
+    ($($vector:literal),* $(,)?) => {
+        [$(($vector, stub::<$vector> as Stub)),*]
+    };
+}
+
+type Stub = extern "x86-interrupt" fn(InterruptFrame);
+
+#[rustfmt::skip]
+const STUBS: [(usize, Stub); 224] = stubs!(
+    32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47,
+    48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63,
+    64, 65, 66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79,
+    80, 81, 82, 83, 84, 85, 86, 87, 88, 89, 90, 91, 92, 93, 94, 95,
+    96, 97, 98, 99, 100, 101, 102, 103, 104, 105, 106, 107, 108, 109, 110, 111,
+    112, 113, 114, 115, 116, 117, 118, 119, 120, 121, 122, 123, 124, 125, 126, 127,
+    128, 129, 130, 131, 132, 133, 134, 135, 136, 137, 138, 139, 140, 141, 142, 143,
+    144, 145, 146, 147, 148, 149, 150, 151, 152, 153, 154, 155, 156, 157, 158, 159,
+    160, 161, 162, 163, 164, 165, 166, 167, 168, 169, 170, 171, 172, 173, 174, 175,
+    176, 177, 178, 179, 180, 181, 182, 183, 184, 185, 186, 187, 188, 189, 190, 191,
+    192, 193, 194, 195, 196, 197, 198, 199, 200, 201, 202, 203, 204, 205, 206, 207,
+    208, 209, 210, 211, 212, 213, 214, 215, 216, 217, 218, 219, 220, 221, 222, 223,
+    224, 225, 226, 227, 228, 229, 230, 231, 232, 233, 234, 235, 236, 237, 238, 239,
+    240, 241, 242, 243, 244, 245, 246, 247, 248, 249, 250, 251, 252, 253, 254, 255,
+);
+
+/// Points vectors 32 to 255 at stubs that forward to [`dispatch`].
+pub fn init() {
+    let cs = x86::get_cs();
+    let attr = make_idt_attr(DescriptorType::InterruptGate(), 0, true, 0);
+    STUBS.iter().for_each(|(vector, stub)| {
+        set_idt_entry(
+            unsafe { &mut IDT[*vector] },
+            attr,
+            *stub as usize as u64,
+            cs,
+        );
+    });
+}
+
+/// Runs the handler registered for `vector` and signals end of interrupt, so that handlers
+/// do not have to.
+fn dispatch(vector: usize) {
+    #[allow(static_mut_refs)]
+    if let Some(handler) = unsafe { HANDLERS[vector].as_mut() } {
+        handler();
+    }
+    // The local APIC does not expect an EOI for its spurious vector.
+    if vector != InterruptVector::SPURIOUS {
+        notify_end_of_interrupt();
+    }
+}
+
+/// Hands out a vector that neither the CPU nor another driver uses.
+pub fn allocate_vector() -> Result<InterruptVector> {
+    x86::without_interrupts(|| {
+        #[allow(static_mut_refs)]
+        let allocated = unsafe { &mut ALLOCATED };
+        let vector = (InterruptVector::LEGACY_PIC_END..InterruptVector::SPURIOUS)
+            .find(|vector| !allocated[*vector])
+            .ok_or("no free interrupt vector.")?;
+        allocated[vector] = true;
+        Ok(InterruptVector(vector))
+    })
+}
+
+/// Installs `handler` for `vector` and marks the vector allocated, so that [`allocate_vector`]
+/// never hands it out as well. It runs with interrupts disabled.
+pub fn register_handler(vector: InterruptVector, handler: impl FnMut() + 'static) -> Result<()> {
+    if vector.get() < 32 {
+        return Err("vector is reserved for exceptions.");
+    }
+    x86::without_interrupts(|| {
+        #[allow(static_mut_refs)]
+        let slot = unsafe { &mut HANDLERS[vector.get()] };
+        if slot.is_some() {
+            return Err("interrupt handler is already registered.");
+        }
+        *slot = Some(Box::new(handler));
+        unsafe { ALLOCATED[vector.get()] = true };
+        Ok(())
+    })
+}
+
+/// Allocates a vector and installs `handler` for it.
+pub fn request(handler: impl FnMut() + 'static) -> Result<InterruptVector> {
+    let vector = allocate_vector()?;
+    register_handler(vector, handler)?;
+    Ok(vector)
+}
+
+/// Removes the handler for `vector` and frees the vector.
+pub fn unregister_handler(vector: InterruptVector) {
+    x86::without_interrupts(|| unsafe {
+        HANDLERS[vector.get()] = None;
+        ALLOCATED[vector.get()] = false;
+    });
+}
@@ -0,0 +1,178 @@
+use crate::{Result, acpi, paging, x86};
+use alloc::vec::Vec;
+use bit_field::BitField;
+
+const IOREGSEL: u64 = 0x00;
+const IOWIN: u64 = 0x10;
+
+const IOAPICVER: u32 = 0x01;
+const IOREDTBL: u32 = 0x10;
+
+const REDIRECTION_POLARITY: usize = 13;
+const REDIRECTION_TRIGGER_MODE: usize = 15;
+const REDIRECTION_MASK: usize = 16;
+
+const PIC_MASTER_COMMAND: u16 = 0x20;
+const PIC_MASTER_DATA: u16 = 0x21;
+const PIC_SLAVE_COMMAND: u16 = 0xa0;
+const PIC_SLAVE_DATA: u16 = 0xa1;
+
+static mut IO_APICS: Vec<IoApic> = Vec::new();
+
+fn io_apics() -> &'static mut Vec<IoApic> {
+    #[allow(static_mut_refs)]
+    unsafe {
+        &mut IO_APICS
+    }
+}
+
+/// [82093AA I/O Advanced Programmable Interrupt Controller (IOAPIC)](https://pdos.csail.mit.edu/6.828/2018/readings/ia32/ioapic.pdf)
+struct IoApic {
+    base: u64,
+    gsi_base: u32,
+    entries: u32,
+}
+
+impl IoApic {
+    fn read(&self, index: u32) -> u32 {
+        unsafe {
+            ((self.base + IOREGSEL) as *mut u32).write_volatile(index);
+            ((self.base + IOWIN) as *const u32).read_volatile()
+        }
+    }
+
+    fn write(&self, index: u32, value: u32) {
+        unsafe {
+            ((self.base + IOREGSEL) as *mut u32).write_volatile(index);
+            ((self.base + IOWIN) as *mut u32).write_volatile(value);
+        }
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.entries).contains(&gsi)
+    }
+
+    fn read_redirection(&self, gsi: u32) -> (u32, u32) {
+        let index = IOREDTBL + (gsi - self.gsi_base) * 2;
+        (self.read(index), self.read(index + 1))
+    }
+
+    fn write_redirection(&self, gsi: u32, low: u32, high: u32) {
+        let index = IOREDTBL + (gsi - self.gsi_base) * 2;
+        // Keep the entry masked while the two halves disagree.
+        self.write(index, low | 1 << REDIRECTION_MASK);
+        self.write(index + 1, high);
+        self.write(index, low);
+    }
+}
+
+/// Masks every line of the legacy 8259 PICs after moving them off the exception vectors,
+/// so that a spurious interrupt cannot be mistaken for a CPU exception.
+fn disable_pic() {
+    // ICW1: edge triggered, cascade, ICW4 needed.
+    x86::io_out8(PIC_MASTER_COMMAND, 0x11);
+    x86::io_out8(PIC_SLAVE_COMMAND, 0x11);
+    // ICW2: vector offsets.
+    x86::io_out8(PIC_MASTER_DATA, 0x20);
+    x86::io_out8(PIC_SLAVE_DATA, 0x28);
+    // ICW3: slave on IRQ2.
+    x86::io_out8(PIC_MASTER_DATA, 1 << 2);
+    x86::io_out8(PIC_SLAVE_DATA, 2);
+    // ICW4: 8086 mode.
+    x86::io_out8(PIC_MASTER_DATA, 0x01);
+    x86::io_out8(PIC_SLAVE_DATA, 0x01);
+
+    x86::io_out8(PIC_MASTER_DATA, 0xff);
+    x86::io_out8(PIC_SLAVE_DATA, 0xff);
+}
+
+/// Masks the 8259 PICs and every I/O APIC listed in the MADT. Lines stay masked until
+/// they are routed with [`route`] or [`route_gsi`].
+pub fn init() -> Result<()> {
+    let madt = acpi::madt();
+    if madt.has_8259() {
+        disable_pic();
+    }
+
+    for entry in madt.io_apics() {
+        let base = paging::map_mmio(entry.address as u64, 0x20)?;
+        let mut io_apic = IoApic {
+            base,
+            gsi_base: entry.gsi_base,
+            entries: 0,
+        };
+        io_apic.entries = io_apic.read(IOAPICVER).get_bits(16..24) + 1;
+
+        for gsi in io_apic.gsi_base..io_apic.gsi_base + io_apic.entries {
+            io_apic.write_redirection(gsi, 1 << REDIRECTION_MASK, 0);
+        }
+        io_apics().push(io_apic);
+    }
+
+    if io_apics().is_empty() {
+        return Err("no I/O APIC.");
+    }
+    Ok(())
+}
+
+fn find(gsi: u32) -> Result<&'static IoApic> {
+    io_apics()
+        .iter()
+        .find(|io_apic| io_apic.handles(gsi))
+        .ok_or("no I/O APIC handles the GSI.")
+}
+
+/// Routes ISA `irq` to `vector` on the local APIC `apic_id`, applying the MADT's
+/// interrupt source overrides. ISA interrupts default to active high, edge triggered.
+pub fn route(irq: u8, vector: u8, apic_id: u8) -> Result<()> {
+    let iso = acpi::madt()
+        .interrupt_source_overrides()
+        .find(|iso| iso.bus == 0 && iso.source == irq);
+
+    let (gsi, active_low, level_triggered) = match iso {
+        Some(iso) => (
+            iso.gsi,
+            iso.active_low().unwrap_or(false),
+            iso.level_triggered().unwrap_or(false),
+        ),
+        None => (irq as u32, false, false),
+    };
+    route_gsi(gsi, vector, apic_id, active_low, level_triggered)
+}
+
+/// Routes a global system interrupt with fixed delivery in physical destination mode.
+pub fn route_gsi(
+    gsi: u32,
+    vector: u8,
+    apic_id: u8,
+    active_low: bool,
+    level_triggered: bool,
+) -> Result<()> {
+    let io_apic = find(gsi)?;
+
+    let mut low = vector as u32;
+    low.set_bit(REDIRECTION_POLARITY, active_low)
+        .set_bit(REDIRECTION_TRIGGER_MODE, level_triggered)
+        .set_bit(REDIRECTION_MASK, false);
+    let high = (apic_id as u32) << 24;
+    io_apic.write_redirection(gsi, low, high);
+    Ok(())
+}
+
+#[allow(dead_code)]
+pub fn mask(gsi: u32) -> Result<()> {
+    set_mask(gsi, true)
+}
+
+#[allow(dead_code)]
+pub fn unmask(gsi: u32) -> Result<()> {
+    set_mask(gsi, false)
+}
+
+fn set_mask(gsi: u32, masked: bool) -> Result<()> {
+    let io_apic = find(gsi)?;
+    let (mut low, high) = io_apic.read_redirection(gsi);
+    low.set_bit(REDIRECTION_MASK, masked);
+    io_apic.write_redirection(gsi, low, high);
+    Ok(())
+}
@@ -0,0 +1,154 @@
+// Keys are identified by their USB HID usage ID in every keyboard driver, so drivers for
+// other buses translate into that space first.
+
+/// Modifier byte bits, laid out as in a HID boot keyboard report.
+pub struct Modifier;
+
+#[allow(dead_code)]
+impl Modifier {
+    pub const L_CONTROL: u8 = 1 << 0;
+    pub const L_SHIFT: u8 = 1 << 1;
+    pub const L_ALT: u8 = 1 << 2;
+    pub const L_GUI: u8 = 1 << 3;
+    pub const R_CONTROL: u8 = 1 << 4;
+    pub const R_SHIFT: u8 = 1 << 5;
+    pub const R_ALT: u8 = 1 << 6;
+    pub const R_GUI: u8 = 1 << 7;
+}
+
+/// [HID Usage Tables 1.5, 10 Keyboard/Keypad Page (0x07)](https://usb.org/sites/default/files/hut1_5.pdf)
+///
+/// Usage IDs of the eight modifier keys, in the bit order of the modifier byte above.
+const MODIFIER_KEYCODES: core::ops::RangeInclusive<u8> = 0xe0..=0xe7;
+
+/// The modifier bit for `keycode`, if it is a modifier key.
+pub fn modifier_bit(keycode: u8) -> Option<u8> {
+    MODIFIER_KEYCODES
+        .contains(&keycode)
+        .then(|| 1 << (keycode - MODIFIER_KEYCODES.start()))
+}
+
+/// Keyboard layouts [`keycode_to_ascii`] can translate with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Us,
+    Jp106,
+}
+
+static mut LAYOUT: Layout = Layout::Us;
+
+pub fn set_layout(layout: Layout) {
+    unsafe { LAYOUT = layout };
+}
+
+pub fn layout() -> Layout {
+    unsafe { LAYOUT }
+}
+
+/// Usage ID of the L key, which switches layouts together with Ctrl and Alt.
+const LAYOUT_SWITCH_KEYCODE: u8 = 0x0f;
+
+/// Switches to the other layout if `keycode` pressed with `modifier` is Ctrl+Alt+L and returns
+/// whether it did, in which case the key should not be typed.
+pub fn switch_layout_on_hotkey(modifier: u8, keycode: u8) -> bool {
+    let control = modifier & (Modifier::L_CONTROL | Modifier::R_CONTROL) != 0;
+    let alt = modifier & (Modifier::L_ALT | Modifier::R_ALT) != 0;
+    if !control || !alt || keycode != LAYOUT_SWITCH_KEYCODE {
+        return false;
+    }
+    set_layout(match layout() {
+        Layout::Us => Layout::Jp106,
+        Layout::Jp106 => Layout::Us,
+    });
+    true
+}
+
+#[rustfmt::skip]
+const US_KEYMAP: [u8; 0x68] = [
+    0, 0, 0, 0, b'a', b'b', b'c', b'd', // 0x00
+    b'e', b'f', b'g', b'h', b'i', b'j', b'k', b'l', // 0x08
+    b'm', b'n', b'o', b'p', b'q', b'r', b's', b't', // 0x10
+    b'u', b'v', b'w', b'x', b'y', b'z', b'1', b'2', // 0x18
+    b'3', b'4', b'5', b'6', b'7', b'8', b'9', b'0', // 0x20
+    b'\n', 0x1b, 0x08, b'\t', b' ', b'-', b'=', b'[', // 0x28
+    b']', b'\\', b'#', b';', b'\'', b'`', b',', b'.', // 0x30
+    b'/', 0, 0, 0, 0, 0, 0, 0, // 0x38
+    0, 0, 0, 0, 0, 0, 0, 0, // 0x40
+    0, 0, 0, 0, 0, 0, 0, 0, // 0x48
+    0, 0, 0, 0, b'/', b'*', b'-', b'+', // 0x50
+    b'\n', b'1', b'2', b'3', b'4', b'5', b'6', b'7', // 0x58
+    b'8', b'9', b'0', b'.', b'\\', 0, 0, b'=', // 0x60
+];
+
+#[rustfmt::skip]
+const US_KEYMAP_SHIFTED: [u8; 0x68] = [
+    0, 0, 0, 0, b'A', b'B', b'C', b'D', // 0x00
+    b'E', b'F', b'G', b'H', b'I', b'J', b'K', b'L', // 0x08
+    b'M', b'N', b'O', b'P', b'Q', b'R', b'S', b'T', // 0x10
+    b'U', b'V', b'W', b'X', b'Y', b'Z', b'!', b'@', // 0x18
+    b'#', b'$', b'%', b'^', b'&', b'*', b'(', b')', // 0x20
+    b'\n', 0x1b, 0x08, b'\t', b' ', b'_', b'+', b'{', // 0x28
+    b'}', b'|', b'~', b':', b'"', b'~', b'<', b'>', // 0x30
+    b'?', 0, 0, 0, 0, 0, 0, 0, // 0x38
+    0, 0, 0, 0, 0, 0, 0, 0, // 0x40
+    0, 0, 0, 0, 0, 0, 0, 0, // 0x48
+    0, 0, 0, 0, b'/', b'*', b'-', b'+', // 0x50
+    b'\n', b'1', b'2', b'3', b'4', b'5', b'6', b'7', // 0x58
+    b'8', b'9', b'0', b'.', b'|', 0, 0, b'=', // 0x60
+];
+
+#[rustfmt::skip]
+const JP106_KEYMAP: [u8; 0x90] = [
+    0, 0, 0, 0, b'a', b'b', b'c', b'd', // 0x00
+    b'e', b'f', b'g', b'h', b'i', b'j', b'k', b'l', // 0x08
+    b'm', b'n', b'o', b'p', b'q', b'r', b's', b't', // 0x10
+    b'u', b'v', b'w', b'x', b'y', b'z', b'1', b'2', // 0x18
+    b'3', b'4', b'5', b'6', b'7', b'8', b'9', b'0', // 0x20
+    b'\n', 0x1b, 0x08, b'\t', b' ', b'-', b'^', b'@', // 0x28
+    b'[', 0, b']', b';', b':', 0, b',', b'.', // 0x30
+    b'/', 0, 0, 0, 0, 0, 0, 0, // 0x38
+    0, 0, 0, 0, 0, 0, 0, 0, // 0x40
+    0, 0, 0, 0, 0, 0, 0, 0, // 0x48
+    0, 0, 0, 0, b'/', b'*', b'-', b'+', // 0x50
+    b'\n', b'1', b'2', b'3', b'4', b'5', b'6', b'7', // 0x58
+    b'8', b'9', b'0', b'.', 0, 0, 0, b'=', // 0x60
+    0, 0, 0, 0, 0, 0, 0, 0, // 0x68
+    0, 0, 0, 0, 0, 0, 0, 0, // 0x70
+    0, 0, 0, 0, 0, 0, 0, 0, // 0x78
+    0, 0, 0, 0, 0, 0, 0, b'\\', // 0x80
+    0, b'\\', 0, 0, 0, 0, 0, 0, // 0x88
+];
+
+#[rustfmt::skip]
+const JP106_KEYMAP_SHIFTED: [u8; 0x90] = [
+    0, 0, 0, 0, b'A', b'B', b'C', b'D', // 0x00
+    b'E', b'F', b'G', b'H', b'I', b'J', b'K', b'L', // 0x08
+    b'M', b'N', b'O', b'P', b'Q', b'R', b'S', b'T', // 0x10
+    b'U', b'V', b'W', b'X', b'Y', b'Z', b'!', b'"', // 0x18
+    b'#', b'$', b'%', b'&', b'\'', b'(', b')', 0, // 0x20
+    b'\n', 0x1b, 0x08, b'\t', b' ', b'=', b'~', b'`', // 0x28
+    b'{', 0, b'}', b'+', b'*', 0, b'<', b'>', // 0x30
+    b'?', 0, 0, 0, 0, 0, 0, 0, // 0x38
+    0, 0, 0, 0, 0, 0, 0, 0, // 0x40
+    0, 0, 0, 0, 0, 0, 0, 0, // 0x48
+    0, 0, 0, 0, b'/', b'*', b'-', b'+', // 0x50
+    b'\n', b'1', b'2', b'3', b'4', b'5', b'6', b'7', // 0x58
+    b'8', b'9', b'0', b'.', 0, 0, 0, b'=', // 0x60
+    0, 0, 0, 0, 0, 0, 0, 0, // 0x68
+    0, 0, 0, 0, 0, 0, 0, 0, // 0x70
+    0, 0, 0, 0, 0, 0, 0, 0, // 0x78
+    0, 0, 0, 0, 0, 0, 0, b'_', // 0x80
+    0, b'|', 0, 0, 0, 0, 0, 0, // 0x88
+];
+
+/// Translates `keycode` with the current layout. Returns 0 for keys without a character.
+pub fn keycode_to_ascii(modifier: u8, keycode: u8) -> u8 {
+    let shifted = modifier & (Modifier::L_SHIFT | Modifier::R_SHIFT) != 0;
+    let keymap: &[u8] = match (unsafe { LAYOUT }, shifted) {
+        (Layout::Us, false) => &US_KEYMAP,
+        (Layout::Us, true) => &US_KEYMAP_SHIFTED,
+        (Layout::Jp106, false) => &JP106_KEYMAP,
+        (Layout::Jp106, true) => &JP106_KEYMAP_SHIFTED,
+    };
+    keymap.get(keycode as usize).copied().unwrap_or(0)
+}
@@ -0,0 +1,41 @@
+use crate::{
+    Result,
+    paging::{self, phys_to_virt},
+};
+
+/// Architectural default. The MADT may relocate it; see [`init`].
+pub const LOCAL_APIC_BASE: u64 = 0xfee0_0000;
+
+static mut BASE: u64 = phys_to_virt(LOCAL_APIC_BASE);
+
+pub const ID: u64 = 0x020;
+pub const EOI: u64 = 0x0b0;
+pub const LVT_TIMER: u64 = 0x320;
+pub const INITIAL_COUNT: u64 = 0x380;
+pub const CURRENT_COUNT: u64 = 0x390;
+pub const DIVIDE_CONFIG: u64 = 0x3e0;
+
+/// Switches register access to the local APIC at physical address `phys`.
+pub fn init(phys: u64) -> Result<()> {
+    let base = paging::map_mmio(phys, 0x1000)?;
+    unsafe { BASE = base };
+    Ok(())
+}
+
+pub fn read(offset: u64) -> u32 {
+    let p = (unsafe { BASE } + offset) as *const u32;
+    unsafe { p.read_volatile() }
+}
+
+pub fn write(offset: u64, value: u32) {
+    let p = (unsafe { BASE } + offset) as *mut u32;
+    unsafe { p.write_volatile(value) };
+}
+
+pub fn id() -> u8 {
+    (read(ID) >> 24) as u8
+}
+
+pub fn end_of_interrupt() {
+    write(EOI, 0);
+}
@@ -1,40 +1,50 @@
 #![feature(str_from_raw_parts)]
 #![feature(abi_x86_interrupt)]
 #![feature(const_trait_impl)]
+#![feature(alloc_error_handler)]
+#![feature(naked_functions)]
 #![no_std]
 #![no_main]
 
-use core::fmt::Write;
+extern crate alloc;
+
+use core::{alloc::Layout, fmt::Write};
 
 #[macro_use]
 mod macros;
 
 #[rustfmt::skip]
-r#mod!(fonts, console, frame_buffer, graphics, mouse, pci, usb, interrupt, queue, segment, x86, x86_descriptor, paging, memory_manager);
+r#mod!(fonts, console, frame_buffer, graphics, mouse, pci, usb, interrupt, queue, segment, x86, x86_descriptor, paging, memory_manager, allocator, slab, exception, serial, stack, local_apic, timer, acpi, io_apic, keyboard, ps2, input, block);
 
 use console::console;
 use frame_buffer::{FrameBufferConfig, Rgb, pixel_writer};
 use graphics::{Vector2D, draw_rectangle};
-use interrupt::{
-    IDT, InterruptDescriptor, InterruptFrame, InterruptVector, make_idt_attr,
-    notify_end_of_interrupt, set_idt_entry,
-};
+use input::{InputEvent, KeyEvent};
+use interrupt::{IDT, InterruptDescriptor};
 use mouse::mouse_cursor;
-use paging::setup_identity_page_table;
-use pci::{DEVICES, read_bar, scan_all_bus};
-use queue::ArrayQueue;
+use pci::{read_bar, scan_all_bus};
+use queue::SlabQueue;
 use segment::setup_segments;
 use share::memory_map::{self, MemoryMap};
-use usb::xhc;
 use x86_descriptor::DescriptorType;
 
 type Result<T> = core::result::Result<T, &'static str>;
 
-#[repr(align(16))]
-struct KernelMainStack([u8; 1024 * 1024]);
+/// Rate of timer interrupts. `MessageType::TimerTick` is only sent for the ticks that
+/// [`timer::wake_at`] asks for.
+const TIMER_FREQUENCY: u64 = 100;
+
+const KEYBOARD_LAYOUT: keyboard::Layout = keyboard::Layout::Us;
+
+const KERNEL_MAIN_STACK_SIZE: usize = 1024 * 1024;
+
+/// The lowest page is unmapped once paging is set up and acts as the stack's guard page.
+#[repr(align(4096))]
+struct KernelMainStack([u8; stack::GUARD_PAGE_SIZE + KERNEL_MAIN_STACK_SIZE]);
 
 #[unsafe(no_mangle)]
-static mut KERNEL_MAIN_STACK: KernelMainStack = KernelMainStack([0; 1024 * 1024]);
+static mut KERNEL_MAIN_STACK: KernelMainStack =
+    KernelMainStack([0; stack::GUARD_PAGE_SIZE + KERNEL_MAIN_STACK_SIZE]);
 
 fn kernel_main_stack_() -> &'static [u8] {
     #[allow(static_mut_refs)]
@@ -43,8 +53,8 @@ fn kernel_main_stack_() -> &'static [u8] {
     }
 }
 
-static mut MAIN_QUEUE: ArrayQueue<Message, 32> = ArrayQueue::new();
-fn main_queue() -> &'static mut ArrayQueue<Message, 32> {
+static mut MAIN_QUEUE: SlabQueue<Message> = SlabQueue::new("message");
+fn main_queue() -> &'static mut SlabQueue<Message> {
     #[allow(static_mut_refs)]
     unsafe {
         &mut MAIN_QUEUE
@@ -57,20 +67,27 @@ struct Message(MessageType);
 #[derive(Debug, Clone, Copy)]
 enum MessageType {
     InterruptXhci,
+    TimerTick,
+    Ps2Keyboard(u8),
+    Ps2Mouse(u8),
+    Input(input::InputEvent),
 }
 
 #[unsafe(no_mangle)]
 extern "C" fn kernel_main(
     frame_buffer_config: &'static mut FrameBufferConfig,
     memory_map_: &'static MemoryMap,
+    acpi_rsdp: u64,
 ) -> ! {
     frame_buffer::init(frame_buffer_config);
     console::init(Rgb::white(), Rgb::black());
-    mouse::init(Rgb::black(), 200, 100).unwrap();
+    serial::init();
+    mouse::init(200, 100).unwrap();
     memory_map::init(memory_map_);
+    acpi::save_rsdp(acpi_rsdp);
 
     x86::switch_rsp(
-        kernel_main_stack_().as_ptr().addr() + 1024 * 1024,
+        kernel_main_stack_().as_ptr_range().end.addr(),
         kernel_main_new_stack,
     );
 
@@ -95,7 +112,25 @@ fn main() -> Result<()> {
     x86::set_ds_all(0);
     x86::set_cs_ss(KERNEL_CS, KERNEL_SS);
 
-    setup_identity_page_table();
+    exception::init();
+    interrupt::init();
+    #[allow(static_mut_refs)]
+    let param = x86::IdtParam {
+        limit: size_of::<[InterruptDescriptor; 256]>() as u16 - 1,
+        base: unsafe { &IDT[0] as *const InterruptDescriptor as usize },
+    };
+    x86::load_idt(&param);
+
+    memory_manager::init(memory_map::memory_map());
+    slab::init();
+    paging::init(memory_map::memory_map())?;
+    memory_manager::reclaim_boot_services_memory(memory_map::memory_map())?;
+    stack::protect_guard_page("kernel_main", kernel_main_stack_().as_ptr().addr() as u64)?;
+    segment::setup_guarded_interrupt_stacks()?;
+
+    acpi::init()?;
+    local_apic::init(acpi::madt().local_apic_address())?;
+    io_apic::init()?;
 
     draw_rectangle(
         &Vector2D::new(100, 100),
@@ -103,106 +138,224 @@ fn main() -> Result<()> {
         Rgb::red(),
     )?;
 
+    if let Err(e) = pci::init() {
+        println!("pci: {}", e);
+    }
     scan_all_bus()?;
 
-    let xhc_dev = unsafe { DEVICES }
-        .iter()
-        .filter_map(|dev| {
-            let dev = (*dev)?;
-            // println!("0x{:04x}", read_vendor_id_from_device(&dev));
-            if dev.class_code.eq(&(0x0c, 0x03, 0x30))
-            //&& read_vendor_id_from_device(&dev).eq(&0x8086)
-            {
-                return Some(dev);
-            }
-            None
-        })
-        .next()
-        .ok_or("no xhci device")?;
-
-    dbg!(&xhc_dev);
-
-    let cs = x86::get_cs();
-    let attr = make_idt_attr(DescriptorType::InterruptGate(), 0, true, 0);
-    set_idt_entry(
-        unsafe { &mut IDT[InterruptVector::Xhci().get()] },
-        attr,
-        interrupt_handler_xhci as usize as u64,
-        cs,
-    );
+    println!("pci: {} function(s)", pci::num_devices());
 
-    #[allow(static_mut_refs)]
-    let param = x86::IdtParam {
-        limit: size_of::<[InterruptDescriptor; 256]>() as u16 - 1,
-        base: unsafe { &IDT[0] as *const InterruptDescriptor as usize },
-    };
-    x86::load_idt(&param);
+    let timer_vector = interrupt::request(|| {
+        if timer::on_interrupt() {
+            _ = main_queue().push(Message(MessageType::TimerTick));
+        }
+    })?;
+    timer::init(TIMER_FREQUENCY, timer_vector.get() as u8)?;
 
-    let bsp_local_apic_id = (unsafe { *(0xfee00020 as *const u32) } >> 24) as u8;
+    let bsp_local_apic_id = local_apic::id();
     dbg!(bsp_local_apic_id);
 
-    pci::configure_msi_fixed_destination(
-        &xhc_dev,
+    pci::register_driver(&XHC_DRIVER);
+    pci::bind_drivers();
+    if !pci::is_bound(&XHC_DRIVER) {
+        println!("no xhci device");
+    }
+
+    keyboard::set_layout(KEYBOARD_LAYOUT);
+    input::set_sink(input_sink);
+    input::subscribe(|event| mouse_cursor().on_input(event));
+    input::subscribe(|event| {
+        if let InputEvent::Key(KeyEvent {
+            modifier,
+            keycode,
+            ascii,
+            press: true,
+        }) = *event
+        {
+            if keyboard::switch_layout_on_hotkey(modifier, keycode) {
+                println!("keyboard: {:?} layout", keyboard::layout());
+            } else if ascii != 0 {
+                print!("{}", ascii as char);
+            }
+        }
+    });
+    let keyboard_vector = interrupt::request(|| {
+        _ = main_queue().push(Message(MessageType::Ps2Keyboard(ps2::read_data())));
+    })?;
+    let mouse_vector = interrupt::request(|| {
+        _ = main_queue().push(Message(MessageType::Ps2Mouse(ps2::read_data())));
+    })?;
+    io_apic::route(
+        ps2::KEYBOARD_IRQ,
+        keyboard_vector.get() as u8,
         bsp_local_apic_id,
-        pci::MSITriggerMode::Level,
-        pci::MSIDeliveryMode::Fixed(),
-        InterruptVector::Xhci().get() as u8,
-        0,
     )?;
+    io_apic::route(ps2::MOUSE_IRQ, mouse_vector.get() as u8, bsp_local_apic_id)?;
+    match ps2::init() {
+        Ok(()) => {
+            println!(
+                "ps2: keyboard{}",
+                if ps2::has_mouse() { ", mouse" } else { "" }
+            );
+        }
+        Err(e) => println!("ps2: {}", e),
+    }
 
-    let xhc_bar = read_bar(&xhc_dev, 0)?;
-    let xhc_mmio_base = xhc_bar & !0xf;
-
-    println!(
-        "xhc_bar: 0x{:08x}, xhc_mmio_base: 0x{:08x}",
-        xhc_bar, xhc_mmio_base
-    );
+    x86::sti();
 
-    usb::init(xhc_mmio_base as usize);
-    let xhc = xhc();
-    xhc.initialize()?;
-    xhc.run()?;
+    if usb::is_initialized() {
+        usb::with_xhc(|xhc| {
+            xhc.configure_port();
+            Ok(())
+        })?;
+    }
 
-    x86::sti();
+    for id in block::ids() {
+        if let Err(e) = block::with_device(id, print_boot_signature) {
+            println!("block: {}", e);
+        }
+    }
 
-    usb::register_mouse_observer(mouse_observer);
-    xhc.configure_port();
+    for cache in slab::stats().filter(|cache| cache.slabs > 0) {
+        println!(
+            "slab: {}: {}/{} objects of {} bytes in use ({}%), {} slab(s)",
+            cache.name,
+            cache.objects_in_use,
+            cache.objects_total,
+            cache.object_size,
+            cache.utilization(),
+            cache.slabs
+        );
+    }
 
     loop {
         x86::cli();
-        if main_queue().count() == 0 {
+        let Some(msg) = main_queue().pop() else {
             x86::sti();
             x86::halt();
             continue;
-        }
-
-        let msg = main_queue().front();
-        _ = main_queue().pop();
+        };
         x86::sti();
 
         match msg.0 {
             MessageType::InterruptXhci => {
-                while xhc.process_event_ring_has_front() {
-                    let _e = xhc.process_event();
-                    // dbg!(e);
+                let result = usb::with_xhc(|xhc| {
+                    while xhc.process_event_ring_has_front() {
+                        if let Err(e) = xhc.process_event() {
+                            println!("xhc: {}", e);
+                        }
+                    }
+                    Ok(())
+                });
+                if let Err(e) = result {
+                    println!("xhc: {}", e);
                 }
             }
-            msg => {
-                dbg!("unknown message type:", msg);
+            MessageType::TimerTick => {
+                if usb::is_initialized() {
+                    if let Err(e) = usb::with_xhc(|xhc| xhc.on_timer()) {
+                        println!("xhc: {}", e);
+                    }
+                }
             }
+            MessageType::Ps2Keyboard(data) => ps2::process_keyboard_data(data),
+            MessageType::Ps2Mouse(data) => ps2::process_mouse_data(data),
+            MessageType::Input(event) => input::dispatch(&event),
         }
     }
+}
 
+/// Reads the first block of `disk` and reports whether it carries an MBR boot signature.
+fn print_boot_signature(disk: &mut dyn block::BlockDevice) -> Result<()> {
+    let mut block = alloc::vec![0; disk.block_size()];
+    disk.read(0, &mut block)?;
+    let signed = block.get(510..512) == Some(&[0x55, 0xaa][..]);
+    println!(
+        "block: {}: {} blocks, {} boot signature",
+        disk.name(),
+        disk.num_blocks(),
+        if signed { "with" } else { "no" }
+    );
     Ok(())
 }
 
-extern "C" fn mouse_observer(dx: i8, dy: i8) {
-    _ = mouse_cursor().move_relative(Vector2D::new(dx as i32, dy as i32));
+static XHC_DRIVER: pci::Driver = pci::Driver {
+    name: "xhci",
+    matches: &[pci::Match::Class {
+        class: 0x0c,
+        subclass: 0x03,
+        interface: Some(0x30),
+    }],
+    probe: probe_xhc,
+};
+
+fn probe_xhc(xhc_dev: &pci::Device) -> Result<()> {
+    if usb::is_initialized() {
+        return Err("only one xHC is supported.");
+    }
+
+    let xhc_bar = read_bar(xhc_dev, 0)?;
+    let bar_address = xhc_bar.mem_address().ok_or("xhc bar is not memory.")?;
+    // The direct map already covers the BAR if the memory map lists it; that mapping stays.
+    let mapped = paging::translate(paging::phys_to_virt(bar_address)).is_some();
+    let xhc_mmio_base = paging::map_mmio(bar_address, xhc_bar.size())?;
+    println!(
+        "xhc_bar: {:x?}, xhc_mmio_base: 0x{:08x}",
+        xhc_bar, xhc_mmio_base
+    );
+
+    let result = setup_xhc(xhc_dev, xhc_mmio_base);
+    if result.is_err() && !mapped {
+        // The probe's error is the one worth reporting.
+        _ = paging::unmap_mmio(bar_address, xhc_bar.size());
+    }
+    result
 }
 
-extern "x86-interrupt" fn interrupt_handler_xhci(_: InterruptFrame) {
-    _ = main_queue().push(Message(MessageType::InterruptXhci));
-    notify_end_of_interrupt();
+fn setup_xhc(xhc_dev: &pci::Device, xhc_mmio_base: u64) -> Result<()> {
+    let xhci_vector = interrupt::request(|| {
+        _ = main_queue().push(Message(MessageType::InterruptXhci));
+    })?;
+    let command = pci::read_command(xhc_dev);
+    let result = start_xhc(xhc_dev, xhci_vector, xhc_mmio_base);
+    if result.is_err() {
+        // Leave the function as it was found, for the next driver that matches it.
+        pci::disable_msi(xhc_dev);
+        pci::write_command(xhc_dev, command);
+        interrupt::unregister_handler(xhci_vector);
+    }
+    result
+}
+
+fn start_xhc(
+    xhc_dev: &pci::Device,
+    vector: interrupt::InterruptVector,
+    xhc_mmio_base: u64,
+) -> Result<()> {
+    pci::configure_msi_fixed_destination(
+        xhc_dev,
+        local_apic::id(),
+        pci::MSITriggerMode::Level,
+        pci::MSIDeliveryMode::Fixed(),
+        vector.get() as u8,
+        0,
+    )?;
+    pci::enable_memory_space(xhc_dev);
+    pci::enable_bus_master(xhc_dev);
+    usb::init(xhc_mmio_base)
+}
+
+fn input_sink(event: InputEvent) {
+    _ = main_queue().push(Message(MessageType::Input(event)));
+}
+
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    panic!(
+        "memory allocation of {} bytes (align {}) failed",
+        layout.size(),
+        layout.align()
+    );
 }
 
 #[panic_handler]
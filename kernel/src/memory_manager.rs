@@ -1,4 +1,8 @@
-use crate::Result;
+use crate::{Result, paging};
+use bit_field::BitField;
+use share::memory_map::{
+    EFIMemoryType, MemoryDescriptorVisitor, MemoryMap, UEFI_PAGE_SIZE, is_available,
+};
 
 #[const_trait]
 trait Kib {
@@ -33,8 +37,7 @@ impl const Gib for usize {
     }
 }
 
-const BYTES_PER_FRAME: usize = 4.kib();
-const FULL_FRAME: FrameID = FrameID::new(usize::MAX);
+pub const BYTES_PER_FRAME: usize = 4.kib();
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct FrameID {
@@ -50,10 +53,19 @@ impl FrameID {
         self.id
     }
 
+    /// Returns the frame containing `p`, which must point into the direct map.
+    pub fn from_ptr(p: *const u8) -> Self {
+        Self::new(paging::virt_to_phys(p.addr() as u64) as usize / BYTES_PER_FRAME)
+    }
+
+    pub const fn phys_addr(&self) -> u64 {
+        (self.id * BYTES_PER_FRAME) as u64
+    }
+
     pub fn frame(&mut self) -> &mut [u8] {
         unsafe {
             core::slice::from_raw_parts_mut(
-                (self.id() * BYTES_PER_FRAME) as *mut u8,
+                paging::phys_to_virt(self.phys_addr()) as *mut u8,
                 BYTES_PER_FRAME,
             )
         }
@@ -62,22 +74,142 @@ impl FrameID {
 
 type MapLineType = u64;
 
-struct BitmapMemoryManager {}
+pub static mut MEMORY_MANAGER: BitmapMemoryManager = BitmapMemoryManager::new();
+
+pub fn memory_manager() -> &'static mut BitmapMemoryManager {
+    #[allow(static_mut_refs)]
+    unsafe {
+        &mut MEMORY_MANAGER
+    }
+}
+
+fn is_boot_services(memory_type: EFIMemoryType) -> bool {
+    memory_type == EFIMemoryType::BootServicesCode || memory_type == EFIMemoryType::BootServicesData
+}
+
+/// Marks every frame that is not reported as available by the UEFI memory map as allocated.
+/// Gaps between descriptors are treated as unavailable as well.
+///
+/// Boot services memory still holds the page tables set up by the firmware, so it is kept
+/// allocated until [`reclaim_boot_services_memory`] is called after switching to the kernel's
+/// own page tables.
+pub fn init(memory_map: &MemoryMap) {
+    let mut available_end = 0;
+    MemoryDescriptorVisitor::new(memory_map).for_each(|desc| {
+        let physical_start = desc.physical_start as usize;
+        if available_end < physical_start {
+            memory_manager().mark_allocated(
+                FrameID::new(available_end / BYTES_PER_FRAME),
+                (physical_start - available_end) / BYTES_PER_FRAME,
+            );
+        }
+
+        let physical_end = physical_start + desc.number_of_pages as usize * UEFI_PAGE_SIZE as usize;
+        if is_available(desc.typ) {
+            available_end = physical_end;
+        }
+        if !is_available(desc.typ) || is_boot_services(desc.typ) {
+            memory_manager().mark_allocated(
+                FrameID::new(physical_start / BYTES_PER_FRAME),
+                desc.number_of_pages as usize * UEFI_PAGE_SIZE as usize / BYTES_PER_FRAME,
+            );
+        }
+    });
+
+    memory_manager().set_memory_range(
+        FrameID::new(1),
+        FrameID::new(available_end / BYTES_PER_FRAME),
+    );
+}
+
+pub fn reclaim_boot_services_memory(memory_map: &MemoryMap) -> Result<()> {
+    MemoryDescriptorVisitor::new(memory_map)
+        .filter(|desc| is_boot_services(desc.typ))
+        .try_for_each(|desc| {
+            memory_manager().free(
+                FrameID::new(desc.physical_start as usize / BYTES_PER_FRAME),
+                desc.number_of_pages as usize * UEFI_PAGE_SIZE as usize / BYTES_PER_FRAME,
+            )
+        })
+}
+
+pub struct BitmapMemoryManager {
+    alloc_map: [MapLineType; Self::FRAME_COUNT / Self::BITS_PER_MAP_LINE],
+    range_begin: FrameID,
+    range_end: FrameID,
+}
 
 impl BitmapMemoryManager {
     const MAX_PHYSICAL_MEMORY_BYTES: usize = 128.gib();
     const FRAME_COUNT: usize = Self::MAX_PHYSICAL_MEMORY_BYTES / BYTES_PER_FRAME;
-    const BITS_PER_MAP_LINE: usize = 8 * size_of::<u64>();
+    const BITS_PER_MAP_LINE: usize = MapLineType::BITS as usize;
+
+    pub const fn new() -> Self {
+        Self {
+            alloc_map: [0; Self::FRAME_COUNT / Self::BITS_PER_MAP_LINE],
+            range_begin: FrameID::new(0),
+            range_end: FrameID::new(0),
+        }
+    }
+
+    /// Allocates `num_frames` physically contiguous frames with a first-fit search.
+    pub fn allocate(&mut self, num_frames: usize) -> Result<FrameID> {
+        let mut start_frame_id = self.range_begin.id();
+        loop {
+            let mut i = 0;
+            while i < num_frames {
+                if start_frame_id + i >= self.range_end.id() {
+                    return Err("no enough memory.");
+                }
+                if self.get_bit(FrameID::new(start_frame_id + i)) {
+                    break;
+                }
+                i += 1;
+            }
+
+            if i == num_frames {
+                self.mark_allocated(FrameID::new(start_frame_id), num_frames);
+                return Ok(FrameID::new(start_frame_id));
+            }
+
+            start_frame_id += i + 1;
+        }
+    }
 
-    fn allocate(num_frames: usize) -> Result<FrameID> {
-        todo!()
+    pub fn free(&mut self, start_frame: FrameID, num_frames: usize) -> Result<()> {
+        if start_frame.id() + num_frames > Self::FRAME_COUNT {
+            return Err("frame is out of range.");
+        }
+
+        (0..num_frames).for_each(|i| self.set_bit(FrameID::new(start_frame.id() + i), false));
+        Ok(())
     }
 
-    fn free(start_frame: FrameID, num_frames: usize) -> Result<()> {
-        todo!()
+    pub fn mark_allocated(&mut self, start_frame: FrameID, num_frames: usize) {
+        let end = (start_frame.id() + num_frames).min(Self::FRAME_COUNT);
+        (start_frame.id()..end).for_each(|i| self.set_bit(FrameID::new(i), true));
     }
 
-    fn mark_allocated(start_frame: FrameID, num_frames: usize) {
-        todo!()
+    pub fn set_memory_range(&mut self, range_begin: FrameID, range_end: FrameID) {
+        self.range_begin = range_begin;
+        self.range_end = FrameID::new(range_end.id().min(Self::FRAME_COUNT));
+    }
+
+    fn get_bit(&self, frame: FrameID) -> bool {
+        let line_index = frame.id() / Self::BITS_PER_MAP_LINE;
+        let bit_index = frame.id() % Self::BITS_PER_MAP_LINE;
+        self.alloc_map[line_index].get_bit(bit_index)
+    }
+
+    fn set_bit(&mut self, frame: FrameID, allocated: bool) {
+        let line_index = frame.id() / Self::BITS_PER_MAP_LINE;
+        let bit_index = frame.id() % Self::BITS_PER_MAP_LINE;
+        self.alloc_map[line_index].set_bit(bit_index, allocated);
+    }
+}
+
+impl Default for BitmapMemoryManager {
+    fn default() -> Self {
+        Self::new()
     }
 }
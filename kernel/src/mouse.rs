@@ -1,17 +1,15 @@
 use crate::{
     Result, Rgb, Vector2D,
     frame_buffer::{BGRPixelWriter, PixelWriter},
+    input::InputEvent,
     pixel_writer,
 };
 
 pub static mut MOUSE_CURSOR: Option<MouseCursor> = None;
 
-pub fn init(erace_color: Rgb, initial_pos_x: i32, initial_pos_y: i32) -> Result<()> {
-    let mouse_cursor = MouseCursor::new(
-        pixel_writer(),
-        erace_color,
-        Vector2D::new(initial_pos_x, initial_pos_y),
-    )?;
+pub fn init(initial_pos_x: i32, initial_pos_y: i32) -> Result<()> {
+    let mouse_cursor =
+        MouseCursor::new(pixel_writer(), Vector2D::new(initial_pos_x, initial_pos_y))?;
     unsafe { MOUSE_CURSOR = Some(mouse_cursor) };
     Ok(())
 }
@@ -54,77 +52,128 @@ const MOUSE_CURSOR_SHAPE: [&str; MOUSE_CURSOR_HEIGHT] = [
     "         @@@   ",
 ];
 
-pub fn draw_mouse_cursor<W: PixelWriter>(
-    pixel_writer: &mut W,
+/// Screen coordinates and shape character of each cursor pixel at `position` that lies
+/// on screen. Pixels past an edge are dropped rather than wrapped.
+fn visible_pixels<W: PixelWriter>(
+    pixel_writer: &W,
     position: Vector2D<i32>,
-) -> Result<()> {
-    (0..MOUSE_CURSOR_HEIGHT).try_for_each(|dy| {
-        (0..MOUSE_CURSOR_WIDTH).try_for_each(|dx| {
-            if MOUSE_CURSOR_SHAPE[dy].chars().nth(dx).eq(&Some('@')) {
-                pixel_writer.write(
-                    (position.x + dx as i32) as u32,
-                    (position.y + dy as i32) as u32,
-                    Rgb::black(),
-                )
-            } else if MOUSE_CURSOR_SHAPE[dy].chars().nth(dx).eq(&Some('.')) {
-                pixel_writer.write(
-                    (position.x + dx as i32) as u32,
-                    (position.y + dy as i32) as u32,
-                    Rgb::white(),
-                )
-            } else {
-                Ok(())
+) -> impl Iterator<Item = (u32, u32, u8)> + use<W> {
+    let width = pixel_writer.horizontal_resolution() as i32;
+    let height = pixel_writer.vertical_resolution() as i32;
+    (0..MOUSE_CURSOR_HEIGHT).flat_map(move |dy| {
+        (0..MOUSE_CURSOR_WIDTH).filter_map(move |dx| {
+            let x = position.x + dx as i32;
+            let y = position.y + dy as i32;
+            if !(0..width).contains(&x) || !(0..height).contains(&y) {
+                return None;
             }
+            Some((x as u32, y as u32, MOUSE_CURSOR_SHAPE[dy].as_bytes()[dx]))
         })
-    })?;
-    Ok(())
+    })
 }
 
-pub fn erace_mouse_cursor<W: PixelWriter>(
+pub fn draw_mouse_cursor<W: PixelWriter>(
     pixel_writer: &mut W,
     position: Vector2D<i32>,
-    erasr_color: Rgb,
 ) -> Result<()> {
-    (0..MOUSE_CURSOR_HEIGHT).try_for_each(|dy| {
-        (0..MOUSE_CURSOR_WIDTH).try_for_each(|dx| {
-            if MOUSE_CURSOR_SHAPE[dy].chars().nth(dx).ne(&Some(' ')) {
-                pixel_writer.write(
-                    (position.x + dx as i32) as u32,
-                    (position.y + dy as i32) as u32,
-                    erasr_color,
-                )
-            } else {
-                Ok(())
-            }
-        })
-    })?;
-    Ok(())
+    visible_pixels(pixel_writer, position).try_for_each(|(x, y, c)| match c {
+        b'@' => pixel_writer.write(x, y, Rgb::black()),
+        b'.' => pixel_writer.write(x, y, Rgb::white()),
+        _ => Ok(()),
+    })
 }
 
+/// Draws the cursor on top of whatever is on screen and puts those pixels back when it moves.
+///
+/// Anything drawn under the cursor while it is shown is overwritten on the next move. Once
+/// there is a compositor the cursor should become an overlay layer instead.
 pub struct MouseCursor {
     pixel_writer: &'static mut BGRPixelWriter,
-    erase_color: Rgb,
     position: Vector2D<i32>,
+    save_under: [[Rgb; MOUSE_CURSOR_WIDTH]; MOUSE_CURSOR_HEIGHT],
 }
 
 impl MouseCursor {
     pub fn new(
         pixel_writer: &'static mut BGRPixelWriter,
-        erase_color: Rgb,
         initial_position: Vector2D<i32>,
     ) -> Result<Self> {
-        draw_mouse_cursor(pixel_writer, Vector2D::new(200, 100))?;
-        Ok(Self {
+        let mut mouse_cursor = Self {
             pixel_writer,
-            erase_color,
             position: initial_position,
+            save_under: [[Rgb::black(); MOUSE_CURSOR_WIDTH]; MOUSE_CURSOR_HEIGHT],
+        };
+        mouse_cursor.position = mouse_cursor.clamp(initial_position);
+        mouse_cursor.show()?;
+        Ok(mouse_cursor)
+    }
+
+    fn show(&mut self) -> Result<()> {
+        let position = self.position;
+        visible_pixels(self.pixel_writer, position).try_for_each(|(x, y, _)| {
+            let (dx, dy) = (
+                (x as i32 - position.x) as usize,
+                (y as i32 - position.y) as usize,
+            );
+            self.save_under[dy][dx] = self.pixel_writer.read(x, y)?;
+            Ok::<_, &'static str>(())
+        })?;
+        draw_mouse_cursor(self.pixel_writer, position)
+    }
+
+    fn hide(&mut self) -> Result<()> {
+        let position = self.position;
+        visible_pixels(self.pixel_writer, position).try_for_each(|(x, y, c)| {
+            if c == b' ' {
+                return Ok(());
+            }
+            let (dx, dy) = (
+                (x as i32 - position.x) as usize,
+                (y as i32 - position.y) as usize,
+            );
+            self.pixel_writer.write(x, y, self.save_under[dy][dx])
         })
     }
 
+    /// Keeps the hot spot on screen. The rest of the cursor may hang over the edge.
+    fn clamp(&self, position: Vector2D<i32>) -> Vector2D<i32> {
+        let max_x = self.pixel_writer.horizontal_resolution() as i32 - 1;
+        let max_y = self.pixel_writer.vertical_resolution() as i32 - 1;
+        Vector2D::new(position.x.clamp(0, max_x), position.y.clamp(0, max_y))
+    }
+
+    fn move_to(&mut self, position: Vector2D<i32>) -> Result<()> {
+        let position = self.clamp(position);
+        self.hide()?;
+        self.position = position;
+        self.show()
+    }
+
     pub fn move_relative(&mut self, displacement: Vector2D<i32>) -> Result<()> {
-        erace_mouse_cursor(self.pixel_writer, self.position, self.erase_color)?;
-        self.position += displacement;
-        draw_mouse_cursor(self.pixel_writer, self.position)?;
-        Ok(())
+        self.move_to(self.position + displacement)
+    }
+
+    /// Moves to the point `(x, y)` of a device whose coordinates run from 0 to `max`.
+    pub fn move_absolute(&mut self, x: u32, y: u32, max: Vector2D<u32>) -> Result<()> {
+        let scale = |value: u32, max: u32, resolution: u32| {
+            (value.min(max) as u64 * (resolution as u64 - 1) / max.max(1) as u64) as i32
+        };
+        let position = Vector2D::new(
+            scale(x, max.x, self.pixel_writer.horizontal_resolution()),
+            scale(y, max.y, self.pixel_writer.vertical_resolution()),
+        );
+        self.move_to(position)
+    }
+
+    pub fn on_input(&mut self, event: &InputEvent) {
+        match *event {
+            InputEvent::RelativeMotion { dx, dy } => {
+                _ = self.move_relative(Vector2D::new(dx, dy));
+            }
+            InputEvent::AbsoluteMotion { x, y, max_x, max_y } => {
+                _ = self.move_absolute(x, y, Vector2D::new(max_x, max_y));
+            }
+            _ => {}
+        }
     }
 }
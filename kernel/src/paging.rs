@@ -1,52 +1,405 @@
-use crate::x86;
+use crate::{
+    Result, local_apic::LOCAL_APIC_BASE, memory_manager::memory_manager, pixel_writer, x86,
+};
+use bit_field::BitField;
+use core::ops::BitOr;
+use share::{
+    memory_layout::DIRECT_MAP_BASE,
+    memory_map::{EFIMemoryType, MemoryDescriptorVisitor, MemoryMap, UEFI_PAGE_SIZE},
+};
 
-const PAGE_DIRECTORY_COUNT: usize = 64;
 const PAGE_SIZE_4K: u64 = 4096;
 const PAGE_SIZE_2M: u64 = 512 * PAGE_SIZE_4K;
 const PAGE_SIZE_1G: u64 = 512 * PAGE_SIZE_2M;
+const ENTRIES_PER_TABLE: usize = 512;
 
-#[repr(align(4096))]
-struct Pml4Table([u64; 512]);
+/// PML4 slot holding the kernel image mapping (`share::memory_layout::KERNEL_BASE`).
+const KERNEL_PML4_INDEX: usize = 511;
 
-#[repr(align(4096))]
-struct PdpTable([u64; 512]);
+/// Returns the address through which the kernel accesses physical address `phys`.
+pub const fn phys_to_virt(phys: u64) -> u64 {
+    DIRECT_MAP_BASE + phys
+}
+
+/// Inverse of [`phys_to_virt`]. Only valid for addresses inside the direct map.
+pub const fn virt_to_phys(virt: u64) -> u64 {
+    virt - DIRECT_MAP_BASE
+}
+
+static mut PML4_TABLE: u64 = 0;
+fn pml4_table() -> &'static mut PageTable {
+    table_at(unsafe { PML4_TABLE })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageSize {
+    Size4KiB,
+    Size2MiB,
+    Size1GiB,
+}
+
+impl PageSize {
+    pub const fn bytes(self) -> u64 {
+        match self {
+            PageSize::Size4KiB => PAGE_SIZE_4K,
+            PageSize::Size2MiB => PAGE_SIZE_2M,
+            PageSize::Size1GiB => PAGE_SIZE_1G,
+        }
+    }
+
+    /// Paging structure level whose entries map a page of this size (PT = 1, PD = 2, PDPT = 3).
+    const fn level(self) -> usize {
+        match self {
+            PageSize::Size4KiB => 1,
+            PageSize::Size2MiB => 2,
+            PageSize::Size1GiB => 3,
+        }
+    }
+
+    const fn from_level(level: usize) -> Self {
+        match level {
+            3 => PageSize::Size1GiB,
+            2 => PageSize::Size2MiB,
+            _ => PageSize::Size4KiB,
+        }
+    }
+}
+
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFlags(u64);
+
+#[allow(non_snake_case)]
+#[allow(unused)]
+impl PageFlags {
+    const PRESENT: u64 = 1 << 0;
+    const WRITABLE: u64 = 1 << 1;
+    const USER: u64 = 1 << 2;
+    const WRITE_THROUGH: u64 = 1 << 3;
+    const CACHE_DISABLE: u64 = 1 << 4;
+    const HUGE_PAGE: u64 = 1 << 7;
+    /// PAT bit of a 4 KiB page. Large pages use this bit as `HUGE_PAGE` and carry the PAT bit
+    /// in `LARGE_PAT` instead.
+    const PAT: u64 = 1 << 7;
+    const LARGE_PAT: u64 = 1 << 12;
+    const GLOBAL: u64 = 1 << 8;
+    const NO_EXECUTE: u64 = 1 << 63;
+
+    pub const fn Present() -> Self {
+        Self(Self::PRESENT)
+    }
+
+    pub const fn Writable() -> Self {
+        Self(Self::WRITABLE)
+    }
+
+    pub const fn User() -> Self {
+        Self(Self::USER)
+    }
+
+    pub const fn WriteThrough() -> Self {
+        Self(Self::WRITE_THROUGH)
+    }
 
-#[repr(align(4096))]
-struct PageDirectory([[u64; 512]; PAGE_DIRECTORY_COUNT]);
+    pub const fn CacheDisable() -> Self {
+        Self(Self::CACHE_DISABLE)
+    }
+
+    pub const fn Global() -> Self {
+        Self(Self::GLOBAL)
+    }
+
+    pub const fn NoExecute() -> Self {
+        Self(Self::NO_EXECUTE)
+    }
+
+    /// Read/write, write-back cached memory.
+    pub const fn KernelData() -> Self {
+        Self(Self::PRESENT | Self::WRITABLE)
+    }
+
+    /// Read/write, uncached memory for device registers.
+    pub const fn Mmio() -> Self {
+        Self(Self::PRESENT | Self::WRITABLE | Self::WRITE_THROUGH | Self::CACHE_DISABLE)
+    }
 
-static mut PML4_TABLE: Pml4Table = Pml4Table([0; 512]);
-fn pml4_table() -> &'static mut [u64; 512] {
-    #[allow(static_mut_refs)]
-    unsafe {
-        &mut PML4_TABLE.0
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
     }
 }
 
-static mut PDP_TABLE: PdpTable = PdpTable([0; 512]);
-fn pdp_table() -> &'static mut [u64; 512] {
-    #[allow(static_mut_refs)]
-    unsafe {
-        &mut PDP_TABLE.0
+impl BitOr for PageFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
     }
 }
 
-static mut PAGE_DIRECTORY: PageDirectory = PageDirectory([[0; 512]; PAGE_DIRECTORY_COUNT]);
-fn page_directory() -> &'static mut [[u64; 512]; PAGE_DIRECTORY_COUNT] {
-    #[allow(static_mut_refs)]
-    unsafe {
-        &mut PAGE_DIRECTORY.0
+#[repr(transparent)]
+#[derive(Debug, Default, Clone, Copy)]
+struct PageTableEntry(u64);
+
+impl PageTableEntry {
+    const ADDRESS_BITS: core::ops::Range<usize> = 12..52;
+    const ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+    fn present(&self) -> bool {
+        self.0.get_bit(0)
+    }
+
+    fn huge(&self) -> bool {
+        self.0.get_bit(7)
+    }
+
+    fn flags(&self) -> PageFlags {
+        PageFlags(self.0 & !Self::ADDRESS_MASK)
+    }
+
+    /// Physical address this entry points at. For large pages the PAT bit (bit 12) is masked off.
+    fn addr(&self, level: usize) -> u64 {
+        let addr = self.0.get_bits(Self::ADDRESS_BITS) << 12;
+        if level > 1 && self.huge() {
+            addr & !(PageSize::from_level(level).bytes() - 1)
+        } else {
+            addr
+        }
+    }
+
+    fn set(&mut self, addr: u64, flags: PageFlags) {
+        self.0 = addr | flags.0;
+    }
+
+    fn clear(&mut self) {
+        self.0 = 0;
     }
 }
 
-pub fn setup_identity_page_table() {
-    pml4_table()[0] = pdp_table().as_ptr().addr() as u64 | 0x003;
-    (0..page_directory().len()).for_each(|i_pdpt| {
-        pdp_table()[i_pdpt] = (page_directory()[i_pdpt]).as_ptr().addr() as u64 | 0x003;
-        (0..512).for_each(|i_pd| {
-            page_directory()[i_pdpt][i_pd] =
-                (i_pdpt as u64 * PAGE_SIZE_1G + i_pd as u64 * PAGE_SIZE_2M) | 0x083;
-        });
-    });
+#[repr(C, align(4096))]
+struct PageTable([PageTableEntry; ENTRIES_PER_TABLE]);
+
+fn table_at(phys: u64) -> &'static mut PageTable {
+    unsafe { &mut *(phys_to_virt(phys) as *mut PageTable) }
+}
+
+fn new_table() -> Result<u64> {
+    let mut frame = memory_manager().allocate(1)?;
+    frame.frame().fill(0);
+    Ok(frame.phys_addr())
+}
+
+fn current_pml4_table() -> &'static mut PageTable {
+    table_at(x86::get_cr3() & PageTableEntry::ADDRESS_MASK)
+}
+
+fn index(virt: u64, level: usize) -> usize {
+    virt.get_bits(12 + 9 * (level - 1)..21 + 9 * (level - 1)) as usize
+}
+
+/// Flags given to intermediate tables. Access rights are enforced by the leaf entries.
+fn table_flags() -> PageFlags {
+    PageFlags::Present() | PageFlags::Writable()
+}
+
+/// Replaces a large page with a table of the next smaller pages covering the same range.
+fn split(entry: &mut PageTableEntry, level: usize) -> Result<()> {
+    let base = entry.addr(level);
+    let child_size = PageSize::from_level(level - 1);
+    let mut child_flags = PageFlags(entry.flags().0 & !PageFlags::HUGE_PAGE);
+    // `flags` masks off the address bits, among them the PAT bit of a large page.
+    let pat = entry.0 & PageFlags::LARGE_PAT != 0;
+    if child_size != PageSize::Size4KiB {
+        child_flags = child_flags | PageFlags(PageFlags::HUGE_PAGE);
+        if pat {
+            child_flags = child_flags | PageFlags(PageFlags::LARGE_PAT);
+        }
+    } else if pat {
+        child_flags = child_flags | PageFlags(PageFlags::PAT);
+    }
+
+    let table_phys = new_table()?;
+    table_at(table_phys)
+        .0
+        .iter_mut()
+        .enumerate()
+        .for_each(|(i, e)| e.set(base + i as u64 * child_size.bytes(), child_flags));
+    entry.set(table_phys, table_flags());
+    x86::flush_tlb();
+    Ok(())
+}
+
+/// Walks down to the entry that maps `virt` with a page of `size`, creating or splitting
+/// intermediate tables as needed.
+fn walk_create(virt: u64, size: PageSize) -> Result<&'static mut PageTableEntry> {
+    let mut table = pml4_table();
+    for level in (size.level() + 1..=4).rev() {
+        let entry = &mut table.0[index(virt, level)];
+        if !entry.present() {
+            entry.set(new_table()?, table_flags());
+        } else if entry.huge() {
+            split(entry, level)?;
+        }
+        table = table_at(entry.addr(level));
+    }
+    Ok(&mut table.0[index(virt, size.level())])
+}
+
+/// Returns the leaf entry mapping `virt` and the size of the page it maps.
+fn walk(virt: u64) -> Option<(&'static mut PageTableEntry, PageSize)> {
+    let mut table = pml4_table();
+    for level in (1..=4).rev() {
+        let entry = &mut table.0[index(virt, level)];
+        if !entry.present() {
+            return None;
+        }
+        if level == 1 || (level <= 3 && entry.huge()) {
+            return Some((entry, PageSize::from_level(level)));
+        }
+        table = table_at(entry.addr(level));
+    }
+    None
+}
+
+/// Maps the page of `size` at `virt` to `phys`. An existing leaf mapping is replaced.
+pub fn map(virt: u64, phys: u64, size: PageSize, flags: PageFlags) -> Result<()> {
+    if virt % size.bytes() != 0 || phys % size.bytes() != 0 {
+        return Err("address is not aligned to the page size.");
+    }
+    if size == PageSize::Size1GiB && !x86::supports_1gib_pages() {
+        return Err("1 GiB pages are not supported.");
+    }
+
+    let entry = walk_create(virt, size)?;
+    if entry.present() && size != PageSize::Size4KiB && !entry.huge() {
+        return Err("range is already mapped with smaller pages.");
+    }
+
+    let was_present = entry.present();
+    let mut flags = flags | PageFlags::Present();
+    if size != PageSize::Size4KiB {
+        flags = flags | PageFlags(PageFlags::HUGE_PAGE);
+    }
+    entry.set(phys, flags);
+    if was_present {
+        x86::invlpg(virt);
+    }
+    Ok(())
+}
+
+/// Maps `[virt, virt + len)` to `[phys, phys + len)` using the largest pages alignment allows.
+pub fn map_range(virt: u64, phys: u64, len: u64, flags: PageFlags) -> Result<()> {
+    let mut offset = 0;
+    while offset < len {
+        let size = [PageSize::Size1GiB, PageSize::Size2MiB, PageSize::Size4KiB]
+            .into_iter()
+            .filter(|s| *s != PageSize::Size1GiB || x86::supports_1gib_pages())
+            .find(|s| {
+                (virt + offset) % s.bytes() == 0
+                    && (phys + offset) % s.bytes() == 0
+                    && len - offset >= s.bytes()
+            })
+            .unwrap_or(PageSize::Size4KiB);
+        map(virt + offset, phys + offset, size, flags)?;
+        offset += size.bytes();
+    }
+    Ok(())
+}
+
+/// Removes the mapping of the 4 KiB page containing `virt`. A larger page around it is split
+/// first, so the rest of it stays mapped.
+pub fn unmap(virt: u64) -> Result<()> {
+    walk(virt).ok_or("address is not mapped.")?;
+    let entry = walk_create(virt, PageSize::Size4KiB)?;
+    entry.clear();
+    x86::invlpg(virt & !(PAGE_SIZE_4K - 1));
+    Ok(())
+}
+
+/// Removes the mappings of `[virt, virt + len)`. Pages that reach past either end are split,
+/// so that only the range itself goes away.
+pub fn unmap_range(virt: u64, len: u64) -> Result<()> {
+    let end = virt + len;
+    let mut virt = virt & !(PAGE_SIZE_4K - 1);
+    while virt < end {
+        match walk(virt) {
+            Some((entry, size)) if virt % size.bytes() == 0 && end - virt >= size.bytes() => {
+                entry.clear();
+                x86::invlpg(virt);
+                virt += size.bytes();
+            }
+            Some(_) => {
+                unmap(virt)?;
+                virt += PAGE_SIZE_4K;
+            }
+            None => virt += PAGE_SIZE_4K,
+        }
+    }
+    Ok(())
+}
+
+pub fn translate(virt: u64) -> Option<u64> {
+    let (entry, size) = walk(virt)?;
+    Some(entry.addr(size.level()) + (virt & (size.bytes() - 1)))
+}
+
+/// Maps a device register region uncached into the direct map and returns its virtual address.
+pub fn map_mmio(phys: u64, len: u64) -> Result<u64> {
+    let start = phys & !(PAGE_SIZE_4K - 1);
+    let end = (phys + len).next_multiple_of(PAGE_SIZE_4K);
+    map_range(phys_to_virt(start), start, end - start, PageFlags::Mmio())?;
+    Ok(phys_to_virt(phys))
+}
+
+/// Removes what [`map_mmio`] mapped for the same region.
+pub fn unmap_mmio(phys: u64, len: u64) -> Result<()> {
+    let start = phys & !(PAGE_SIZE_4K - 1);
+    let end = (phys + len).next_multiple_of(PAGE_SIZE_4K);
+    unmap_range(phys_to_virt(start), end - start)
+}
+
+fn is_mmio(memory_type: EFIMemoryType) -> bool {
+    memory_type == EFIMemoryType::MemoryMappedIO
+        || memory_type == EFIMemoryType::MemoryMappedIOPortSpace
+        || memory_type == EFIMemoryType::ReservedMemoryType
+}
+
+/// Builds the kernel page tables: every region of the UEFI memory map is mapped into the direct
+/// map, device regions uncached, and the frame buffer and local APIC are mapped explicitly.
+/// The kernel image mapping is taken over from the tables the loader built. The lower half is
+/// left empty apart from what callers identity map on purpose.
+pub fn init(memory_map: &MemoryMap) -> Result<()> {
+    let pml4 = new_table()?;
+    table_at(pml4).0[KERNEL_PML4_INDEX] = current_pml4_table().0[KERNEL_PML4_INDEX];
+    unsafe { PML4_TABLE = pml4 };
+
+    MemoryDescriptorVisitor::new(memory_map).try_for_each(|desc| {
+        let flags = if is_mmio(desc.typ) {
+            PageFlags::Mmio()
+        } else {
+            PageFlags::KernelData()
+        };
+        map_range(
+            phys_to_virt(desc.physical_start),
+            desc.physical_start,
+            desc.number_of_pages * UEFI_PAGE_SIZE as u64,
+            flags,
+        )
+    })?;
+
+    let config = pixel_writer().config();
+    let frame_buffer_size =
+        config.pixels_per_scan_line as u64 * config.vertical_resolution as u64 * 4;
+    let frame_buffer_phys = virt_to_phys(config.frame_buffer.addr() as u64);
+    let frame_buffer_start = frame_buffer_phys & !(PAGE_SIZE_4K - 1);
+    map_range(
+        phys_to_virt(frame_buffer_start),
+        frame_buffer_start,
+        (frame_buffer_phys + frame_buffer_size - frame_buffer_start).next_multiple_of(PAGE_SIZE_4K),
+        PageFlags::KernelData() | PageFlags::WriteThrough(),
+    )?;
+
+    map_mmio(LOCAL_APIC_BASE, PAGE_SIZE_4K)?;
 
-    x86::set_cr3(pml4_table().as_ptr().addr() as u64);
+    x86::set_cr3(pml4);
+    Ok(())
 }
@@ -1,10 +1,22 @@
 // #![allow(unused)]
 
-use crate::{Result, x86};
+use crate::{
+    Result, acpi, paging,
+    slab::{self, CacheId, SlabBox},
+    x86,
+};
+use alloc::vec::Vec;
 use bit_field::BitField;
+use core::alloc::Layout;
+
+mod driver;
+
+pub use driver::{Driver, Match, bind_drivers, is_bound, register_driver};
 
 const CONFIG_ADDRESS: u16 = 0x0cf8;
 const CONFIG_DATA: u16 = 0x0cfc;
+/// Bytes of configuration space per function that the I/O ports reach.
+const LEGACY_CONFIG_SPACE_SIZE: u16 = 0x100;
 
 #[allow(unused)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -57,8 +69,63 @@ pub struct Device {
     pub bus: u8,
     pub device: u8,
     pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    /// Zero for bridges, which keep theirs in a capability instead.
+    pub subsystem_vendor_id: u16,
+    pub subsystem_id: u16,
+    pub revision: u8,
+    /// Including the multi-function bit.
     pub header_type: u8,
+    /// Base class, subclass and programming interface.
     pub class_code: (u8, u8, u8),
+    pub interrupt_line: u8,
+    /// 1 to 4 for INTA# to INTD#, or 0 if the function uses no legacy interrupt.
+    pub interrupt_pin: u8,
+}
+
+const HEADER_TYPE_NORMAL: u8 = 0x00;
+const HEADER_TYPE_BRIDGE: u8 = 0x01;
+
+impl Device {
+    fn read(bus: u8, device: u8, function: u8) -> Self {
+        let id = read_config(bus, device, function, 0x00);
+        let class = read_config(bus, device, function, 0x08);
+        let header_type = read_header_type(bus, device, function);
+        let subsystem = match header_type & 0x7f {
+            HEADER_TYPE_NORMAL => read_config(bus, device, function, 0x2c),
+            _ => 0,
+        };
+        let interrupt = read_config(bus, device, function, 0x3c);
+        Self {
+            bus,
+            device,
+            function,
+            vendor_id: id.get_bits(0..16) as u16,
+            device_id: id.get_bits(16..32) as u16,
+            subsystem_vendor_id: subsystem.get_bits(0..16) as u16,
+            subsystem_id: subsystem.get_bits(16..32) as u16,
+            revision: class.get_bits(0..8) as u8,
+            header_type,
+            class_code: (
+                class.get_bits(24..32) as u8,
+                class.get_bits(16..24) as u8,
+                class.get_bits(8..16) as u8,
+            ),
+            interrupt_line: interrupt.get_bits(0..8) as u8,
+            interrupt_pin: interrupt.get_bits(8..16) as u8,
+        }
+    }
+
+    /// Bus, device and function numbers, which identify the function.
+    pub fn location(&self) -> (u8, u8, u8) {
+        (self.bus, self.device, self.function)
+    }
+
+    /// Whether this is a PCI-to-PCI bridge with buses behind it.
+    pub fn is_bridge(&self) -> bool {
+        self.header_type & 0x7f == HEADER_TYPE_BRIDGE
+    }
 }
 
 #[repr(C)]
@@ -107,25 +174,55 @@ impl MSICapabilityHeader {
     }
 }
 
-pub static mut DEVICES: [Option<Device>; 32] = [None; 32];
+static mut DEVICES: Vec<SlabBox<Device>> = Vec::new();
+/// Device records come from a cache of their own, created by the first scan.
+static mut DEVICE_CACHE: Option<CacheId> = None;
 
-pub fn scan_all_bus() -> Result<()> {
-    let header_type = read_header_type(0, 0, 0);
-    if is_single_function_device(header_type) {
-        return scan_bus(0);
+/// Functions found by the last [`scan_all_bus`], in the order they were found. This is a copy,
+/// so a later scan does not pull the functions out from under the caller.
+pub fn devices() -> Vec<Device> {
+    devices_mut().iter().map(|dev| **dev).collect()
+}
+
+fn devices_mut() -> &'static mut Vec<SlabBox<Device>> {
+    #[allow(static_mut_refs)]
+    unsafe {
+        &mut DEVICES
     }
-    (1..8)
-        .filter(|function| read_vendor_id(0, 0, *function) != 0xffff)
-        .try_for_each(|function| scan_bus(function))
 }
 
-fn scan_bus(bus: u8) -> Result<()> {
+/// Enumerates every function on bus 0 and on the first bus of each ECAM region, which is where
+/// additional host bridges put theirs, descending through PCI-to-PCI bridges.
+pub fn scan_all_bus() -> Result<()> {
+    devices_mut().clear();
+    let cache = match unsafe { DEVICE_CACHE } {
+        Some(cache) => cache,
+        None => {
+            let cache = slab::create_cache("pci-device", Layout::new::<Device>())?;
+            unsafe { DEVICE_CACHE = Some(cache) };
+            cache
+        }
+    };
+    let mut visited = [false; 256];
+    let root_buses: Vec<u8> = core::iter::once(0)
+        .chain(ecam_regions().iter().map(|region| region.start_bus))
+        .collect();
+    root_buses
+        .into_iter()
+        .try_for_each(|bus| scan_bus(cache, bus, &mut visited))
+}
+
+/// `visited` guards against firmware that assigned the same secondary bus twice.
+fn scan_bus(cache: CacheId, bus: u8, visited: &mut [bool; 256]) -> Result<()> {
+    if core::mem::replace(&mut visited[bus as usize], true) {
+        return Ok(());
+    }
     (0..32)
         .filter(|device| read_vendor_id(bus, *device, 0) != 0xffff)
-        .try_for_each(|device| scan_device(bus, device))
+        .try_for_each(|device| scan_device(cache, bus, device, visited))
 }
 
-fn read_msi_capability(dev: &Device, cap_addr: u8) -> MSICapability {
+fn read_msi_capability(dev: &Device, cap_addr: u16) -> MSICapability {
     let mut msi_cap = MSICapability {
         header: MSICapabilityHeader(read_conf_reg(dev, cap_addr)),
         msg_addr: read_conf_reg(dev, cap_addr + 4),
@@ -148,7 +245,7 @@ fn read_msi_capability(dev: &Device, cap_addr: u8) -> MSICapability {
     msi_cap
 }
 
-fn write_msi_capability(dev: &Device, cap_addr: u8, msi_cap: &MSICapability) {
+fn write_msi_capability(dev: &Device, cap_addr: u16, msi_cap: &MSICapability) {
     write_conf_reg(dev, cap_addr, msi_cap.header.data());
     write_conf_reg(dev, cap_addr + 4, msi_cap.msg_addr);
 
@@ -168,7 +265,7 @@ fn write_msi_capability(dev: &Device, cap_addr: u8, msi_cap: &MSICapability) {
 
 fn configure_msi_register(
     dev: &Device,
-    cap_addr: u8,
+    cap_addr: u16,
     msg_addr: u32,
     msg_data: u32,
     num_vector_exponent: u32,
@@ -193,6 +290,153 @@ fn configure_msi_register(
     Ok(())
 }
 
+#[repr(transparent)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MSIXCapabilityHeader(u32);
+
+impl MSIXCapabilityHeader {
+    fn table_size(&self) -> u16 {
+        self.0.get_bits(16..27) as u16 + 1
+    }
+
+    fn set_function_mask(&mut self, value: bool) {
+        self.0.set_bit(30, value);
+    }
+
+    fn set_msix_enable(&mut self, value: bool) {
+        self.0.set_bit(31, value);
+    }
+}
+
+const MSIX_TABLE_ENTRY_SIZE: u64 = 16;
+/// Bit of the Vector Control word that masks the entry.
+const MSIX_VECTOR_MASK: usize = 0;
+
+/// The MSI-X table and pending bit array of a device, mapped into the kernel.
+///
+/// [PCI Local Bus Specification 3.0, 6.8.2 MSI-X Capability and Table Structure](https://pcisig.com/specifications)
+#[derive(Debug, Clone, Copy)]
+pub struct MSIXTable {
+    dev: Device,
+    cap_addr: u16,
+    table: u64,
+    pba: u64,
+    size: u16,
+}
+
+#[allow(dead_code)]
+impl MSIXTable {
+    fn new(dev: &Device, cap_addr: u16) -> Result<Self> {
+        let header = MSIXCapabilityHeader(read_conf_reg(dev, cap_addr));
+        let size = header.table_size();
+        let map = |reg: u32, len: u64| -> Result<u64> {
+            let bar = read_bar(dev, reg.get_bits(0..3))?
+                .mem_address()
+                .ok_or("msi-x table is not in memory space.")?;
+            paging::map_mmio(bar + (reg & !0x7) as u64, len)
+        };
+        let table = map(
+            read_conf_reg(dev, cap_addr + 4),
+            size as u64 * MSIX_TABLE_ENTRY_SIZE,
+        )?;
+        let pba = map(
+            read_conf_reg(dev, cap_addr + 8),
+            (size as u64).div_ceil(64) * 8,
+        )?;
+        Ok(Self {
+            dev: *dev,
+            cap_addr,
+            table,
+            pba,
+            size,
+        })
+    }
+
+    /// Number of vectors the device supports.
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    fn entry(&self, index: u16, offset: u64) -> Result<*mut u32> {
+        if index >= self.size {
+            return Err("index is out of range.");
+        }
+        Ok((self.table + index as u64 * MSIX_TABLE_ENTRY_SIZE + offset) as *mut u32)
+    }
+
+    /// Programs the message of the vector at `index`, leaving its mask as it is.
+    pub fn set_message(&self, index: u16, msg_addr: u64, msg_data: u32) -> Result<()> {
+        unsafe {
+            self.entry(index, 0)?.write_volatile(msg_addr as u32);
+            self.entry(index, 4)?
+                .write_volatile((msg_addr >> 32) as u32);
+            self.entry(index, 8)?.write_volatile(msg_data);
+        }
+        Ok(())
+    }
+
+    pub fn set_masked(&self, index: u16, masked: bool) -> Result<()> {
+        let control = self.entry(index, 12)?;
+        unsafe {
+            let value = *control.read_volatile().set_bit(MSIX_VECTOR_MASK, masked);
+            control.write_volatile(value);
+        }
+        Ok(())
+    }
+
+    pub fn is_masked(&self, index: u16) -> Result<bool> {
+        Ok(unsafe { self.entry(index, 12)?.read_volatile() }.get_bit(MSIX_VECTOR_MASK))
+    }
+
+    /// Whether the vector at `index` has a message waiting because it was masked.
+    pub fn is_pending(&self, index: u16) -> Result<bool> {
+        if index >= self.size {
+            return Err("index is out of range.");
+        }
+        let qword = unsafe { ((self.pba + index as u64 / 64 * 8) as *const u64).read_volatile() };
+        Ok(qword.get_bit(index as usize % 64))
+    }
+
+    /// Masks or unmasks every vector of the device at once, whatever their own masks are.
+    pub fn set_function_mask(&self, masked: bool) {
+        let mut header = MSIXCapabilityHeader(read_conf_reg(&self.dev, self.cap_addr));
+        header.set_function_mask(masked);
+        write_conf_reg(&self.dev, self.cap_addr, header.0);
+    }
+
+    pub fn set_enable(&self, enable: bool) {
+        let mut header = MSIXCapabilityHeader(read_conf_reg(&self.dev, self.cap_addr));
+        header.set_msix_enable(enable);
+        write_conf_reg(&self.dev, self.cap_addr, header.0);
+    }
+}
+
+/// Enables MSI-X with entry `i` of the table sending `messages[i]`. The other entries stay
+/// masked.
+fn configure_msix_register(
+    dev: &Device,
+    cap_addr: u16,
+    messages: &[(u32, u32)],
+) -> Result<MSIXTable> {
+    let table = MSIXTable::new(dev, cap_addr)?;
+    if messages.len() > table.size() as usize {
+        return Err("too many msi-x vectors.");
+    }
+
+    // Nothing may be sent while the table is half written.
+    table.set_function_mask(true);
+    table.set_enable(true);
+    for index in 0..table.size() {
+        table.set_masked(index, true)?;
+    }
+    for (index, &(msg_addr, msg_data)) in (0..).zip(messages) {
+        table.set_message(index, msg_addr as u64, msg_data)?;
+        table.set_masked(index, false)?;
+    }
+    table.set_function_mask(false);
+    Ok(table)
+}
+
 #[repr(transparent)]
 #[derive(Debug, Default)]
 struct CapabilityHeader(u32);
@@ -202,8 +446,8 @@ impl CapabilityHeader {
         self.0.get_bits(0..8) as u8
     }
 
-    fn next_ptr(&self) -> u8 {
-        self.0.get_bits(8..18) as u8
+    fn next_ptr(&self) -> u16 {
+        self.0.get_bits(8..16) as u16
     }
 
     #[allow(dead_code)]
@@ -212,87 +456,205 @@ impl CapabilityHeader {
     }
 }
 
-fn read_capability_header(dev: &Device, addr: u8) -> CapabilityHeader {
+fn read_capability_header(dev: &Device, addr: u16) -> CapabilityHeader {
     CapabilityHeader(read_conf_reg(dev, addr))
 }
 
 const CAPABILITY_MSI: u8 = 0x05;
 const CAPABILITY_MSIX: u8 = 0x11;
 
+fn find_capability(dev: &Device, cap_id: u8) -> Option<u16> {
+    let mut cap_addr = (read_conf_reg(dev, 0x34) & 0xff) as u16;
+    while cap_addr != 0 {
+        let header = read_capability_header(dev, cap_addr);
+        if header.cap_id() == cap_id {
+            return Some(cap_addr);
+        }
+        cap_addr = header.next_ptr();
+    }
+    None
+}
+
+/// Walks the extended capabilities that follow the first 256 bytes of a PCI Express function.
+///
+/// [PCI Express Base Specification 4.0, 7.6 PCI Express Extended Capabilities](https://pcisig.com/specifications)
+#[allow(dead_code)]
+pub fn find_extended_capability(dev: &Device, cap_id: u16) -> Option<u16> {
+    let mut cap_addr = LEGACY_CONFIG_SPACE_SIZE;
+    while cap_addr != 0 {
+        let header = read_conf_reg(dev, cap_addr);
+        // Functions without extended capabilities read as 0, or all ones without ECAM.
+        if header == 0 || header == 0xffff_ffff {
+            return None;
+        }
+        if header.get_bits(0..16) as u16 == cap_id {
+            return Some(cap_addr);
+        }
+        cap_addr = header.get_bits(20..32) as u16 & !0x3;
+    }
+    None
+}
+
+/// Configures MSI, or MSI-X with consecutive vectors if the device only has MSI-X.
 fn configure_msi(
     dev: &Device,
     msg_addr: u32,
     msg_data: u32,
     num_vector_exponent: u32,
 ) -> Result<()> {
-    let mut cap_addr = (read_conf_reg(dev, 0x34) & 0xff) as u8;
-    let mut msi_cap_addr = 0;
-    let mut msix_cap_addr = 0;
-    while cap_addr != 0 {
-        let header = read_capability_header(dev, cap_addr);
-        if header.cap_id() == CAPABILITY_MSI {
-            msi_cap_addr = cap_addr;
-        } else if header.cap_id() == CAPABILITY_MSIX {
-            msix_cap_addr = cap_addr;
-        }
-        cap_addr = header.next_ptr();
-    }
-    if msi_cap_addr > 0 {
+    if let Some(msi_cap_addr) = find_capability(dev, CAPABILITY_MSI) {
         return configure_msi_register(dev, msi_cap_addr, msg_addr, msg_data, num_vector_exponent);
-    } else if msix_cap_addr > 0 {
-        return Err("not implemented.");
+    }
+    if let Some(msix_cap_addr) = find_capability(dev, CAPABILITY_MSIX) {
+        let messages: Vec<_> = (0..1 << num_vector_exponent)
+            .map(|i| (msg_addr, msg_data + i))
+            .collect();
+        return configure_msix_register(dev, msix_cap_addr, &messages).map(|_| ());
     }
     Err("no pci msi.")
 }
 
-pub fn configure_msi_fixed_destination(
-    dev: &Device,
+fn msi_message(
     apic_id: u8,
     trigger_mode: MSITriggerMode,
     delivery_mode: MSIDeliveryMode,
     vector: u8,
-    num_vector_exponent: u32,
-) -> Result<()> {
+) -> (u32, u32) {
     let msg_addr = 0xfee0_0000 | ((apic_id as u32) << 12);
     let mut msg_data = ((delivery_mode.0 as u32) << 8) | vector as u32;
     if trigger_mode == MSITriggerMode::Level {
         msg_data |= 0xc000;
     }
-    configure_msi(dev, msg_addr, msg_data, num_vector_exponent)
+    (msg_addr, msg_data)
 }
 
-fn scan_function(bus: u8, device: u8, function: u8) -> Result<()> {
-    let header_type = read_header_type(bus, device, function);
-    let class_code = read_class_code(bus, device, function);
-
-    let dev = Device {
-        bus,
-        device,
-        function,
-        header_type,
-        class_code,
-    };
+pub fn configure_msi_fixed_destination(
+    dev: &Device,
+    apic_id: u8,
+    trigger_mode: MSITriggerMode,
+    delivery_mode: MSIDeliveryMode,
+    vector: u8,
+    num_vector_exponent: u32,
+) -> Result<()> {
+    let (msg_addr, msg_data) = msi_message(apic_id, trigger_mode, delivery_mode, vector);
+    configure_msi(dev, msg_addr, msg_data, num_vector_exponent)
+}
 
-    add_device(dev)?;
+/// Turns MSI and MSI-X off again, for a driver that gives up on the function.
+pub fn disable_msi(dev: &Device) {
+    if let Some(cap_addr) = find_capability(dev, CAPABILITY_MSI) {
+        let mut header = MSICapabilityHeader(read_conf_reg(dev, cap_addr));
+        header.set_msi_enable(false);
+        write_conf_reg(dev, cap_addr, header.data());
+    }
+    if let Some(cap_addr) = find_capability(dev, CAPABILITY_MSIX) {
+        let mut header = MSIXCapabilityHeader(read_conf_reg(dev, cap_addr));
+        header.set_msix_enable(false);
+        write_conf_reg(dev, cap_addr, header.0);
+    }
+}
 
-    if class_code.0 == 0x06 && class_code.1 == 0x04 {
-        let bus_numbers = read_bus_numbers(bus, device, function);
-        let secondary_bus = (bus_numbers >> 8) as u8;
-        return scan_bus(secondary_bus);
+/// Enables MSI-X with table entry `i` raising `vectors[i]` on the local APIC `apic_id`. The
+/// returned table masks and unmasks the entries later on.
+#[allow(dead_code)]
+pub fn configure_msix_fixed_destination(
+    dev: &Device,
+    apic_id: u8,
+    trigger_mode: MSITriggerMode,
+    delivery_mode: MSIDeliveryMode,
+    vectors: &[u8],
+) -> Result<MSIXTable> {
+    let cap_addr = find_capability(dev, CAPABILITY_MSIX).ok_or("no pci msi-x.")?;
+    let messages: Vec<_> = vectors
+        .iter()
+        .map(|&vector| msi_message(apic_id, trigger_mode, delivery_mode, vector))
+        .collect();
+    configure_msix_register(dev, cap_addr, &messages)
+}
+
+fn scan_function(
+    cache: CacheId,
+    bus: u8,
+    device: u8,
+    function: u8,
+    visited: &mut [bool; 256],
+) -> Result<()> {
+    let dev = Device::read(bus, device, function);
+    devices_mut().push(SlabBox::new(cache, dev)?);
+
+    if dev.is_bridge() {
+        let secondary_bus = read_bus_numbers(bus, device, function).get_bits(8..16) as u8;
+        // Firmware leaves the secondary bus of a bridge it did not configure at 0.
+        if secondary_bus != 0 {
+            scan_bus(cache, secondary_bus, visited)?;
+        }
     }
     Ok(())
 }
 
-fn scan_device(bus: u8, device: u8) -> Result<()> {
-    scan_function(bus, device, 0)?;
+fn scan_device(cache: CacheId, bus: u8, device: u8, visited: &mut [bool; 256]) -> Result<()> {
+    scan_function(cache, bus, device, 0, visited)?;
 
     if is_single_function_device(read_header_type(bus, device, 0)) {
         return Ok(());
     }
 
-    (0..8)
+    (1..8)
         .filter(|function| read_vendor_id(bus, device, *function) != 0xffff)
-        .try_for_each(|function| scan_function(bus, device, function))
+        .try_for_each(|function| scan_function(cache, bus, device, function, visited))
+}
+
+/// Memory mapped configuration space of a range of buses of PCI segment 0.
+///
+/// [PCI Express Base Specification 4.0, 7.2.2 PCI Express Enhanced Configuration Access Mechanism (ECAM)](https://pcisig.com/specifications)
+#[derive(Debug, Clone, Copy)]
+struct EcamRegion {
+    /// Virtual address of the configuration space of `start_bus`.
+    base: u64,
+    start_bus: u8,
+    end_bus: u8,
+}
+
+static mut ECAM_REGIONS: Vec<EcamRegion> = Vec::new();
+
+fn ecam_regions() -> &'static mut Vec<EcamRegion> {
+    #[allow(static_mut_refs)]
+    unsafe {
+        &mut ECAM_REGIONS
+    }
+}
+
+/// Selects ECAM for the buses the MCFG table covers. Other buses, and every bus if there is no
+/// MCFG, are reached through the legacy I/O ports.
+pub fn init() -> Result<()> {
+    let Some(mcfg) = acpi::mcfg() else {
+        return Ok(());
+    };
+    for entry in mcfg
+        .entries()
+        .filter(|entry| entry.segment == 0 && entry.start_bus <= entry.end_bus)
+    {
+        let buses = (entry.end_bus - entry.start_bus) as u64 + 1;
+        let start = entry.base_address + ((entry.start_bus as u64) << 20);
+        let base = paging::map_mmio(start, buses << 20)?;
+        ecam_regions().push(EcamRegion {
+            base,
+            start_bus: entry.start_bus,
+            end_bus: entry.end_bus,
+        });
+    }
+    Ok(())
+}
+
+fn ecam_address(bus: u8, device: u8, function: u8, reg_addr: u16) -> Option<u64> {
+    let region = ecam_regions()
+        .iter()
+        .find(|region| (region.start_bus..=region.end_bus).contains(&bus))?;
+    let offset = ((bus - region.start_bus) as u64) << 20
+        | (device as u64) << 15
+        | (function as u64) << 12
+        | (reg_addr & 0xffc) as u64;
+    Some(region.base + offset)
 }
 
 fn make_address(bus: u8, device: u8, function: u8, reg_addr: u8) -> u32 {
@@ -306,18 +668,7 @@ fn make_address(bus: u8, device: u8, function: u8, reg_addr: u8) -> u32 {
 }
 
 pub fn num_devices() -> usize {
-    unsafe { DEVICES }.iter().filter(|d| d.is_some()).count()
-}
-
-fn add_device(device: Device) -> Result<()> {
-    if num_devices() == unsafe { DEVICES }.len() {
-        return Err("full");
-    }
-
-    unsafe {
-        DEVICES[num_devices()] = Some(device);
-    };
-    Ok(())
+    devices_mut().len()
 }
 
 fn write_address(address: u32) {
@@ -332,72 +683,214 @@ fn read_data() -> u32 {
     x86::io_in32(CONFIG_DATA)
 }
 
-#[allow(dead_code)]
-pub fn read_vendor_id_from_device(dev: &Device) -> u16 {
-    read_device_id(dev.bus, dev.device, dev.function)
+/// Reads the dword at the 12-bit offset `reg_addr`. Without ECAM only the first 256 bytes
+/// are reachable, and the rest reads as all ones.
+fn read_config(bus: u8, device: u8, function: u8, reg_addr: u16) -> u32 {
+    if let Some(addr) = ecam_address(bus, device, function, reg_addr) {
+        return unsafe { (addr as *const u32).read_volatile() };
+    }
+    if reg_addr >= LEGACY_CONFIG_SPACE_SIZE {
+        return 0xffff_ffff;
+    }
+    write_address(make_address(bus, device, function, reg_addr as u8));
+    read_data()
 }
 
-fn read_vendor_id(bus: u8, device: u8, function: u8) -> u16 {
-    write_address(make_address(bus, device, function, 0x00));
-    read_data() as _
+/// Writes the dword at the 12-bit offset `reg_addr`. Without ECAM, writes beyond the first 256
+/// bytes are dropped.
+fn write_config(bus: u8, device: u8, function: u8, reg_addr: u16, value: u32) {
+    if let Some(addr) = ecam_address(bus, device, function, reg_addr) {
+        unsafe { (addr as *mut u32).write_volatile(value) };
+        return;
+    }
+    if reg_addr < LEGACY_CONFIG_SPACE_SIZE {
+        write_address(make_address(bus, device, function, reg_addr as u8));
+        write_data(value);
+    }
 }
 
-#[allow(dead_code)]
-fn read_device_id(bus: u8, device: u8, function: u8) -> u16 {
-    write_address(make_address(bus, device, function, 0x00));
-    (read_data() >> 16) as _
+fn read_vendor_id(bus: u8, device: u8, function: u8) -> u16 {
+    read_config(bus, device, function, 0x00) as _
 }
 
 fn read_header_type(bus: u8, device: u8, function: u8) -> u8 {
-    write_address(make_address(bus, device, function, 0x0c));
-    (read_data() >> 16) as _
-}
-
-pub fn read_class_code(bus: u8, device: u8, function: u8) -> (u8, u8, u8) {
-    write_address(make_address(bus, device, function, 0x08));
-    let reg = read_data();
-    ((reg >> 24) as _, (reg >> 16) as _, (reg >> 8) as _)
+    (read_config(bus, device, function, 0x0c) >> 16) as _
 }
 
 fn read_bus_numbers(bus: u8, device: u8, function: u8) -> u32 {
-    write_address(make_address(bus, device, function, 0x18));
-    read_data()
+    read_config(bus, device, function, 0x18)
 }
 
-fn read_conf_reg(dev: &Device, reg_addr: u8) -> u32 {
-    write_address(make_address(dev.bus, dev.device, dev.function, reg_addr));
-    read_data()
+fn read_conf_reg(dev: &Device, reg_addr: u16) -> u32 {
+    read_config(dev.bus, dev.device, dev.function, reg_addr)
 }
 
-fn write_conf_reg(dev: &Device, reg_addr: u8, value: u32) {
-    write_address(make_address(dev.bus, dev.device, dev.function, reg_addr));
-    write_data(value)
+fn write_conf_reg(dev: &Device, reg_addr: u16, value: u32) {
+    write_config(dev.bus, dev.device, dev.function, reg_addr, value)
 }
 
 fn is_single_function_device(header_type: u8) -> bool {
     (header_type & 0x80) == 0
 }
 
-pub fn read_bar(device: &Device, bar_index: u32) -> Result<u64> {
-    if bar_index >= 6 {
+/// A decoded Base Address Register with the size of the region behind it.
+///
+/// [PCI Local Bus Specification 3.0, 6.2.5.1 Address Maps](https://pcisig.com/specifications)
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Io {
+        port: u16,
+        size: u32,
+    },
+    Mem32 {
+        addr: u32,
+        size: u32,
+        prefetch: bool,
+    },
+    Mem64 {
+        addr: u64,
+        size: u64,
+        prefetch: bool,
+    },
+}
+
+#[allow(dead_code)]
+impl Bar {
+    /// Physical address of a memory BAR.
+    pub fn mem_address(&self) -> Option<u64> {
+        match *self {
+            Self::Io { .. } => None,
+            Self::Mem32 { addr, .. } => Some(addr as u64),
+            Self::Mem64 { addr, .. } => Some(addr),
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        match *self {
+            Self::Io { size, .. } | Self::Mem32 { size, .. } => size as u64,
+            Self::Mem64 { size, .. } => size,
+        }
+    }
+}
+
+/// Bits of the Command register.
+///
+/// [PCI Local Bus Specification 3.0, 6.2.2 Device Control](https://pcisig.com/specifications)
+pub struct Command;
+
+#[allow(dead_code)]
+impl Command {
+    pub const IO_SPACE: u16 = 1 << 0;
+    pub const MEMORY_SPACE: u16 = 1 << 1;
+    pub const BUS_MASTER: u16 = 1 << 2;
+    pub const INTERRUPT_DISABLE: u16 = 1 << 10;
+}
+
+const COMMAND_REGISTER: u16 = 0x04;
+
+pub fn read_command(dev: &Device) -> u16 {
+    read_conf_reg(dev, COMMAND_REGISTER) as u16
+}
+
+pub fn write_command(dev: &Device, command: u16) {
+    // The Status register shares the dword and clears the bits it is written ones to.
+    write_conf_reg(dev, COMMAND_REGISTER, command as u32);
+}
+
+fn set_command_bits(dev: &Device, bits: u16) {
+    write_command(dev, read_command(dev) | bits);
+}
+
+#[allow(dead_code)]
+pub fn enable_io_space(dev: &Device) {
+    set_command_bits(dev, Command::IO_SPACE);
+}
+
+pub fn enable_memory_space(dev: &Device) {
+    set_command_bits(dev, Command::MEMORY_SPACE);
+}
+
+/// Lets the device initiate DMA, which drivers need before handing it any buffer.
+pub fn enable_bus_master(dev: &Device) {
+    set_command_bits(dev, Command::BUS_MASTER);
+}
+
+/// Writes all ones to the BAR register at `addr` and returns the bits that stuck, restoring the
+/// register afterwards.
+fn read_bar_mask(device: &Device, addr: u16) -> u32 {
+    let bar = read_conf_reg(device, addr);
+    write_conf_reg(device, addr, !0);
+    let mask = read_conf_reg(device, addr);
+    write_conf_reg(device, addr, bar);
+    mask
+}
+
+/// Decodes the BAR at `bar_index` and probes its size. The BARs of bridges are 0 and 1.
+pub fn read_bar(device: &Device, bar_index: u32) -> Result<Bar> {
+    let num_bars = if device.is_bridge() { 2 } else { 6 };
+    if bar_index >= num_bars {
         return Err("index is out of range.");
     }
 
+    // The device must not decode the all-ones address while the BAR is being sized.
+    let command = read_command(device);
+    write_command(
+        device,
+        command & !(Command::IO_SPACE | Command::MEMORY_SPACE),
+    );
+    let bar = probe_bar(device, bar_index, bar_index + 1 < num_bars);
+    write_command(device, command);
+    bar
+}
+
+fn probe_bar(device: &Device, bar_index: u32, has_next: bool) -> Result<Bar> {
     let addr = calc_bar_address(bar_index);
     let bar = read_conf_reg(device, addr);
+    let mask = read_bar_mask(device, addr);
 
-    if (bar & 4) == 0 {
-        return Ok(bar as _);
+    if bar.get_bit(0) {
+        // x86 I/O ports are 16 bits wide, whatever the upper half of the BAR says.
+        let mask = mask as u16 & !0x3;
+        if mask == 0 {
+            return Err("bar is not implemented.");
+        }
+        return Ok(Bar::Io {
+            port: bar as u16 & !0x3,
+            size: (!mask).wrapping_add(1) as u32,
+        });
     }
 
-    if bar_index >= 5 {
-        return Err("index of out of range");
+    let prefetch = bar.get_bit(3);
+    match bar.get_bits(1..3) {
+        0 => {
+            let mask = mask & !0xf;
+            if mask == 0 {
+                return Err("bar is not implemented.");
+            }
+            Ok(Bar::Mem32 {
+                addr: bar & !0xf,
+                size: (!mask).wrapping_add(1),
+                prefetch,
+            })
+        }
+        2 if has_next => {
+            let upper = read_conf_reg(device, addr + 4);
+            let mask = (read_bar_mask(device, addr + 4) as u64) << 32 | (mask & !0xf) as u64;
+            if mask == 0 {
+                return Err("bar is not implemented.");
+            }
+            Ok(Bar::Mem64 {
+                addr: (upper as u64) << 32 | (bar & !0xf) as u64,
+                size: (!mask).wrapping_add(1),
+                prefetch,
+            })
+        }
+        2 => Err("index is out of range."),
+        _ => Err("unsupported bar type."),
     }
-
-    let bar_upper = read_conf_reg(device, addr + 4);
-    Ok(bar as u64 | ((bar_upper as u64) << 32))
 }
 
-fn calc_bar_address(bar_index: u32) -> u8 {
-    0x10u8 + 4 * (bar_index as u8)
+fn calc_bar_address(bar_index: u32) -> u16 {
+    0x10 + 4 * bar_index as u16
 }
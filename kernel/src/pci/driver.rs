@@ -0,0 +1,113 @@
+use super::{Device, devices};
+use crate::{Result, console::console};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// Which functions a driver supports.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Match {
+    /// Base class and subclass, and the programming interface unless it is `None`.
+    Class {
+        class: u8,
+        subclass: u8,
+        interface: Option<u8>,
+    },
+    /// Vendor ID, and the device ID unless it is `None`.
+    Id { vendor: u16, device: Option<u16> },
+}
+
+impl Match {
+    pub fn matches(&self, dev: &Device) -> bool {
+        match *self {
+            Self::Class {
+                class,
+                subclass,
+                interface,
+            } => {
+                (class, subclass) == (dev.class_code.0, dev.class_code.1)
+                    && interface.is_none_or(|interface| interface == dev.class_code.2)
+            }
+            Self::Id { vendor, device } => {
+                vendor == dev.vendor_id && device.is_none_or(|device| device == dev.device_id)
+            }
+        }
+    }
+}
+
+/// A driver for PCI functions. `probe` runs for each function one of `matches` selects and
+/// binds the driver to it unless it fails.
+pub struct Driver {
+    pub name: &'static str,
+    pub matches: &'static [Match],
+    pub probe: fn(&Device) -> Result<()>,
+}
+
+/// A function and the driver bound to it.
+#[derive(Clone, Copy)]
+struct Binding {
+    device: Device,
+    driver: &'static Driver,
+}
+
+static mut DRIVERS: Vec<&'static Driver> = Vec::new();
+static mut BINDINGS: Vec<Binding> = Vec::new();
+
+fn drivers() -> &'static mut Vec<&'static Driver> {
+    #[allow(static_mut_refs)]
+    unsafe {
+        &mut DRIVERS
+    }
+}
+
+/// Functions that have a driver, in the order they were bound.
+fn bindings() -> &'static mut Vec<Binding> {
+    #[allow(static_mut_refs)]
+    unsafe {
+        &mut BINDINGS
+    }
+}
+
+/// Whether `driver` is bound to any function.
+pub fn is_bound(driver: &Driver) -> bool {
+    bindings()
+        .iter()
+        .any(|binding| core::ptr::eq(binding.driver, driver))
+}
+
+pub fn register_driver(driver: &'static Driver) {
+    drivers().push(driver);
+}
+
+pub fn driver_of(dev: &Device) -> Option<&'static Driver> {
+    bindings()
+        .iter()
+        .find(|binding| binding.device.location() == dev.location())
+        .map(|binding| binding.driver)
+}
+
+/// Offers every function found by the last scan that has no driver yet to the registered
+/// drivers in the order they were registered. The first one whose probe succeeds is bound; a
+/// failed probe is reported and the next matching driver gets its turn.
+pub fn bind_drivers() {
+    for dev in devices().iter().filter(|dev| driver_of(dev).is_none()) {
+        let candidates = drivers()
+            .iter()
+            .filter(|driver| driver.matches.iter().any(|m| m.matches(dev)));
+        for &driver in candidates {
+            match (driver.probe)(dev) {
+                Ok(()) => {
+                    bindings().push(Binding {
+                        device: *dev,
+                        driver,
+                    });
+                    break;
+                }
+                Err(e) => println!(
+                    "pci: {:02x}:{:02x}.{}: {}: {}",
+                    dev.bus, dev.device, dev.function, driver.name, e
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,443 @@
+use crate::{
+    Result, acpi,
+    input::{self, InputEvent, KeyEvent},
+    keyboard, x86,
+};
+use bit_field::BitField;
+
+pub const KEYBOARD_IRQ: u8 = 1;
+pub const MOUSE_IRQ: u8 = 12;
+
+const DATA: u16 = 0x60;
+const STATUS: u16 = 0x64;
+const COMMAND: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: usize = 0;
+const STATUS_INPUT_FULL: usize = 1;
+
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const DISABLE_SECOND_PORT: u8 = 0xa7;
+const ENABLE_SECOND_PORT: u8 = 0xa8;
+const TEST_SECOND_PORT: u8 = 0xa9;
+const TEST_CONTROLLER: u8 = 0xaa;
+const TEST_FIRST_PORT: u8 = 0xab;
+const DISABLE_FIRST_PORT: u8 = 0xad;
+const ENABLE_FIRST_PORT: u8 = 0xae;
+const WRITE_SECOND_PORT: u8 = 0xd4;
+
+const CONFIG_FIRST_PORT_INTERRUPT: usize = 0;
+const CONFIG_SECOND_PORT_INTERRUPT: usize = 1;
+const CONFIG_SECOND_PORT_CLOCK_DISABLED: usize = 5;
+const CONFIG_TRANSLATION: usize = 6;
+
+const DEVICE_RESET: u8 = 0xff;
+const DEVICE_SET_DEFAULTS: u8 = 0xf6;
+const DEVICE_ENABLE_SCANNING: u8 = 0xf4;
+const DEVICE_SET_SAMPLE_RATE: u8 = 0xf3;
+const DEVICE_GET_ID: u8 = 0xf2;
+const DEVICE_ACK: u8 = 0xfa;
+const DEVICE_SELF_TEST_PASSED: u8 = 0xaa;
+
+const TIMEOUT: usize = 100_000;
+
+static mut PS2: Option<Ps2> = None;
+
+fn ps2() -> Option<&'static mut Ps2> {
+    #[allow(static_mut_refs)]
+    unsafe {
+        PS2.as_mut()
+    }
+}
+
+struct Ps2 {
+    has_mouse: bool,
+    keyboard: Keyboard,
+    mouse: Mouse,
+}
+
+fn wait_output() -> Result<()> {
+    for _ in 0..TIMEOUT {
+        if x86::io_in8(STATUS).get_bit(STATUS_OUTPUT_FULL) {
+            return Ok(());
+        }
+    }
+    Err("i8042 did not answer.")
+}
+
+fn wait_input() -> Result<()> {
+    for _ in 0..TIMEOUT {
+        if !x86::io_in8(STATUS).get_bit(STATUS_INPUT_FULL) {
+            return Ok(());
+        }
+    }
+    Err("i8042 input buffer stays full.")
+}
+
+fn command(command: u8) -> Result<()> {
+    wait_input()?;
+    x86::io_out8(COMMAND, command);
+    Ok(())
+}
+
+fn write(data: u8) -> Result<()> {
+    wait_input()?;
+    x86::io_out8(DATA, data);
+    Ok(())
+}
+
+fn read() -> Result<u8> {
+    wait_output()?;
+    Ok(x86::io_in8(DATA))
+}
+
+fn flush() {
+    while x86::io_in8(STATUS).get_bit(STATUS_OUTPUT_FULL) {
+        x86::io_in8(DATA);
+    }
+}
+
+fn read_config() -> Result<u8> {
+    command(READ_CONFIG)?;
+    read()
+}
+
+fn write_config(config: u8) -> Result<()> {
+    command(WRITE_CONFIG)?;
+    write(config)
+}
+
+/// Sends `data` to the device on the first port, or the second one if `second` is set,
+/// and waits for its acknowledgement.
+fn send_device(second: bool, data: u8) -> Result<()> {
+    if second {
+        command(WRITE_SECOND_PORT)?;
+    }
+    write(data)?;
+    match read()? {
+        DEVICE_ACK => Ok(()),
+        _ => Err("PS/2 device did not acknowledge."),
+    }
+}
+
+fn reset_device(second: bool) -> Result<()> {
+    send_device(second, DEVICE_RESET)?;
+    if read()? != DEVICE_SELF_TEST_PASSED {
+        return Err("PS/2 device failed its self test.");
+    }
+    // Mice follow the self test result with their device ID.
+    if second {
+        read()?;
+    }
+    Ok(())
+}
+
+/// Switches a mouse that understands the IntelliMouse sequence to 4-byte packets carrying
+/// wheel movement. Returns whether it did.
+fn enable_wheel() -> Result<bool> {
+    for rate in [200, 100, 80] {
+        send_device(true, DEVICE_SET_SAMPLE_RATE)?;
+        send_device(true, rate)?;
+    }
+    send_device(true, DEVICE_GET_ID)?;
+    Ok(read()? == 3)
+}
+
+/// Reads a byte the controller has ready. Called from the IRQ 1 and IRQ 12 handlers.
+pub fn read_data() -> u8 {
+    x86::io_in8(DATA)
+}
+
+/// [OSDev Wiki, "I8042" PS/2 Controller](https://wiki.osdev.org/I8042_PS/2_Controller)
+///
+/// Resets the controller and its devices and enables their interrupts. Route
+/// [`KEYBOARD_IRQ`] and [`MOUSE_IRQ`] before calling this. A missing mouse is not an error.
+pub fn init() -> Result<()> {
+    if acpi::fadt().is_some_and(|fadt| !fadt.has_8042()) {
+        return Err("no i8042 controller.");
+    }
+
+    command(DISABLE_FIRST_PORT)?;
+    command(DISABLE_SECOND_PORT)?;
+    flush();
+
+    let mut config = read_config()?;
+    config
+        .set_bit(CONFIG_FIRST_PORT_INTERRUPT, false)
+        .set_bit(CONFIG_SECOND_PORT_INTERRUPT, false);
+    write_config(config)?;
+
+    command(TEST_CONTROLLER)?;
+    if read()? != 0x55 {
+        return Err("i8042 failed its self test.");
+    }
+    // The self test may reset the controller.
+    write_config(config)?;
+
+    command(ENABLE_SECOND_PORT)?;
+    let mut has_mouse = !read_config()?.get_bit(CONFIG_SECOND_PORT_CLOCK_DISABLED);
+    command(DISABLE_SECOND_PORT)?;
+
+    command(TEST_FIRST_PORT)?;
+    if read()? != 0 {
+        return Err("PS/2 keyboard port failed its test.");
+    }
+    if has_mouse {
+        command(TEST_SECOND_PORT)?;
+        has_mouse = read()? == 0;
+    }
+
+    command(ENABLE_FIRST_PORT)?;
+    reset_device(false)?;
+    send_device(false, DEVICE_ENABLE_SCANNING)?;
+
+    if has_mouse {
+        command(ENABLE_SECOND_PORT)?;
+        has_mouse = reset_device(true)
+            .and_then(|_| send_device(true, DEVICE_SET_DEFAULTS))
+            .is_ok();
+    }
+    let has_wheel = has_mouse && enable_wheel().unwrap_or(false);
+    if has_mouse {
+        has_mouse = send_device(true, DEVICE_ENABLE_SCANNING).is_ok();
+    }
+    flush();
+
+    let mut config = read_config()?;
+    config
+        .set_bit(CONFIG_FIRST_PORT_INTERRUPT, true)
+        .set_bit(CONFIG_SECOND_PORT_INTERRUPT, has_mouse);
+    write_config(config)?;
+
+    let scan_code_set = if config.get_bit(CONFIG_TRANSLATION) {
+        ScanCodeSet::Set1
+    } else {
+        ScanCodeSet::Set2
+    };
+
+    unsafe {
+        PS2 = Some(Ps2 {
+            has_mouse,
+            keyboard: Keyboard::new(scan_code_set),
+            mouse: Mouse::new(if has_wheel { 4 } else { 3 }),
+        })
+    };
+    Ok(())
+}
+
+pub fn has_mouse() -> bool {
+    ps2().is_some_and(|ps2| ps2.has_mouse)
+}
+
+/// Feeds a byte read by the keyboard interrupt handler to the scan code decoder.
+pub fn process_keyboard_data(data: u8) {
+    if let Some(ps2) = ps2() {
+        ps2.keyboard.process(data);
+    }
+}
+
+/// Feeds a byte read by the mouse interrupt handler to the packet decoder.
+pub fn process_mouse_data(data: u8) {
+    if let Some(ps2) = ps2() {
+        ps2.mouse.process(data);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScanCodeSet {
+    /// What the controller produces when translation is enabled, which firmware usually leaves on.
+    Set1,
+    Set2,
+}
+
+struct Keyboard {
+    scan_code_set: ScanCodeSet,
+    extended: bool,
+    release: bool,
+    /// Bytes of a Pause sequence still to be swallowed.
+    skip: u8,
+    modifier: u8,
+}
+
+impl Keyboard {
+    const fn new(scan_code_set: ScanCodeSet) -> Self {
+        Self {
+            scan_code_set,
+            extended: false,
+            release: false,
+            skip: 0,
+            modifier: 0,
+        }
+    }
+
+    fn process(&mut self, data: u8) {
+        if self.skip > 0 {
+            self.skip -= 1;
+            return;
+        }
+
+        let scan_code = match (self.scan_code_set, data) {
+            (_, 0xe0) => {
+                self.extended = true;
+                return;
+            }
+            // Pause has no release code and is not reported.
+            (ScanCodeSet::Set1, 0xe1) => {
+                self.skip = 5;
+                return;
+            }
+            (ScanCodeSet::Set2, 0xe1) => {
+                self.skip = 7;
+                return;
+            }
+            (ScanCodeSet::Set2, 0xf0) => {
+                self.release = true;
+                return;
+            }
+            (ScanCodeSet::Set1, data) => {
+                self.release = data.get_bit(7);
+                data & 0x7f
+            }
+            (ScanCodeSet::Set2, data) => data,
+        };
+
+        let table = match (self.scan_code_set, self.extended) {
+            (ScanCodeSet::Set1, false) => SET1,
+            (ScanCodeSet::Set1, true) => SET1_EXTENDED,
+            (ScanCodeSet::Set2, false) => SET2,
+            (ScanCodeSet::Set2, true) => SET2_EXTENDED,
+        };
+        let press = !self.release;
+        self.extended = false;
+        self.release = false;
+
+        let Some(&(_, keycode)) = table.iter().find(|(code, _)| *code == scan_code) else {
+            return;
+        };
+
+        if let Some(bit) = keyboard::modifier_bit(keycode) {
+            if press {
+                self.modifier |= bit;
+            } else {
+                self.modifier &= !bit;
+            }
+        }
+
+        input::report(InputEvent::Key(KeyEvent::new(
+            self.modifier,
+            keycode,
+            press,
+        )));
+    }
+}
+
+/// [OSDev Wiki, PS/2 Mouse](https://wiki.osdev.org/PS/2_Mouse)
+struct Mouse {
+    packet: [u8; 4],
+    packet_len: usize,
+    len: usize,
+    buttons: u8,
+}
+
+impl Mouse {
+    const fn new(packet_len: usize) -> Self {
+        Self {
+            packet: [0; 4],
+            packet_len,
+            len: 0,
+            buttons: 0,
+        }
+    }
+
+    fn process(&mut self, data: u8) {
+        // Bit 3 of the first byte is always set; use it to get back in step after a lost byte.
+        if self.len == 0 && !data.get_bit(3) {
+            return;
+        }
+        self.packet[self.len] = data;
+        self.len += 1;
+        if self.len < self.packet_len {
+            return;
+        }
+        self.len = 0;
+
+        let [flags, x, y, z] = self.packet;
+        let buttons = flags.get_bits(0..3);
+        input::report_buttons(self.buttons, buttons);
+        self.buttons = buttons;
+
+        // Drop movement that overflowed.
+        if !flags.get_bit(6) && !flags.get_bit(7) {
+            let dx = x as i32 - if flags.get_bit(4) { 0x100 } else { 0 };
+            let dy = y as i32 - if flags.get_bit(5) { 0x100 } else { 0 };
+            // PS/2 counts Y upwards, the screen downwards.
+            if dx != 0 || dy != 0 {
+                input::report(InputEvent::RelativeMotion { dx, dy: -dy });
+            }
+        }
+
+        // The low nibble is a signed count of wheel notches towards the user.
+        let wheel = ((z << 4) as i8 >> 4) as i32;
+        if self.packet_len == 4 && wheel != 0 {
+            input::report(InputEvent::Wheel(-wheel));
+        }
+    }
+}
+
+/// Scan code set 1 make codes and the HID usage ID they map to.
+#[rustfmt::skip]
+const SET1: &[(u8, u8)] = &[
+    (0x01, 0x29), (0x02, 0x1e), (0x03, 0x1f), (0x04, 0x20), (0x05, 0x21), (0x06, 0x22),
+    (0x07, 0x23), (0x08, 0x24), (0x09, 0x25), (0x0a, 0x26), (0x0b, 0x27), (0x0c, 0x2d),
+    (0x0d, 0x2e), (0x0e, 0x2a), (0x0f, 0x2b), (0x10, 0x14), (0x11, 0x1a), (0x12, 0x08),
+    (0x13, 0x15), (0x14, 0x17), (0x15, 0x1c), (0x16, 0x18), (0x17, 0x0c), (0x18, 0x12),
+    (0x19, 0x13), (0x1a, 0x2f), (0x1b, 0x30), (0x1c, 0x28), (0x1d, 0xe0), (0x1e, 0x04),
+    (0x1f, 0x16), (0x20, 0x07), (0x21, 0x09), (0x22, 0x0a), (0x23, 0x0b), (0x24, 0x0d),
+    (0x25, 0x0e), (0x26, 0x0f), (0x27, 0x33), (0x28, 0x34), (0x29, 0x35), (0x2a, 0xe1),
+    (0x2b, 0x31), (0x2c, 0x1d), (0x2d, 0x1b), (0x2e, 0x06), (0x2f, 0x19), (0x30, 0x05),
+    (0x31, 0x11), (0x32, 0x10), (0x33, 0x36), (0x34, 0x37), (0x35, 0x38), (0x36, 0xe5),
+    (0x37, 0x55), (0x38, 0xe2), (0x39, 0x2c), (0x3a, 0x39), (0x3b, 0x3a), (0x3c, 0x3b),
+    (0x3d, 0x3c), (0x3e, 0x3d), (0x3f, 0x3e), (0x40, 0x3f), (0x41, 0x40), (0x42, 0x41),
+    (0x43, 0x42), (0x44, 0x43), (0x45, 0x53), (0x46, 0x47), (0x47, 0x5f), (0x48, 0x60),
+    (0x49, 0x61), (0x4a, 0x56), (0x4b, 0x5c), (0x4c, 0x5d), (0x4d, 0x5e), (0x4e, 0x57),
+    (0x4f, 0x59), (0x50, 0x5a), (0x51, 0x5b), (0x52, 0x62), (0x53, 0x63), (0x56, 0x64),
+    (0x57, 0x44), (0x58, 0x45), (0x70, 0x88), (0x73, 0x87), (0x79, 0x8a), (0x7b, 0x8b),
+    (0x7d, 0x89),
+];
+
+/// Scan code set 1 codes following 0xe0. The fake shifts around Print Screen are left out.
+#[rustfmt::skip]
+const SET1_EXTENDED: &[(u8, u8)] = &[
+    (0x1c, 0x58), (0x1d, 0xe4), (0x35, 0x54), (0x37, 0x46), (0x38, 0xe6), (0x47, 0x4a),
+    (0x48, 0x52), (0x49, 0x4b), (0x4b, 0x50), (0x4d, 0x4f), (0x4f, 0x4d), (0x50, 0x51),
+    (0x51, 0x4e), (0x52, 0x49), (0x53, 0x4c), (0x5b, 0xe3), (0x5c, 0xe7), (0x5d, 0x65),
+];
+
+/// Scan code set 2 make codes and the HID usage ID they map to.
+#[rustfmt::skip]
+const SET2: &[(u8, u8)] = &[
+    (0x01, 0x42), (0x03, 0x3e), (0x04, 0x3c), (0x05, 0x3a), (0x06, 0x3b), (0x07, 0x45),
+    (0x09, 0x43), (0x0a, 0x41), (0x0b, 0x3f), (0x0c, 0x3d), (0x0d, 0x2b), (0x0e, 0x35),
+    (0x11, 0xe2), (0x12, 0xe1), (0x13, 0x88), (0x14, 0xe0), (0x15, 0x14), (0x16, 0x1e),
+    (0x1a, 0x1d), (0x1b, 0x16), (0x1c, 0x04), (0x1d, 0x1a), (0x1e, 0x1f), (0x21, 0x06),
+    (0x22, 0x1b), (0x23, 0x07), (0x24, 0x08), (0x25, 0x21), (0x26, 0x20), (0x29, 0x2c),
+    (0x2a, 0x19), (0x2b, 0x09), (0x2c, 0x17), (0x2d, 0x15), (0x2e, 0x22), (0x31, 0x11),
+    (0x32, 0x05), (0x33, 0x0b), (0x34, 0x0a), (0x35, 0x1c), (0x36, 0x23), (0x3a, 0x10),
+    (0x3b, 0x0d), (0x3c, 0x18), (0x3d, 0x24), (0x3e, 0x25), (0x41, 0x36), (0x42, 0x0e),
+    (0x43, 0x0c), (0x44, 0x12), (0x45, 0x27), (0x46, 0x26), (0x49, 0x37), (0x4a, 0x38),
+    (0x4b, 0x0f), (0x4c, 0x33), (0x4d, 0x13), (0x4e, 0x2d), (0x51, 0x87), (0x52, 0x34),
+    (0x54, 0x2f), (0x55, 0x2e), (0x58, 0x39), (0x59, 0xe5), (0x5a, 0x28), (0x5b, 0x30),
+    (0x5d, 0x31), (0x61, 0x64), (0x64, 0x8a), (0x66, 0x2a), (0x67, 0x8b), (0x69, 0x59),
+    (0x6a, 0x89), (0x6b, 0x5c), (0x6c, 0x5f), (0x70, 0x62), (0x71, 0x63), (0x72, 0x5a),
+    (0x73, 0x5d), (0x74, 0x5e), (0x75, 0x60), (0x76, 0x29), (0x77, 0x53), (0x78, 0x44),
+    (0x79, 0x57), (0x7a, 0x5b), (0x7b, 0x56), (0x7c, 0x55), (0x7d, 0x61), (0x7e, 0x47),
+    (0x83, 0x40),
+];
+
+/// Scan code set 2 codes following 0xe0. The fake shifts around Print Screen are left out.
+#[rustfmt::skip]
+const SET2_EXTENDED: &[(u8, u8)] = &[
+    (0x11, 0xe6), (0x14, 0xe4), (0x1f, 0xe3), (0x27, 0xe7), (0x2f, 0x65), (0x4a, 0x54),
+    (0x5a, 0x58), (0x69, 0x4d), (0x6b, 0x50), (0x6c, 0x4a), (0x70, 0x49), (0x71, 0x4c),
+    (0x72, 0x51), (0x74, 0x4f), (0x75, 0x52), (0x7a, 0x4e), (0x7c, 0x46), (0x7d, 0x4b),
+];
@@ -1,68 +1,68 @@
-use crate::Result;
+use crate::{
+    Result,
+    slab::{self, CacheId, SlabBox},
+    x86,
+};
+use core::{alloc::Layout, ptr};
 
-#[derive(Debug)]
-pub struct ArrayQueue<T: Clone + Copy, const N: usize> {
-    data: [T; N],
-    read_pos: usize,
-    write_pos: usize,
-    count: usize,
+struct Node<T> {
+    value: T,
+    next: Option<SlabBox<Node<T>>>,
 }
 
-impl<T: Clone + Copy, const N: usize> ArrayQueue<T, N> {
-    const CAPACITY: usize = N;
+/// A FIFO whose entries come from a slab cache of its own, so that it is only bounded by
+/// memory and its usage shows up under `name` in the slab statistics.
+pub struct SlabQueue<T> {
+    name: &'static str,
+    cache: Option<CacheId>,
+    head: Option<SlabBox<Node<T>>>,
+    tail: *mut Node<T>,
+}
 
-    pub const fn new() -> Self {
+impl<T> SlabQueue<T> {
+    /// The cache is created by the first [`SlabQueue::push`].
+    pub const fn new(name: &'static str) -> Self {
         Self {
-            data: unsafe { core::mem::zeroed() },
-            read_pos: 0,
-            write_pos: 0,
-            count: 0,
+            name,
+            cache: None,
+            head: None,
+            tail: ptr::null_mut(),
         }
     }
 
+    /// Interrupt handlers push too, so this runs with interrupts disabled.
     pub fn push(&mut self, value: T) -> Result<()> {
-        if self.count == Self::CAPACITY {
-            return Err("queue is full.");
-        }
-
-        self.data[self.write_pos] = value;
-        self.count += 1;
-        self.write_pos += 1;
-        if self.write_pos == Self::CAPACITY {
-            self.write_pos = 0;
-        }
-        Ok(())
+        x86::without_interrupts(|| self.push_node(value))
     }
 
-    pub fn pop(&mut self) -> Result<()> {
-        if self.count == 0 {
-            return Err("queue is empty.");
-        }
+    pub fn pop(&mut self) -> Option<T> {
+        x86::without_interrupts(|| self.pop_node())
+    }
 
-        self.count -= 1;
-        self.read_pos += 1;
-        if self.read_pos == Self::CAPACITY {
-            self.read_pos = 0;
+    fn push_node(&mut self, value: T) -> Result<()> {
+        let cache = match self.cache {
+            Some(cache) => cache,
+            None => *self
+                .cache
+                .insert(slab::create_cache(self.name, Layout::new::<Node<T>>())?),
+        };
+        let mut node = SlabBox::new(cache, Node { value, next: None })?;
+        let tail = &mut *node as *mut Node<T>;
+        if self.tail.is_null() {
+            self.head = Some(node);
+        } else {
+            unsafe { (*self.tail).next = Some(node) };
         }
+        self.tail = tail;
         Ok(())
     }
 
-    pub const fn front(&self) -> &T {
-        &self.data[self.read_pos]
-    }
-
-    pub const fn count(&self) -> usize {
-        self.count
-    }
-
-    #[allow(dead_code)]
-    pub const fn capasity(&self) -> usize {
-        Self::CAPACITY
-    }
-}
-
-impl<T: Clone + Copy, const N: usize> Default for ArrayQueue<T, N> {
-    fn default() -> Self {
-        Self::new()
+    fn pop_node(&mut self) -> Option<T> {
+        let Node { value, next } = SlabBox::into_inner(self.head.take()?);
+        self.head = next;
+        if self.head.is_none() {
+            self.tail = ptr::null_mut();
+        }
+        Some(value)
     }
 }
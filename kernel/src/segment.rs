@@ -1,7 +1,48 @@
-use crate::{DescriptorType, x86};
+use crate::{DescriptorType, Result, memory_manager::BYTES_PER_FRAME, stack, x86};
 use bit_field::BitField;
 
-static mut GDT: [SegmentDescriptor; 3] = [SegmentDescriptor::new(0); 3];
+const GDT_ENTRIES: usize = 5;
+/// The TSS descriptor is 16 bytes wide and takes up entries 3 and 4.
+const TSS_INDEX: usize = 3;
+pub const KERNEL_TSS: u16 = (TSS_INDEX as u16) << 3;
+
+/// IST slots (1-based, as encoded in IDT entries) used by exceptions that must not run on a
+/// possibly broken stack.
+pub const IST_DOUBLE_FAULT: u16 = 1;
+pub const IST_NMI: u16 = 2;
+pub const IST_MACHINE_CHECK: u16 = 3;
+const INTERRUPT_STACK_COUNT: usize = 3;
+const INTERRUPT_STACK_SIZE: usize = 4096 * 4;
+
+#[repr(align(16))]
+struct InterruptStack([u8; INTERRUPT_STACK_SIZE]);
+
+static mut INTERRUPT_STACKS: [InterruptStack; INTERRUPT_STACK_COUNT] =
+    [const { InterruptStack([0; INTERRUPT_STACK_SIZE]) }; INTERRUPT_STACK_COUNT];
+
+/// [Intel SDM Vol.3 8.7 Task Management in 64-bit Mode](https://www.intel.com/content/www/us/en/developer/articles/technical/intel-sdm.html)
+#[repr(C, packed)]
+pub struct TaskStateSegment {
+    _reserved0: u32,
+    rsp: [u64; 3],
+    _reserved1: u64,
+    ist: [u64; 7],
+    _reserved2: u64,
+    _reserved3: u16,
+    iomap_base: u16,
+}
+
+static mut TSS: TaskStateSegment = TaskStateSegment {
+    _reserved0: 0,
+    rsp: [0; 3],
+    _reserved1: 0,
+    ist: [0; 7],
+    _reserved2: 0,
+    _reserved3: 0,
+    iomap_base: size_of::<TaskStateSegment>() as u16,
+};
+
+static mut GDT: [SegmentDescriptor; GDT_ENTRIES] = [SegmentDescriptor::new(0); GDT_ENTRIES];
 fn gdt() -> &'static mut [SegmentDescriptor] {
     #[allow(static_mut_refs)]
     unsafe {
@@ -80,11 +121,59 @@ pub fn setup_segments() {
     gdt()[0].set_data(0);
     set_code_segment(&mut gdt()[1], DescriptorType::ExecuteRead(), 0, 0, 0xfffff);
     set_data_segment(&mut gdt()[2], DescriptorType::ReadWrite(), 0, 0, 0xfffff);
+
+    setup_interrupt_stacks();
+    #[allow(static_mut_refs)]
+    let tss_base = unsafe { &TSS as *const TaskStateSegment as u64 };
+    set_tss_segment(tss_base, (size_of::<TaskStateSegment>() - 1) as u32);
+
     let param = x86::GdtParam {
-        limit: (size_of::<[SegmentDescriptor; 3]>() - 1) as u16,
+        limit: (size_of::<[SegmentDescriptor; GDT_ENTRIES]>() - 1) as u16,
         base: &gdt()[0] as *const SegmentDescriptor as usize,
     };
     x86::load_gdt(&param);
+    x86::load_tr(KERNEL_TSS);
+}
+
+fn setup_interrupt_stacks() {
+    (0..INTERRUPT_STACK_COUNT).for_each(|i| {
+        #[allow(static_mut_refs)]
+        let stack_end = unsafe { INTERRUPT_STACKS[i].0.as_ptr_range().end.addr() as u64 };
+        unsafe { TSS.ist[i] = stack_end };
+    });
+}
+
+/// Replaces the static IST stacks with ones from [`stack::allocate`] once paging is up, so that
+/// an overflow hits a guard page instead of whatever lies below the static stacks.
+pub fn setup_guarded_interrupt_stacks() -> Result<()> {
+    const OWNERS: [&str; INTERRUPT_STACK_COUNT] = [
+        "double fault handler",
+        "NMI handler",
+        "machine check handler",
+    ];
+    for (i, owner) in OWNERS.into_iter().enumerate() {
+        let stack_end = stack::allocate(owner, INTERRUPT_STACK_SIZE / BYTES_PER_FRAME)?;
+        unsafe { TSS.ist[i] = stack_end };
+    }
+    Ok(())
+}
+
+fn set_tss_segment(base: u64, limit: u32) {
+    let desc = &mut gdt()[TSS_INDEX];
+    desc.set_data(0);
+    desc.set_base_low((base & 0xffff) as _);
+    desc.set_base_middle(((base >> 16) & 0xff) as _);
+    desc.set_base_high(((base >> 24) & 0xff) as _);
+
+    desc.set_limit_low((limit & 0xffff) as _);
+    desc.set_limit_high(((limit >> 16) & 0xf) as _);
+
+    desc.set_type(DescriptorType::TssAvailable());
+    desc.set_system_segment(false);
+    desc.set_descriptor_privilege_level(0);
+    desc.set_present(true);
+
+    gdt()[TSS_INDEX + 1].set_data(base >> 32);
 }
 
 fn set_code_segment(
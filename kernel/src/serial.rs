@@ -0,0 +1,67 @@
+use crate::x86;
+use core::fmt;
+
+const COM1: u16 = 0x3f8;
+
+pub static mut SERIAL: Option<SerialPort> = None;
+
+/// Sets up COM1 as 115200 8N1. The port is only used if it passes a loopback test.
+pub fn init() {
+    let port = SerialPort(COM1);
+    if port.initialize() {
+        unsafe { SERIAL = Some(port) };
+    }
+}
+
+pub fn serial() -> Option<&'static mut SerialPort> {
+    #[allow(static_mut_refs)]
+    unsafe {
+        SERIAL.as_mut()
+    }
+}
+
+pub struct SerialPort(u16);
+
+impl SerialPort {
+    const DATA: u16 = 0;
+    const INTERRUPT_ENABLE: u16 = 1;
+    const FIFO_CONTROL: u16 = 2;
+    const LINE_CONTROL: u16 = 3;
+    const MODEM_CONTROL: u16 = 4;
+    const LINE_STATUS: u16 = 5;
+
+    fn initialize(&self) -> bool {
+        x86::io_out8(self.0 + Self::INTERRUPT_ENABLE, 0x00);
+        x86::io_out8(self.0 + Self::LINE_CONTROL, 0x80); // DLAB
+        x86::io_out8(self.0 + Self::DATA, 0x01); // divisor 1: 115200 baud
+        x86::io_out8(self.0 + Self::INTERRUPT_ENABLE, 0x00);
+        x86::io_out8(self.0 + Self::LINE_CONTROL, 0x03); // 8N1
+        x86::io_out8(self.0 + Self::FIFO_CONTROL, 0xc7);
+        x86::io_out8(self.0 + Self::MODEM_CONTROL, 0x1e); // loopback
+
+        x86::io_out8(self.0 + Self::DATA, 0xae);
+        if x86::io_in8(self.0 + Self::DATA) != 0xae {
+            return false;
+        }
+
+        x86::io_out8(self.0 + Self::MODEM_CONTROL, 0x0f);
+        true
+    }
+
+    fn write_byte(&self, b: u8) {
+        while x86::io_in8(self.0 + Self::LINE_STATUS) & 0x20 == 0 {}
+        x86::io_out8(self.0 + Self::DATA, b);
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|b| {
+            if b == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(b);
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,296 @@
+use crate::{
+    Result,
+    memory_manager::{BYTES_PER_FRAME, FrameID, memory_manager},
+    x86,
+};
+use core::{
+    alloc::Layout,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+};
+
+/// Object sizes served by the size-class caches backing the global allocator.
+/// Anything larger goes to the general heap.
+const SIZE_CLASSES: [usize; 7] = [16, 32, 64, 128, 256, 512, 1024];
+const MAX_CACHES: usize = 32;
+
+static mut CACHES: [Option<SlabCache>; MAX_CACHES] = [const { None }; MAX_CACHES];
+fn caches() -> &'static mut [Option<SlabCache>; MAX_CACHES] {
+    #[allow(static_mut_refs)]
+    unsafe {
+        &mut CACHES
+    }
+}
+
+pub fn init() {
+    const NAMES: [&str; SIZE_CLASSES.len()] = [
+        "size-16",
+        "size-32",
+        "size-64",
+        "size-128",
+        "size-256",
+        "size-512",
+        "size-1024",
+    ];
+    SIZE_CLASSES
+        .iter()
+        .zip(NAMES)
+        .enumerate()
+        .for_each(|(i, (size, name))| caches()[i] = Some(SlabCache::new(name, *size)));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheId(usize);
+
+/// Returns the size-class cache that can hold an object of `layout`, if any.
+pub fn size_class(layout: &Layout) -> Option<CacheId> {
+    let size = layout.size().max(layout.align());
+    SIZE_CLASSES
+        .iter()
+        .position(|class| size <= *class)
+        .map(CacheId)
+}
+
+/// Creates a dedicated cache so that a subsystem's objects are accounted separately.
+pub fn create_cache(name: &'static str, layout: Layout) -> Result<CacheId> {
+    let object_size = layout
+        .size()
+        .max(layout.align())
+        .max(size_of::<FreeObject>())
+        .next_power_of_two();
+    if object_size > SIZE_CLASSES[SIZE_CLASSES.len() - 1] {
+        return Err("object is too large for a slab cache.");
+    }
+
+    x86::without_interrupts(|| {
+        let (id, slot) = caches()
+            .iter_mut()
+            .enumerate()
+            .find(|(_, c)| c.is_none())
+            .ok_or("too many slab caches.")?;
+        *slot = Some(SlabCache::new(name, object_size));
+        Ok(CacheId(id))
+    })
+}
+
+pub fn allocate(id: CacheId) -> Result<*mut u8> {
+    x86::without_interrupts(|| {
+        caches()[id.0]
+            .as_mut()
+            .ok_or("no such slab cache.")?
+            .allocate(id)
+    })
+}
+
+/// # Safety
+/// `p` must have been returned by [`allocate`] and not freed since.
+pub unsafe fn free(p: *mut u8) {
+    x86::without_interrupts(|| {
+        let slab = p.with_addr(p.addr() & !(BYTES_PER_FRAME - 1)) as *mut Slab;
+        let id = unsafe { (*slab).cache };
+        if let Some(cache) = caches()[id.0].as_mut() {
+            unsafe { cache.free(slab, p) };
+        }
+    });
+}
+
+/// A `T` in a cache made by [`create_cache`], which gets the object back on drop.
+pub struct SlabBox<T> {
+    object: NonNull<T>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> SlabBox<T> {
+    pub fn new(cache: CacheId, value: T) -> Result<Self> {
+        let object_size = x86::without_interrupts(|| {
+            caches()[cache.0]
+                .as_ref()
+                .map(|cache| cache.object_size)
+                .ok_or("no such slab cache.")
+        })?;
+        if size_of::<T>().max(align_of::<T>()) > object_size {
+            return Err("object does not fit the slab cache.");
+        }
+        let object = NonNull::new(allocate(cache)? as *mut T).ok_or("slab cache is broken.")?;
+        unsafe { object.write(value) };
+        Ok(Self {
+            object,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn into_inner(this: Self) -> T {
+        let value = unsafe { this.object.read() };
+        unsafe { free(this.object.as_ptr() as *mut u8) };
+        core::mem::forget(this);
+        value
+    }
+}
+
+impl<T> Deref for SlabBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.object.as_ref() }
+    }
+}
+
+impl<T> DerefMut for SlabBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.object.as_mut() }
+    }
+}
+
+impl<T> Drop for SlabBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.object.as_ptr());
+            free(self.object.as_ptr() as *mut u8);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub name: &'static str,
+    pub object_size: usize,
+    pub slabs: usize,
+    pub objects_in_use: usize,
+    pub objects_total: usize,
+}
+
+impl CacheStats {
+    /// Percentage of objects in use over all objects the cache's slabs can hold.
+    pub fn utilization(&self) -> usize {
+        if self.objects_total == 0 {
+            return 0;
+        }
+        self.objects_in_use * 100 / self.objects_total
+    }
+}
+
+pub fn stats() -> impl Iterator<Item = CacheStats> {
+    caches().iter().flatten().map(|cache| CacheStats {
+        name: cache.name,
+        object_size: cache.object_size,
+        slabs: cache.slabs,
+        objects_in_use: cache.objects_in_use,
+        objects_total: cache.slabs * cache.objects_per_slab(),
+    })
+}
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// Header at the start of every slab frame. Objects follow it at `SlabCache::first_object_offset`.
+#[repr(C)]
+struct Slab {
+    cache: CacheId,
+    next: *mut Slab,
+    free: *mut FreeObject,
+    in_use: usize,
+}
+
+struct SlabCache {
+    name: &'static str,
+    object_size: usize,
+    head: *mut Slab,
+    slabs: usize,
+    objects_in_use: usize,
+}
+
+impl SlabCache {
+    const fn new(name: &'static str, object_size: usize) -> Self {
+        Self {
+            name,
+            object_size,
+            head: ptr::null_mut(),
+            slabs: 0,
+            objects_in_use: 0,
+        }
+    }
+
+    const fn first_object_offset(&self) -> usize {
+        size_of::<Slab>().next_multiple_of(self.object_size)
+    }
+
+    const fn objects_per_slab(&self) -> usize {
+        (BYTES_PER_FRAME - self.first_object_offset()) / self.object_size
+    }
+
+    fn allocate(&mut self, id: CacheId) -> Result<*mut u8> {
+        let mut slab = self.head;
+        while !slab.is_null() && unsafe { (*slab).free.is_null() } {
+            slab = unsafe { (*slab).next };
+        }
+        if slab.is_null() {
+            slab = self.new_slab(id)?;
+        }
+
+        let slab = unsafe { &mut *slab };
+        let object = slab.free;
+        slab.free = unsafe { (*object).next };
+        slab.in_use += 1;
+        self.objects_in_use += 1;
+        Ok(object as *mut u8)
+    }
+
+    fn new_slab(&mut self, id: CacheId) -> Result<*mut Slab> {
+        let mut frame = memory_manager().allocate(1)?;
+        let base = frame.frame().as_mut_ptr();
+
+        let mut free = ptr::null_mut();
+        (0..self.objects_per_slab()).rev().for_each(|i| {
+            let object = unsafe { base.add(self.first_object_offset() + i * self.object_size) }
+                as *mut FreeObject;
+            unsafe { object.write(FreeObject { next: free }) };
+            free = object;
+        });
+
+        let slab = base as *mut Slab;
+        unsafe {
+            slab.write(Slab {
+                cache: id,
+                next: self.head,
+                free,
+                in_use: 0,
+            })
+        };
+        self.head = slab;
+        self.slabs += 1;
+        Ok(slab)
+    }
+
+    /// # Safety
+    /// `slab` must belong to this cache and `p` must be an allocated object inside it.
+    unsafe fn free(&mut self, slab: *mut Slab, p: *mut u8) {
+        let object = p as *mut FreeObject;
+        unsafe {
+            object.write(FreeObject { next: (*slab).free });
+            (*slab).free = object;
+            (*slab).in_use -= 1;
+        }
+        self.objects_in_use -= 1;
+
+        // Keep the last slab around so a single alloc/free pair does not bounce frames.
+        if unsafe { (*slab).in_use } == 0 && self.slabs > 1 {
+            self.release_slab(slab);
+        }
+    }
+
+    fn release_slab(&mut self, slab: *mut Slab) {
+        if self.head == slab {
+            self.head = unsafe { (*slab).next };
+        } else {
+            let mut prev = self.head;
+            while unsafe { (*prev).next } != slab {
+                prev = unsafe { (*prev).next };
+            }
+            unsafe { (*prev).next = (*slab).next };
+        }
+        self.slabs -= 1;
+        _ = memory_manager().free(FrameID::from_ptr(slab as *const u8), 1);
+    }
+}
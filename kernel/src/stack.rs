@@ -0,0 +1,57 @@
+use crate::{
+    Result,
+    memory_manager::{BYTES_PER_FRAME, FrameID, memory_manager},
+    paging::{self, PageFlags},
+};
+use alloc::vec::Vec;
+
+/// Stacks handed out by [`allocate`] live in their own PML4 slot, each one preceded by an
+/// unmapped guard page.
+const STACK_AREA_BASE: u64 = 0xffff_ff00_0000_0000;
+pub const GUARD_PAGE_SIZE: usize = BYTES_PER_FRAME;
+
+static mut NEXT_STACK: u64 = STACK_AREA_BASE;
+
+static mut GUARD_PAGES: Vec<GuardPage> = Vec::new();
+fn guard_pages() -> &'static mut Vec<GuardPage> {
+    #[allow(static_mut_refs)]
+    unsafe {
+        &mut GUARD_PAGES
+    }
+}
+
+struct GuardPage {
+    owner: &'static str,
+    addr: u64,
+}
+
+/// Unmaps the page at `addr`, which must lie directly below a stack owned by `owner`.
+pub fn protect_guard_page(owner: &'static str, addr: u64) -> Result<()> {
+    paging::unmap(addr)?;
+    guard_pages().push(GuardPage { owner, addr });
+    Ok(())
+}
+
+/// Allocates a stack of `num_pages` pages with an unmapped guard page beneath it and returns
+/// its top address.
+pub fn allocate(owner: &'static str, num_pages: usize) -> Result<u64> {
+    let frames = memory_manager().allocate(num_pages)?;
+    (0..num_pages).for_each(|i| FrameID::new(frames.id() + i).frame().fill(0));
+
+    let guard = unsafe { NEXT_STACK };
+    let bottom = guard + GUARD_PAGE_SIZE as u64;
+    let size = (num_pages * BYTES_PER_FRAME) as u64;
+    paging::map_range(bottom, frames.phys_addr(), size, PageFlags::KernelData())?;
+    unsafe { NEXT_STACK = bottom + size };
+
+    guard_pages().push(GuardPage { owner, addr: guard });
+    Ok(bottom + size)
+}
+
+/// Returns the owner of the stack whose guard page contains `addr`.
+pub fn guard_page_owner(addr: u64) -> Option<&'static str> {
+    guard_pages()
+        .iter()
+        .find(|page| (page.addr..page.addr + GUARD_PAGE_SIZE as u64).contains(&addr))
+        .map(|page| page.owner)
+}
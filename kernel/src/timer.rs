@@ -0,0 +1,125 @@
+use crate::{
+    Result,
+    local_apic::{self, CURRENT_COUNT, DIVIDE_CONFIG, INITIAL_COUNT, LVT_TIMER},
+    x86,
+};
+use bit_field::BitField;
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+const PIT_FREQUENCY: u64 = 1_193_182;
+const PIT_CHANNEL2_DATA: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+/// Keyboard controller port B. Bit 0 gates PIT channel 2, bit 5 reflects its output.
+const PIT_CHANNEL2_GATE: u16 = 0x61;
+const CALIBRATION_MS: u64 = 10;
+
+/// Divide the bus clock by 1.
+const DIVIDE_BY_1: u32 = 0b1011;
+const LVT_MASKED: usize = 16;
+const LVT_PERIODIC: usize = 17;
+
+static TICK: AtomicU64 = AtomicU64::new(0);
+/// Earliest tick passed to [`wake_at`] that has not come yet, or `u64::MAX`.
+static WAKEUP: AtomicU64 = AtomicU64::new(u64::MAX);
+static mut FREQUENCY: u64 = 0;
+static mut LOCAL_APIC_TIMER_FREQUENCY: u64 = 0;
+
+/// Calibrates the local APIC timer and starts it in periodic mode, raising `vector`
+/// `frequency` times per second.
+pub fn init(frequency: u64, vector: u8) -> Result<()> {
+    let apic_frequency = calibrate()?;
+    unsafe {
+        LOCAL_APIC_TIMER_FREQUENCY = apic_frequency;
+        FREQUENCY = frequency;
+    }
+
+    let mut lvt = vector as u32;
+    lvt.set_bit(LVT_MASKED, false).set_bit(LVT_PERIODIC, true);
+    local_apic::write(DIVIDE_CONFIG, DIVIDE_BY_1);
+    local_apic::write(LVT_TIMER, lvt);
+    local_apic::write(INITIAL_COUNT, (apic_frequency / frequency) as u32);
+    Ok(())
+}
+
+/// Counts how far the local APIC timer runs down while PIT channel 2 counts out
+/// `CALIBRATION_MS` milliseconds, and returns the timer frequency in Hz. Fails if the PIT never
+/// finishes before the local APIC timer runs out, as without a gateable channel 2.
+fn calibrate() -> Result<u64> {
+    let mut lvt = 0;
+    lvt.set_bit(LVT_MASKED, true);
+    local_apic::write(DIVIDE_CONFIG, DIVIDE_BY_1);
+    local_apic::write(LVT_TIMER, lvt);
+
+    // Channel 2, lobyte/hibyte, mode 0 (interrupt on terminal count), binary.
+    let count = PIT_FREQUENCY * CALIBRATION_MS / 1000;
+    let gate = x86::io_in8(PIT_CHANNEL2_GATE) & !0x02;
+    x86::io_out8(PIT_CHANNEL2_GATE, gate & !0x01);
+    x86::io_out8(PIT_COMMAND, 0b1011_0000);
+    x86::io_out8(PIT_CHANNEL2_DATA, count as u8);
+    x86::io_out8(PIT_CHANNEL2_DATA, (count >> 8) as u8);
+
+    x86::io_out8(PIT_CHANNEL2_GATE, gate | 0x01);
+    local_apic::write(INITIAL_COUNT, u32::MAX);
+    // The one-shot count stops at 0, seconds after the PIT should have finished.
+    while x86::io_in8(PIT_CHANNEL2_GATE) & 0x20 == 0 && local_apic::read(CURRENT_COUNT) != 0 {}
+    let remaining = local_apic::read(CURRENT_COUNT);
+    local_apic::write(INITIAL_COUNT, 0);
+    x86::io_out8(PIT_CHANNEL2_GATE, gate & !0x01);
+
+    if remaining == 0 {
+        return Err("PIT channel 2 did not count out.");
+    }
+    Ok((u32::MAX - remaining) as u64 * 1000 / CALIBRATION_MS)
+}
+
+/// Called from the timer interrupt handler. Returns whether a tick passed to [`wake_at`] has
+/// come, in which case the handler wakes the main loop.
+pub fn on_interrupt() -> bool {
+    let tick = TICK.fetch_add(1, Ordering::Relaxed) + 1;
+    WAKEUP
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |wakeup| {
+            (wakeup <= tick).then_some(u64::MAX)
+        })
+        .is_ok()
+}
+
+/// Number of timer interrupts since [`init`]. Never decreases.
+pub fn ticks() -> u64 {
+    TICK.load(Ordering::Relaxed)
+}
+
+#[allow(dead_code)]
+pub fn frequency() -> u64 {
+    unsafe { FREQUENCY }
+}
+
+#[allow(dead_code)]
+pub fn uptime() -> Duration {
+    let frequency = unsafe { FREQUENCY };
+    if frequency == 0 {
+        return Duration::ZERO;
+    }
+    let ticks = ticks();
+    Duration::from_secs(ticks / frequency)
+        + Duration::from_nanos((ticks % frequency) * 1_000_000_000 / frequency)
+}
+
+#[allow(dead_code)]
+pub fn local_apic_timer_frequency() -> u64 {
+    unsafe { LOCAL_APIC_TIMER_FREQUENCY }
+}
+
+/// Returns the tick that comes at least `ms` milliseconds from now.
+pub fn deadline_ms(ms: u64) -> u64 {
+    // One tick more, since the current tick may be about to end.
+    ticks() + (ms * frequency()).div_ceil(1000) + 1
+}
+
+/// Asks the timer interrupt to wake the main loop once `tick` has come. A wakeup happens once;
+/// whoever is still waiting after it asks again.
+pub fn wake_at(tick: u64) {
+    WAKEUP.fetch_min(tick, Ordering::Relaxed);
+}
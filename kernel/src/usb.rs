@@ -1,89 +1,47 @@
 use crate::Result;
 
-unsafe extern "C" {
-    #[link_name = "\u{1}_ZN3usb4xhci10ControllerC1Em"]
-    fn usb_xhci_Controller_Controller(this: *mut UsbXhciController, mmio_base: usize);
-
-    fn UsbXhciController_initialize(c_impl: *mut UsbXhciController) -> i32;
-    fn UsbXhciController_run(c_impl: *mut UsbXhciController) -> i32;
-    fn UsbXhciController_configurePort(c_impl: *mut UsbXhciController);
-    fn UsbXhciController_ProcessXhcEvent(c_impl: *mut UsbXhciController) -> i32;
-    fn UsbXhciController_PrimaryEventRing_HasFront(c_impl: *mut UsbXhciController) -> bool;
-    fn RegisterMouseObserver(cb: MouseObserver);
-}
-
-pub static mut XHCI: Option<XhciController> = None;
-
-pub fn init(mmio_base: usize) {
-    let xhci = XhciController::new(mmio_base);
+mod classdriver;
+mod descriptor;
+mod device;
+mod memory;
+mod xhci;
+
+pub use xhci::Controller as XhciController;
+
+static mut XHCI: Option<XhciController> = None;
+/// Set while [`with_xhc`] lends the controller out.
+static mut XHCI_IN_USE: bool = false;
+
+/// Initializes and starts the xHC at `mmio_base`. [`with_xhc`] and [`is_initialized`] only see
+/// it once it runs.
+pub fn init(mmio_base: u64) -> Result<()> {
+    if is_initialized() {
+        return Err("only one xHC is supported.");
+    }
+    let mut xhci = XhciController::new(mmio_base)?;
+    xhci.initialize()?;
+    xhci.run()?;
     unsafe { XHCI = Some(xhci) };
+    Ok(())
 }
 
-pub fn xhc() -> &'static mut XhciController {
+pub fn is_initialized() -> bool {
     #[allow(static_mut_refs)]
     unsafe {
-        XHCI.as_mut().unwrap()
+        XHCI.is_some()
     }
 }
 
-#[repr(C)]
-#[derive(Debug)]
-struct UsbXhciController {
-    _padding: [u8; 128],
-}
-
-impl UsbXhciController {
-    fn new(mmio_base: usize) -> Self {
-        let mut __bindgen_tmp = ::core::mem::MaybeUninit::uninit();
-        unsafe { usb_xhci_Controller_Controller(__bindgen_tmp.as_mut_ptr(), mmio_base) };
-        unsafe { __bindgen_tmp.assume_init() }
-    }
-}
-
-type MouseObserver = extern "C" fn(i8, i8);
-
-pub struct XhciController {
-    c_impl: UsbXhciController,
-}
-
-unsafe impl Send for XhciController {}
-
-impl XhciController {
-    pub fn new(mmio_base: usize) -> Self {
-        Self {
-            c_impl: UsbXhciController::new(mmio_base),
-        }
+/// Runs `f` on the xHC. Fails rather than handing out the controller a second time while it is
+/// in use, as when a class driver callback reaches a block device on the same controller.
+pub fn with_xhc<R>(f: impl FnOnce(&mut XhciController) -> Result<R>) -> Result<R> {
+    if unsafe { XHCI_IN_USE } {
+        return Err("xHC is in use.");
     }
-
-    pub fn initialize(&mut self) -> Result<()> {
-        let error = unsafe { UsbXhciController_initialize(&mut self.c_impl) };
-        if error > 0 {
-            return Err("failed to initialize xhci usb controller.");
-        }
-        Ok(())
-    }
-
-    pub fn run(&mut self) -> Result<()> {
-        let error = unsafe { UsbXhciController_run(&mut self.c_impl) };
-        if error > 0 {
-            return Err("failed to run xhci usb controller.");
-        }
-        Ok(())
-    }
-
-    pub fn configure_port(&mut self) {
-        unsafe { UsbXhciController_configurePort(&mut self.c_impl) };
-    }
-
-    pub fn process_event(&mut self) -> i32 {
-        unsafe { UsbXhciController_ProcessXhcEvent(&mut self.c_impl) }
-    }
-
-    pub fn process_event_ring_has_front(&mut self) -> bool {
-        unsafe { UsbXhciController_PrimaryEventRing_HasFront(&mut self.c_impl) }
-    }
-}
-
-pub fn register_mouse_observer(cb: MouseObserver) {
-    unsafe { RegisterMouseObserver(cb) };
+    #[allow(static_mut_refs)]
+    let xhc = unsafe { XHCI.as_mut() }.ok_or("no xHC.")?;
+    unsafe { XHCI_IN_USE = true };
+    let result = f(xhc);
+    unsafe { XHCI_IN_USE = false };
+    result
 }
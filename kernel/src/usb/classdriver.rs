@@ -0,0 +1,84 @@
+use super::{
+    descriptor::{Descriptor, Descriptors, EndpointDescriptor, InterfaceDescriptor},
+    xhci::Controller,
+};
+use crate::Result;
+use alloc::{boxed::Box, vec::Vec};
+
+mod hid;
+mod hub;
+mod mass_storage;
+
+/// Driver for one interface of a USB device.
+pub trait ClassDriver {
+    fn name(&self) -> &'static str;
+
+    /// Endpoints the controller configures before [`ClassDriver::start`] runs.
+    fn endpoints(&self) -> &[EndpointDescriptor];
+
+    /// Runs once the configuration is set, typically to queue the first transfers.
+    fn start(&mut self, xhc: &mut Controller, slot_id: u8) -> Result<()>;
+
+    /// A transfer queued on one of the driver's endpoints finished with `transferred` bytes,
+    /// or failed. A failed endpoint has already been reset and accepts new transfers.
+    fn on_transfer(
+        &mut self,
+        xhc: &mut Controller,
+        slot_id: u8,
+        dci: u8,
+        transferred: Result<usize>,
+    ) -> Result<()>;
+
+    /// Runs when the main loop is woken by the timer, for drivers that wait for a tick they
+    /// passed to [`crate::timer::wake_at`]. Other wakeups run it as well.
+    fn on_timer(&mut self, _xhc: &mut Controller, _slot_id: u8) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// An interface descriptor with the class-specific and endpoint descriptors that follow it.
+pub struct Interface<'a> {
+    pub descriptor: InterfaceDescriptor,
+    pub endpoints: Vec<EndpointDescriptor>,
+    /// Every descriptor up to the next interface, class-specific ones included.
+    pub descriptors: Vec<&'a [u8]>,
+}
+
+impl<'a> Interface<'a> {
+    /// Splits a configuration descriptor set into its interfaces, skipping alternate settings.
+    pub fn parse_all(configuration: &'a [u8]) -> Vec<Self> {
+        let mut interfaces: Vec<Self> = Vec::new();
+        for bytes in Descriptors::new(configuration) {
+            if let Some(descriptor) = InterfaceDescriptor::from_bytes(bytes) {
+                interfaces.push(Self {
+                    descriptor,
+                    endpoints: Vec::new(),
+                    descriptors: Vec::new(),
+                });
+                continue;
+            }
+            let Some(interface) = interfaces.last_mut() else {
+                continue;
+            };
+            if let Some(endpoint) = EndpointDescriptor::from_bytes(bytes) {
+                interface.endpoints.push(endpoint);
+            }
+            interface.descriptors.push(bytes);
+        }
+        interfaces.retain(|interface| interface.descriptor.alternate_setting == 0);
+        interfaces
+    }
+
+    pub fn find<T: Descriptor>(&self) -> Option<T> {
+        self.descriptors
+            .iter()
+            .find_map(|bytes| T::from_bytes(bytes))
+    }
+}
+
+/// Returns a driver for `interface` if any class driver supports it.
+pub fn probe(interface: &Interface) -> Option<Box<dyn ClassDriver>> {
+    hid::probe(interface)
+        .or_else(|| hub::probe(interface))
+        .or_else(|| mass_storage::probe(interface))
+}
@@ -0,0 +1,430 @@
+use super::{ClassDriver, Interface};
+use crate::{
+    Result,
+    input::{self, InputEvent, KeyEvent},
+    usb::{
+        descriptor::{
+            DescriptorType, EndpointDescriptor, HidDescriptor, Request, RequestType, SetupData,
+            TransferType,
+        },
+        memory::DmaBuffer,
+        xhci::Controller,
+    },
+};
+use alloc::{boxed::Box, vec::Vec};
+use bit_field::BitField;
+
+const CLASS_HID: u8 = 3;
+const SUBCLASS_BOOT: u8 = 1;
+const PROTOCOL_KEYBOARD: u8 = 1;
+const PROTOCOL_MOUSE: u8 = 2;
+
+/// [HID 1.11, 7.2 Class-Specific Requests](https://www.usb.org/document-library/device-class-definition-hid-111)
+const SET_IDLE: u8 = 0x0a;
+const SET_PROTOCOL: u8 = 0x0b;
+const BOOT_PROTOCOL: u16 = 0;
+
+/// Usage ID reported in every key slot when too many keys are held down.
+const ERROR_ROLL_OVER: u8 = 0x01;
+/// Usage ID of the left control key; the modifier byte bits follow in order.
+const MODIFIER_KEYCODE_BASE: u8 = 0xe0;
+
+enum Kind {
+    Keyboard {
+        previous: [u8; 8],
+    },
+    Mouse {
+        buttons: u8,
+    },
+    /// Report protocol mouse or tablet, described by its report descriptor.
+    Pointer {
+        layout: Option<PointerLayout>,
+        buttons: u8,
+    },
+}
+
+pub struct HidDriver {
+    interface: u8,
+    report_descriptor_length: u16,
+    endpoints: [EndpointDescriptor; 1],
+    buffer: DmaBuffer,
+    kind: Kind,
+}
+
+pub fn probe(interface: &Interface) -> Option<Box<dyn ClassDriver>> {
+    let descriptor = &interface.descriptor;
+    if descriptor.interface_class != CLASS_HID {
+        return None;
+    }
+    let endpoint = *interface
+        .endpoints
+        .iter()
+        .find(|e| e.is_in() && e.transfer_type() == TransferType::Interrupt)?;
+
+    let kind = match (
+        descriptor.interface_sub_class,
+        descriptor.interface_protocol,
+    ) {
+        (SUBCLASS_BOOT, PROTOCOL_KEYBOARD) => Kind::Keyboard { previous: [0; 8] },
+        (SUBCLASS_BOOT, PROTOCOL_MOUSE) => Kind::Mouse { buttons: 0 },
+        _ => Kind::Pointer {
+            layout: None,
+            buttons: 0,
+        },
+    };
+    let report_descriptor_length = interface
+        .find::<HidDescriptor>()
+        .filter(|hid| hid.class_descriptor_type == DescriptorType::REPORT)
+        .map_or(0, |hid| hid.class_descriptor_length);
+
+    Some(Box::new(HidDriver {
+        interface: descriptor.interface_number,
+        report_descriptor_length,
+        endpoints: [endpoint],
+        buffer: DmaBuffer::new(endpoint.max_packet_size() as usize, 64).ok()?,
+        kind,
+    }))
+}
+
+impl HidDriver {
+    fn class_request(&self, request: u8, value: u16) -> SetupData {
+        SetupData::new(
+            RequestType::CLASS | RequestType::INTERFACE,
+            request,
+            value,
+            self.interface as u16,
+            0,
+        )
+    }
+
+    fn request_report(&self, xhc: &mut Controller, slot_id: u8) -> Result<()> {
+        let endpoint = &self.endpoints[0];
+        xhc.transfer(slot_id, endpoint.dci(), &self.buffer, self.buffer.len())
+    }
+}
+
+impl ClassDriver for HidDriver {
+    fn name(&self) -> &'static str {
+        match self.kind {
+            Kind::Keyboard { .. } => "keyboard",
+            Kind::Mouse { .. } => "mouse",
+            Kind::Pointer { .. } => "pointer",
+        }
+    }
+
+    fn endpoints(&self) -> &[EndpointDescriptor] {
+        &self.endpoints
+    }
+
+    fn start(&mut self, xhc: &mut Controller, slot_id: u8) -> Result<()> {
+        match self.kind {
+            Kind::Keyboard { .. } => {
+                xhc.control(slot_id, self.class_request(SET_PROTOCOL, BOOT_PROTOCOL))?;
+                // Only report when something changes.
+                xhc.control(slot_id, self.class_request(SET_IDLE, 0))?;
+            }
+            Kind::Mouse { .. } => {
+                xhc.control(slot_id, self.class_request(SET_PROTOCOL, BOOT_PROTOCOL))?;
+            }
+            Kind::Pointer { .. } => {
+                let setup = SetupData::new(
+                    RequestType::DEVICE_TO_HOST | RequestType::STANDARD | RequestType::INTERFACE,
+                    Request::GET_DESCRIPTOR,
+                    (DescriptorType::REPORT as u16) << 8,
+                    self.interface as u16,
+                    self.report_descriptor_length,
+                );
+                let descriptor = xhc.control_in(slot_id, setup)?;
+                let parsed = PointerLayout::parse(&descriptor).ok_or("unsupported HID report.")?;
+                if let Kind::Pointer { layout, .. } = &mut self.kind {
+                    *layout = Some(parsed);
+                }
+            }
+        }
+        self.request_report(xhc, slot_id)
+    }
+
+    fn on_transfer(
+        &mut self,
+        xhc: &mut Controller,
+        slot_id: u8,
+        _dci: u8,
+        transferred: Result<usize>,
+    ) -> Result<()> {
+        if let Ok(len) = transferred {
+            let report = &self.buffer.as_slice()[..len];
+            match &mut self.kind {
+                Kind::Keyboard { previous } => {
+                    if let Some(Ok(current)) = report.get(..8).map(<[u8; 8]>::try_from) {
+                        report_keys(previous, &current);
+                        *previous = current;
+                    }
+                }
+                Kind::Mouse { buttons } => {
+                    if report.len() >= 3 {
+                        input::report_buttons(*buttons, report[0]);
+                        *buttons = report[0];
+                        report_motion(report[1] as i8 as i32, report[2] as i8 as i32);
+                    }
+                    if let Some(&wheel) = report.get(3) {
+                        report_wheel(wheel as i8 as i32);
+                    }
+                }
+                Kind::Pointer {
+                    layout: Some(layout),
+                    buttons,
+                } => layout.report(report, buttons),
+                Kind::Pointer { layout: None, .. } => {}
+            }
+        }
+        self.request_report(xhc, slot_id)
+    }
+}
+
+/// Reports the keys that differ between two boot keyboard reports, modifiers included.
+fn report_keys(previous: &[u8; 8], current: &[u8; 8]) {
+    if current[2..].contains(&ERROR_ROLL_OVER) {
+        return;
+    }
+    let modifier = current[0];
+    (0..8)
+        .filter(|bit| (previous[0] ^ modifier).get_bit(*bit))
+        .for_each(|bit| {
+            let keycode = MODIFIER_KEYCODE_BASE + bit as u8;
+            let press = modifier.get_bit(bit);
+            input::report(InputEvent::Key(KeyEvent::new(modifier, keycode, press)));
+        });
+
+    let is_key = |keycode: &&u8| **keycode > ERROR_ROLL_OVER;
+    previous[2..]
+        .iter()
+        .filter(is_key)
+        .filter(|keycode| !current[2..].contains(keycode))
+        .for_each(|&keycode| {
+            input::report(InputEvent::Key(KeyEvent::new(modifier, keycode, false)));
+        });
+    current[2..]
+        .iter()
+        .filter(is_key)
+        .filter(|keycode| !previous[2..].contains(keycode))
+        .for_each(|&keycode| {
+            input::report(InputEvent::Key(KeyEvent::new(modifier, keycode, true)));
+        });
+}
+
+fn report_motion(dx: i32, dy: i32) {
+    if dx != 0 || dy != 0 {
+        input::report(InputEvent::RelativeMotion { dx, dy });
+    }
+}
+
+fn report_wheel(wheel: i32) {
+    if wheel != 0 {
+        input::report(InputEvent::Wheel(wheel));
+    }
+}
+
+/// [HID Usage Tables 1.5, 4 Generic Desktop Page (0x01)](https://usb.org/sites/default/files/hut1_5.pdf)
+const USAGE_X: u32 = 0x01_0030;
+const USAGE_Y: u32 = 0x01_0031;
+const USAGE_WHEEL: u32 = 0x01_0038;
+const USAGE_PAGE_BUTTON: u32 = 0x09;
+
+/// A value in an input report, `size` bits wide starting at bit `offset`.
+#[derive(Debug, Clone, Copy)]
+struct Field {
+    offset: usize,
+    size: usize,
+    signed: bool,
+    logical_max: i32,
+}
+
+impl Field {
+    fn extract(&self, report: &[u8]) -> Option<i32> {
+        let mut value = 0u32;
+        for i in 0..self.size.min(32) {
+            let bit = self.offset + i;
+            let byte = *report.get(bit / 8)?;
+            value.set_bit(i, byte.get_bit(bit % 8));
+        }
+        if self.signed && self.size < 32 && value.get_bit(self.size - 1) {
+            value |= !0 << self.size;
+        }
+        Some(value as i32)
+    }
+}
+
+/// Where a mouse or tablet puts its buttons and axes in its input report.
+#[derive(Debug)]
+struct PointerLayout {
+    report_id: Option<u8>,
+    buttons: Option<Field>,
+    x: Field,
+    y: Field,
+    wheel: Option<Field>,
+    absolute: bool,
+}
+
+/// Item state of the report descriptor parser.
+///
+/// [HID 1.11, 6.2.2 Report Descriptor](https://www.usb.org/document-library/device-class-definition-hid-111)
+#[derive(Default)]
+struct ItemState {
+    usage_page: u32,
+    logical_min: i32,
+    logical_max: i32,
+    report_size: usize,
+    report_count: usize,
+    report_id: Option<u8>,
+    usages: Vec<u32>,
+    usage_min: Option<u32>,
+    /// Bits used so far in each report.
+    offsets: Vec<(Option<u8>, usize)>,
+}
+
+impl ItemState {
+    fn offset(&mut self) -> &mut usize {
+        let id = self.report_id;
+        let index = match self.offsets.iter().position(|(report, _)| *report == id) {
+            Some(index) => index,
+            None => {
+                // A report ID, if any, occupies the first byte of the report.
+                self.offsets.push((id, if id.is_some() { 8 } else { 0 }));
+                self.offsets.len() - 1
+            }
+        };
+        &mut self.offsets[index].1
+    }
+
+    fn usage(&self, n: usize) -> Option<u32> {
+        self.usages
+            .get(n)
+            .or(self.usages.last())
+            .copied()
+            .or(self.usage_min.map(|min| min + n as u32))
+    }
+}
+
+impl PointerLayout {
+    /// Finds X, Y, buttons and wheel in the first report that carries an X axis.
+    fn parse(descriptor: &[u8]) -> Option<Self> {
+        let mut state = ItemState::default();
+        // (report ID, usage, field, relative)
+        let mut fields: Vec<(Option<u8>, u32, Field, bool)> = Vec::new();
+
+        let mut i = 0;
+        while i < descriptor.len() {
+            let prefix = descriptor[i];
+            if prefix == 0xfe {
+                // Long item.
+                i += 3 + *descriptor.get(i + 1)? as usize;
+                continue;
+            }
+            let size = [0, 1, 2, 4][prefix as usize & 0x3];
+            let data = descriptor.get(i + 1..i + 1 + size)?;
+            i += 1 + size;
+
+            let unsigned = data.iter().rev().fold(0u32, |v, b| v << 8 | *b as u32);
+            let signed = match size {
+                1 => unsigned as u8 as i8 as i32,
+                2 => unsigned as u16 as i16 as i32,
+                _ => unsigned as i32,
+            };
+
+            match (prefix >> 2 & 0x3, prefix >> 4) {
+                // Input
+                (0, 0x8) => {
+                    let relative = unsigned.get_bit(2);
+                    let constant = unsigned.get_bit(0);
+                    let base = *state.offset();
+                    for n in 0..state.report_count {
+                        let field = Field {
+                            offset: base + n * state.report_size,
+                            size: state.report_size,
+                            signed: state.logical_min < 0,
+                            logical_max: state.logical_max,
+                        };
+                        if let (false, Some(usage)) = (constant, state.usage(n)) {
+                            fields.push((state.report_id, usage, field, relative));
+                        }
+                    }
+                    *state.offset() += state.report_count * state.report_size;
+                    state.usages.clear();
+                    state.usage_min = None;
+                }
+                // Other main items end the local state as well.
+                (0, _) => {
+                    state.usages.clear();
+                    state.usage_min = None;
+                }
+                (1, 0x0) => state.usage_page = unsigned,
+                (1, 0x1) => state.logical_min = signed,
+                (1, 0x2) => {
+                    state.logical_max = if state.logical_min < 0 {
+                        signed
+                    } else {
+                        unsigned as i32
+                    }
+                }
+                (1, 0x7) => state.report_size = unsigned as usize,
+                (1, 0x8) => state.report_id = Some(unsigned as u8),
+                (1, 0x9) => state.report_count = unsigned as usize,
+                (2, 0x0) if size == 4 => state.usages.push(unsigned),
+                (2, 0x0) => state.usages.push(state.usage_page << 16 | unsigned),
+                (2, 0x1) if size == 4 => state.usage_min = Some(unsigned),
+                (2, 0x1) => state.usage_min = Some(state.usage_page << 16 | unsigned),
+                _ => {}
+            }
+        }
+
+        let &(report_id, _, x, relative) = fields.iter().find(|f| f.1 == USAGE_X)?;
+        let find = |usage: u32| {
+            fields
+                .iter()
+                .find(|f| f.0 == report_id && f.1 == usage)
+                .map(|f| f.2)
+        };
+        let buttons = fields
+            .iter()
+            .filter(|f| f.0 == report_id && f.1 >> 16 == USAGE_PAGE_BUTTON && f.2.size == 1)
+            .map(|f| f.2)
+            .reduce(|first, next| Field {
+                size: (next.offset + 1 - first.offset).min(8),
+                ..first
+            });
+        Some(Self {
+            report_id,
+            buttons,
+            x,
+            y: find(USAGE_Y)?,
+            wheel: find(USAGE_WHEEL),
+            absolute: !relative,
+        })
+    }
+
+    fn report(&self, report: &[u8], buttons: &mut u8) {
+        if self.report_id.is_some() && report.first().copied() != self.report_id {
+            return;
+        }
+        if let Some(current) = self.buttons.and_then(|field| field.extract(report)) {
+            input::report_buttons(*buttons, current as u8);
+            *buttons = current as u8;
+        }
+        let (Some(x), Some(y)) = (self.x.extract(report), self.y.extract(report)) else {
+            return;
+        };
+        if self.absolute {
+            input::report(InputEvent::AbsoluteMotion {
+                x: x.max(0) as u32,
+                y: y.max(0) as u32,
+                max_x: self.x.logical_max as u32,
+                max_y: self.y.logical_max as u32,
+            });
+        } else {
+            report_motion(x, y);
+        }
+        if let Some(wheel) = self.wheel.and_then(|field| field.extract(report)) {
+            report_wheel(wheel);
+        }
+    }
+}
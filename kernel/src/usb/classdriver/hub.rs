@@ -0,0 +1,460 @@
+use super::{ClassDriver, Interface};
+use crate::{
+    Result, timer,
+    usb::{
+        descriptor::{
+            DescriptorType, EndpointDescriptor, Request, RequestType, SetupData, TransferType,
+        },
+        device::Speed,
+        memory::DmaBuffer,
+        xhci::Controller,
+    },
+};
+use alloc::{boxed::Box, vec, vec::Vec};
+use bit_field::BitField;
+
+const CLASS_HUB: u8 = 9;
+/// Interface protocol of a hub whose ports each have their own transaction translator.
+const PROTOCOL_MULTI_TT: u8 = 2;
+
+/// [11.24.2 Class-specific Requests](https://www.usb.org/document-library/usb-20-specification)
+const SET_HUB_DEPTH: u8 = 12;
+
+/// Hub class feature selectors.
+struct Feature;
+
+impl Feature {
+    const C_HUB_LOCAL_POWER: u16 = 0;
+    const C_HUB_OVER_CURRENT: u16 = 1;
+    const PORT_RESET: u16 = 4;
+    const PORT_POWER: u16 = 8;
+    const C_PORT_CONNECTION: u16 = 16;
+    const C_PORT_ENABLE: u16 = 17;
+    const C_PORT_SUSPEND: u16 = 18;
+    const C_PORT_OVER_CURRENT: u16 = 19;
+    const C_PORT_RESET: u16 = 20;
+    const C_PORT_LINK_STATE: u16 = 25;
+    const C_PORT_CONFIG_ERROR: u16 = 26;
+    const C_BH_PORT_RESET: u16 = 29;
+}
+
+/// [11.24.2.6 Get Hub Status](https://www.usb.org/document-library/usb-20-specification)
+const HUB_OVER_CURRENT: usize = 1;
+
+/// wHubChange bits and the features that clear them.
+const HUB_CHANGES: [(usize, u16); 2] = [
+    (0, Feature::C_HUB_LOCAL_POWER),
+    (1, Feature::C_HUB_OVER_CURRENT),
+];
+const HUB_CHANGE_OVER_CURRENT: usize = 1;
+
+/// [11.24.2.7.1 Port Status Bits](https://www.usb.org/document-library/usb-20-specification)
+const PORT_CONNECTION: usize = 0;
+const PORT_ENABLE: usize = 1;
+const PORT_LOW_SPEED: usize = 9;
+const PORT_HIGH_SPEED: usize = 10;
+
+/// wPortChange bits and the features that clear them, for USB 2.0 hubs.
+const CHANGES: [(usize, u16); 5] = [
+    (0, Feature::C_PORT_CONNECTION),
+    (1, Feature::C_PORT_ENABLE),
+    (2, Feature::C_PORT_SUSPEND),
+    (3, Feature::C_PORT_OVER_CURRENT),
+    (4, Feature::C_PORT_RESET),
+];
+/// The same for SuperSpeed hubs.
+///
+/// [USB 3.2, 10.16.2.6.2 Port Change Field](https://www.usb.org/document-library/usb-32-revision-11-june-2022)
+const SUPER_SPEED_CHANGES: [(usize, u16); 6] = [
+    (0, Feature::C_PORT_CONNECTION),
+    (3, Feature::C_PORT_OVER_CURRENT),
+    (4, Feature::C_PORT_RESET),
+    (5, Feature::C_BH_PORT_RESET),
+    (6, Feature::C_PORT_LINK_STATE),
+    (7, Feature::C_PORT_CONFIG_ERROR),
+];
+const CHANGE_CONNECTION: usize = 0;
+const CHANGE_RESET: usize = 4;
+
+/// Time for a newly connected device to settle before it is reset.
+const DEBOUNCE_MS: u64 = 100;
+/// Time a device may take after reset before it must answer to its address.
+const RESET_RECOVERY_MS: u64 = 10;
+const RESET_TIMEOUT_MS: u64 = 500;
+
+/// Where a port is on the way from a connection to an addressed device. Deadlines are in
+/// timer ticks.
+#[derive(Debug, Clone, Copy)]
+enum PortState {
+    Idle,
+    /// A device was connected and is settling.
+    Debouncing {
+        until: u64,
+    },
+    /// The port is being reset, which the hub reports as a `C_PORT_RESET` change.
+    Resetting {
+        timeout: u64,
+    },
+    /// The reset device answers to the default address until it is addressed.
+    Recovering {
+        until: u64,
+        speed: u8,
+    },
+}
+
+impl PortState {
+    fn deadline(&self) -> Option<u64> {
+        match *self {
+            Self::Idle => None,
+            Self::Debouncing { until } | Self::Recovering { until, .. } => Some(until),
+            Self::Resetting { timeout } => Some(timeout),
+        }
+    }
+}
+
+/// First bytes of the USB 2.0 and SuperSpeed hub descriptors, which agree up to here.
+///
+/// [11.23.2.1 Hub Descriptor](https://www.usb.org/document-library/usb-20-specification)
+#[allow(dead_code)]
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+struct HubDescriptor {
+    length: u8,
+    descriptor_type: u8,
+    num_ports: u8,
+    characteristics: u16,
+    /// In units of 2 ms.
+    power_on_to_power_good: u8,
+    controller_current: u8,
+}
+
+impl HubDescriptor {
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < size_of::<Self>() {
+            return None;
+        }
+        Some(unsafe { bytes.as_ptr().cast::<Self>().read_unaligned() })
+    }
+
+    /// Transaction translator think time, in units of 8 full-speed bit times minus one.
+    fn think_time(&self) -> u8 {
+        { self.characteristics }.get_bits(5..7) as u8
+    }
+}
+
+pub struct HubDriver {
+    endpoints: [EndpointDescriptor; 1],
+    /// One bit per port, bit 0 being the hub itself.
+    status_change: DmaBuffer,
+    num_ports: u8,
+    /// Indexed by port number minus one.
+    ports: Vec<PortState>,
+    /// Tick at which the ports have power after [`ClassDriver::start`] switched it on.
+    power_good: u64,
+    super_speed: bool,
+    multi_tt: bool,
+}
+
+pub fn probe(interface: &Interface) -> Option<Box<dyn ClassDriver>> {
+    if interface.descriptor.interface_class != CLASS_HUB {
+        return None;
+    }
+    let endpoint = *interface
+        .endpoints
+        .iter()
+        .find(|e| e.is_in() && e.transfer_type() == TransferType::Interrupt)?;
+    Some(Box::new(HubDriver {
+        endpoints: [endpoint],
+        status_change: DmaBuffer::new(endpoint.max_packet_size() as usize, 64).ok()?,
+        num_ports: 0,
+        ports: Vec::new(),
+        power_good: 0,
+        super_speed: false,
+        multi_tt: interface.descriptor.interface_protocol == PROTOCOL_MULTI_TT,
+    }))
+}
+
+impl HubDriver {
+    /// Returns wHubStatus and wHubChange.
+    fn hub_status(xhc: &mut Controller, slot_id: u8) -> Result<(u16, u16)> {
+        let setup = SetupData::new(
+            RequestType::DEVICE_TO_HOST | RequestType::CLASS | RequestType::DEVICE,
+            Request::GET_STATUS,
+            0,
+            0,
+            4,
+        );
+        match *xhc.control_in(slot_id, setup)? {
+            [s0, s1, c0, c1] => Ok((u16::from_le_bytes([s0, s1]), u16::from_le_bytes([c0, c1]))),
+            _ => Err("short hub status."),
+        }
+    }
+
+    fn clear_hub_feature(xhc: &mut Controller, slot_id: u8, feature: u16) -> Result<()> {
+        let setup = SetupData::new(
+            RequestType::CLASS | RequestType::DEVICE,
+            Request::CLEAR_FEATURE,
+            feature,
+            0,
+            0,
+        );
+        xhc.control(slot_id, setup)
+    }
+
+    fn port_request(request: u8, feature: u16, port: u8) -> SetupData {
+        SetupData::new(
+            RequestType::CLASS | RequestType::OTHER,
+            request,
+            feature,
+            port as u16,
+            0,
+        )
+    }
+
+    fn set_feature(xhc: &mut Controller, slot_id: u8, port: u8, feature: u16) -> Result<()> {
+        xhc.control(
+            slot_id,
+            Self::port_request(Request::SET_FEATURE, feature, port),
+        )
+    }
+
+    fn clear_feature(xhc: &mut Controller, slot_id: u8, port: u8, feature: u16) -> Result<()> {
+        xhc.control(
+            slot_id,
+            Self::port_request(Request::CLEAR_FEATURE, feature, port),
+        )
+    }
+
+    /// Returns wPortStatus and wPortChange.
+    fn port_status(xhc: &mut Controller, slot_id: u8, port: u8) -> Result<(u16, u16)> {
+        let setup = SetupData::new(
+            RequestType::DEVICE_TO_HOST | RequestType::CLASS | RequestType::OTHER,
+            Request::GET_STATUS,
+            0,
+            port as u16,
+            4,
+        );
+        match *xhc.control_in(slot_id, setup)? {
+            [s0, s1, c0, c1] => Ok((u16::from_le_bytes([s0, s1]), u16::from_le_bytes([c0, c1]))),
+            _ => Err("short hub port status."),
+        }
+    }
+
+    fn request_status_change(&self, xhc: &mut Controller, slot_id: u8) -> Result<()> {
+        let endpoint = &self.endpoints[0];
+        xhc.transfer(
+            slot_id,
+            endpoint.dci(),
+            &self.status_change,
+            self.status_change.len(),
+        )
+    }
+
+    /// Speed of the device on a port that was just reset, from its wPortStatus.
+    fn speed(&self, status: u16) -> u8 {
+        if self.super_speed {
+            Speed::SUPER
+        } else if status.get_bit(PORT_LOW_SPEED) {
+            Speed::LOW
+        } else if status.get_bit(PORT_HIGH_SPEED) {
+            Speed::HIGH
+        } else {
+            Speed::FULL
+        }
+    }
+
+    /// Only one device may answer to the default address, so ports are reset one at a time.
+    fn resetting(&self) -> bool {
+        self.ports.iter().any(|state| {
+            matches!(
+                state,
+                PortState::Resetting { .. } | PortState::Recovering { .. }
+            )
+        })
+    }
+
+    /// Acknowledges a change of the hub's own power or over-current state.
+    fn on_hub_change(&self, xhc: &mut Controller, slot_id: u8) -> Result<()> {
+        let (status, change) = Self::hub_status(xhc, slot_id)?;
+        for &(bit, feature) in &HUB_CHANGES {
+            if change.get_bit(bit) {
+                Self::clear_hub_feature(xhc, slot_id, feature)?;
+            }
+        }
+        if change.get_bit(HUB_CHANGE_OVER_CURRENT) && status.get_bit(HUB_OVER_CURRENT) {
+            return Err("hub reports over-current.");
+        }
+        Ok(())
+    }
+
+    /// Handles a connection or other change on `port`. A new device is only scheduled for its
+    /// debounce here; [`HubDriver::advance_port`] takes it through the reset from the timer.
+    fn on_port_change(&mut self, xhc: &mut Controller, slot_id: u8, port: u8) -> Result<()> {
+        let (status, change) = Self::port_status(xhc, slot_id, port)?;
+        // Acknowledge every change so that the hub reports the next one.
+        let changes: &[(usize, u16)] = if self.super_speed {
+            &SUPER_SPEED_CHANGES
+        } else {
+            &CHANGES
+        };
+        for &(bit, feature) in changes {
+            if change.get_bit(bit) {
+                Self::clear_feature(xhc, slot_id, port, feature)?;
+            }
+        }
+
+        let index = port as usize - 1;
+        if change.get_bit(CHANGE_CONNECTION) {
+            self.ports[index] = PortState::Idle;
+            // A device that was replaced before its disconnect was seen goes away as well.
+            xhc.detach_from_hub(slot_id, port)?;
+            if status.get_bit(PORT_CONNECTION) {
+                let until = timer::deadline_ms(DEBOUNCE_MS).max(self.power_good);
+                self.ports[index] = PortState::Debouncing { until };
+                timer::wake_at(until);
+            }
+            return Ok(());
+        }
+        if !change.get_bit(CHANGE_RESET)
+            || !matches!(self.ports[index], PortState::Resetting { .. })
+        {
+            return Ok(());
+        }
+
+        self.ports[index] = PortState::Idle;
+        if !status.get_bit(PORT_ENABLE) {
+            return Err("hub port is not enabled.");
+        }
+        let until = timer::deadline_ms(RESET_RECOVERY_MS);
+        self.ports[index] = PortState::Recovering {
+            until,
+            speed: self.speed(status),
+        };
+        timer::wake_at(until);
+        Ok(())
+    }
+
+    /// Moves `port` on once the deadline of its state has passed.
+    fn advance_port(&mut self, xhc: &mut Controller, slot_id: u8, port: u8) -> Result<()> {
+        let index = port as usize - 1;
+        let now = timer::ticks();
+        match self.ports[index] {
+            PortState::Debouncing { until } if now >= until && !self.resetting() => {
+                self.ports[index] = PortState::Idle;
+                Self::set_feature(xhc, slot_id, port, Feature::PORT_RESET)?;
+                self.ports[index] = PortState::Resetting {
+                    timeout: timer::deadline_ms(RESET_TIMEOUT_MS),
+                };
+                Ok(())
+            }
+            PortState::Resetting { timeout } if now >= timeout => {
+                self.ports[index] = PortState::Idle;
+                Err("hub port reset did not finish.")
+            }
+            PortState::Recovering { until, speed } if now >= until => {
+                self.ports[index] = PortState::Idle;
+                xhc.attach_to_hub(slot_id, port, speed)?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ClassDriver for HubDriver {
+    fn name(&self) -> &'static str {
+        "hub"
+    }
+
+    fn endpoints(&self) -> &[EndpointDescriptor] {
+        &self.endpoints
+    }
+
+    fn start(&mut self, xhc: &mut Controller, slot_id: u8) -> Result<()> {
+        let device = xhc.device(slot_id)?;
+        self.super_speed = device.speed >= Speed::SUPER;
+        let depth = device.route.depth();
+
+        let (descriptor_type, len) = if self.super_speed {
+            (DescriptorType::SUPERSPEED_HUB, 12)
+        } else {
+            (DescriptorType::HUB, 9)
+        };
+        let setup = SetupData::new(
+            RequestType::DEVICE_TO_HOST | RequestType::CLASS | RequestType::DEVICE,
+            Request::GET_DESCRIPTOR,
+            (descriptor_type as u16) << 8,
+            0,
+            len,
+        );
+        let bytes = xhc.control_in(slot_id, setup)?;
+        let descriptor = HubDescriptor::from_bytes(&bytes).ok_or("bad hub descriptor.")?;
+        self.num_ports = descriptor.num_ports;
+
+        xhc.set_hub(
+            slot_id,
+            self.num_ports,
+            descriptor.think_time(),
+            self.multi_tt,
+        )?;
+        if self.super_speed {
+            let setup = SetupData::new(
+                RequestType::CLASS | RequestType::DEVICE,
+                SET_HUB_DEPTH,
+                depth as u16,
+                0,
+                0,
+            );
+            xhc.control(slot_id, setup)?;
+        }
+
+        // Connected devices show up as connection changes, and are only reset once their ports
+        // have had time to power up.
+        self.ports = vec![PortState::Idle; self.num_ports as usize];
+        for port in 1..=self.num_ports {
+            Self::set_feature(xhc, slot_id, port, Feature::PORT_POWER)?;
+        }
+        self.power_good = timer::deadline_ms(descriptor.power_on_to_power_good as u64 * 2);
+        self.request_status_change(xhc, slot_id)
+    }
+
+    fn on_transfer(
+        &mut self,
+        xhc: &mut Controller,
+        slot_id: u8,
+        _dci: u8,
+        transferred: Result<usize>,
+    ) -> Result<()> {
+        let mut result = Ok(());
+        if let Ok(len) = transferred {
+            let bitmap = self.status_change.as_slice()[..len].to_vec();
+            let changed = |bit: usize| {
+                bitmap
+                    .get(bit / 8)
+                    .is_some_and(|bits| bits.get_bit(bit % 8))
+            };
+            if changed(0) {
+                result = result.and(self.on_hub_change(xhc, slot_id));
+            }
+            for port in 1..=self.num_ports {
+                if changed(port as usize) {
+                    result = result.and(self.on_port_change(xhc, slot_id, port));
+                }
+            }
+        }
+        self.request_status_change(xhc, slot_id)?;
+        result
+    }
+
+    fn on_timer(&mut self, xhc: &mut Controller, slot_id: u8) -> Result<()> {
+        let mut result = Ok(());
+        for port in 1..=self.num_ports {
+            result = result.and(self.advance_port(xhc, slot_id, port));
+        }
+        // A port that waits for another one's reset is overdue and wakes the next tick again.
+        if let Some(tick) = self.ports.iter().filter_map(PortState::deadline).min() {
+            timer::wake_at(tick);
+        }
+        result
+    }
+}
@@ -0,0 +1,404 @@
+use super::{ClassDriver, Interface};
+use crate::{
+    Result,
+    block::{self, BlockDevice, BlockDeviceId},
+    console::console,
+    usb::{
+        self,
+        descriptor::{EndpointDescriptor, RequestType, SetupData, TransferType},
+        memory::DmaBuffer,
+        xhci::Controller,
+    },
+};
+use alloc::{boxed::Box, format, string::String};
+use core::fmt::Write;
+
+const CLASS_MASS_STORAGE: u8 = 8;
+const SUBCLASS_SCSI: u8 = 6;
+const PROTOCOL_BULK_ONLY: u8 = 0x50;
+
+/// [BOT 1.0, 3.1 Bulk-Only Mass Storage Reset](https://www.usb.org/document-library/mass-storage-bulk-only-10)
+const BULK_ONLY_RESET: u8 = 0xff;
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CBW_DATA_IN: u8 = 1 << 7;
+const CSW_PASSED: u8 = 0;
+const CSW_FAILED: u8 = 1;
+
+/// SCSI operation codes.
+///
+/// [SCSI Commands Reference Manual](https://www.seagate.com/files/staticfiles/support/docs/manual/Interface%20manuals/100293068j.pdf)
+struct Operation;
+
+impl Operation {
+    const TEST_UNIT_READY: u8 = 0x00;
+    const REQUEST_SENSE: u8 = 0x03;
+    const INQUIRY: u8 = 0x12;
+    const READ_CAPACITY_10: u8 = 0x25;
+    const READ_10: u8 = 0x28;
+    const WRITE_10: u8 = 0x2a;
+}
+
+const INQUIRY_LEN: u8 = 36;
+const SENSE_LEN: u8 = 18;
+
+/// Command data goes through one page-aligned page, which never crosses the 64 KiB boundary a
+/// single TRB must not cross. Larger requests are split into several commands.
+const DATA_BUFFER_SIZE: usize = 4096;
+/// A device reports a unit attention after reset, which the first commands have to clear.
+const READY_ATTEMPTS: usize = 5;
+
+/// [BOT 1.0, 5.1 Command Block Wrapper](https://www.usb.org/document-library/mass-storage-bulk-only-10)
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+struct CommandBlockWrapper {
+    signature: u32,
+    tag: u32,
+    data_transfer_length: u32,
+    flags: u8,
+    lun: u8,
+    command_length: u8,
+    command: [u8; 16],
+}
+
+/// [BOT 1.0, 5.2 Command Status Wrapper](https://www.usb.org/document-library/mass-storage-bulk-only-10)
+#[allow(dead_code)]
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+struct CommandStatusWrapper {
+    signature: u32,
+    tag: u32,
+    data_residue: u32,
+    status: u8,
+}
+
+/// Claims the bulk endpoints of a SCSI disk and registers it as a block device once it
+/// answers.
+pub struct MassStorageDriver {
+    interface: u8,
+    /// Bulk IN, then bulk OUT.
+    endpoints: [EndpointDescriptor; 2],
+    block_device: Option<BlockDeviceId>,
+}
+
+pub fn probe(interface: &Interface) -> Option<Box<dyn ClassDriver>> {
+    let descriptor = &interface.descriptor;
+    if (
+        descriptor.interface_class,
+        descriptor.interface_sub_class,
+        descriptor.interface_protocol,
+    ) != (CLASS_MASS_STORAGE, SUBCLASS_SCSI, PROTOCOL_BULK_ONLY)
+    {
+        return None;
+    }
+    let bulk = |is_in: bool| {
+        interface
+            .endpoints
+            .iter()
+            .find(|e| e.is_in() == is_in && e.transfer_type() == TransferType::Bulk)
+            .copied()
+    };
+    Some(Box::new(MassStorageDriver {
+        interface: descriptor.interface_number,
+        endpoints: [bulk(true)?, bulk(false)?],
+        block_device: None,
+    }))
+}
+
+impl ClassDriver for MassStorageDriver {
+    fn name(&self) -> &'static str {
+        "mass storage"
+    }
+
+    fn endpoints(&self) -> &[EndpointDescriptor] {
+        &self.endpoints
+    }
+
+    fn start(&mut self, xhc: &mut Controller, slot_id: u8) -> Result<()> {
+        let [bulk_in, bulk_out] = self.endpoints;
+        let mut disk = UsbDisk::new(slot_id, self.interface, bulk_in, bulk_out)?;
+        disk.name = disk.inquiry(xhc)?;
+        disk.wait_ready(xhc)?;
+        let (num_blocks, block_size) = disk.read_capacity(xhc)?;
+        if block_size == 0 || block_size > DATA_BUFFER_SIZE {
+            return Err("unsupported block size.");
+        }
+        disk.num_blocks = num_blocks;
+        disk.block_size = block_size;
+
+        println!(
+            "usb: slot {}: {}: {} blocks of {} bytes",
+            slot_id, disk.name, num_blocks, block_size
+        );
+        self.block_device = Some(block::register(Box::new(disk)));
+        Ok(())
+    }
+
+    fn on_transfer(
+        &mut self,
+        _xhc: &mut Controller,
+        _slot_id: u8,
+        _dci: u8,
+        _transferred: Result<usize>,
+    ) -> Result<()> {
+        // Every transfer is waited for by the command that issued it.
+        Ok(())
+    }
+}
+
+impl Drop for MassStorageDriver {
+    fn drop(&mut self) {
+        if let Some(id) = self.block_device {
+            block::unregister(id);
+        }
+    }
+}
+
+/// Logical unit 0 of a SCSI disk behind the Bulk-Only Transport.
+struct UsbDisk {
+    name: String,
+    slot_id: u8,
+    interface: u8,
+    bulk_in: EndpointDescriptor,
+    bulk_out: EndpointDescriptor,
+    tag: u32,
+    command_block: DmaBuffer,
+    command_status: DmaBuffer,
+    data: DmaBuffer,
+    num_blocks: u64,
+    block_size: usize,
+}
+
+impl UsbDisk {
+    fn new(
+        slot_id: u8,
+        interface: u8,
+        bulk_in: EndpointDescriptor,
+        bulk_out: EndpointDescriptor,
+    ) -> Result<Self> {
+        Ok(Self {
+            name: String::new(),
+            slot_id,
+            interface,
+            bulk_in,
+            bulk_out,
+            tag: 0,
+            command_block: DmaBuffer::new(size_of::<CommandBlockWrapper>(), 64)?,
+            command_status: DmaBuffer::new(size_of::<CommandStatusWrapper>(), 64)?,
+            data: DmaBuffer::new(DATA_BUFFER_SIZE, DATA_BUFFER_SIZE)?,
+            num_blocks: 0,
+            block_size: 0,
+        })
+    }
+
+    /// Runs a SCSI command with up to `len` bytes of data in `self.data`, and returns the
+    /// number of bytes the device transferred.
+    ///
+    /// [BOT 1.0, 5.3 Data Transfer Conditions](https://www.usb.org/document-library/mass-storage-bulk-only-10)
+    fn command(
+        &mut self,
+        xhc: &mut Controller,
+        command: &[u8],
+        data_in: bool,
+        len: usize,
+    ) -> Result<usize> {
+        self.tag = self.tag.wrapping_add(1);
+        let mut block = [0; 16];
+        block[..command.len()].copy_from_slice(command);
+        let wrapper = CommandBlockWrapper {
+            signature: CBW_SIGNATURE,
+            tag: self.tag,
+            data_transfer_length: len as u32,
+            flags: if data_in { CBW_DATA_IN } else { 0 },
+            lun: 0,
+            command_length: command.len() as u8,
+            command: block,
+        };
+        unsafe {
+            self.command_block
+                .as_ptr::<CommandBlockWrapper>()
+                .write_unaligned(wrapper)
+        };
+        let (dci, size) = (self.bulk_out.dci(), size_of::<CommandBlockWrapper>());
+        if let Err(e) = xhc.transfer_and_wait(self.slot_id, dci, &self.command_block, size) {
+            self.reset_recovery(xhc)?;
+            return Err(e);
+        }
+
+        let mut transferred = 0;
+        if len > 0 {
+            let endpoint = if data_in { self.bulk_in } else { self.bulk_out };
+            match xhc.transfer_and_wait(self.slot_id, endpoint.dci(), &self.data, len) {
+                Ok(len) => transferred = len,
+                // A device stalls the data stage to end it early; the status still follows.
+                Err(_) => self.clear_halt(xhc, endpoint)?,
+            }
+        }
+
+        let status = match self.status(xhc) {
+            Ok(status) => status,
+            Err(_) => {
+                self.clear_halt(xhc, self.bulk_in)?;
+                self.status(xhc)?
+            }
+        };
+        if { status.signature } != CSW_SIGNATURE || { status.tag } != self.tag {
+            self.reset_recovery(xhc)?;
+            return Err("bad command status.");
+        }
+        match status.status {
+            CSW_PASSED => Ok(transferred),
+            CSW_FAILED => Err("SCSI command failed."),
+            _ => {
+                self.reset_recovery(xhc)?;
+                Err("mass storage phase error.")
+            }
+        }
+    }
+
+    fn status(&mut self, xhc: &mut Controller) -> Result<CommandStatusWrapper> {
+        let len = size_of::<CommandStatusWrapper>();
+        if xhc.transfer_and_wait(self.slot_id, self.bulk_in.dci(), &self.command_status, len)? < len
+        {
+            return Err("short command status.");
+        }
+        Ok(unsafe {
+            self.command_status
+                .as_ptr::<CommandStatusWrapper>()
+                .read_unaligned()
+        })
+    }
+
+    fn clear_halt(&self, xhc: &mut Controller, endpoint: EndpointDescriptor) -> Result<()> {
+        xhc.control(
+            self.slot_id,
+            SetupData::clear_endpoint_halt(endpoint.endpoint_address),
+        )
+    }
+
+    /// [BOT 1.0, 5.3.4 Reset Recovery](https://www.usb.org/document-library/mass-storage-bulk-only-10)
+    fn reset_recovery(&self, xhc: &mut Controller) -> Result<()> {
+        let setup = SetupData::new(
+            RequestType::CLASS | RequestType::INTERFACE,
+            BULK_ONLY_RESET,
+            0,
+            self.interface as u16,
+            0,
+        );
+        xhc.control(self.slot_id, setup)?;
+        self.clear_halt(xhc, self.bulk_in)?;
+        self.clear_halt(xhc, self.bulk_out)
+    }
+
+    /// Returns the vendor and product identification.
+    fn inquiry(&mut self, xhc: &mut Controller) -> Result<String> {
+        let command = [Operation::INQUIRY, 0, 0, 0, INQUIRY_LEN, 0];
+        let len = self.command(xhc, &command, true, INQUIRY_LEN as usize)?;
+        let data = &self.data.as_slice()[..len];
+        let text = |start: usize, end: usize| {
+            data.get(start..end)
+                .and_then(|bytes| core::str::from_utf8(bytes).ok())
+                .unwrap_or("")
+                .trim()
+        };
+        Ok(format!("{} {}", text(8, 16), text(16, 32)))
+    }
+
+    fn wait_ready(&mut self, xhc: &mut Controller) -> Result<()> {
+        for _ in 0..READY_ATTEMPTS {
+            let command = [Operation::TEST_UNIT_READY, 0, 0, 0, 0, 0];
+            if self.command(xhc, &command, false, 0).is_ok() {
+                return Ok(());
+            }
+            // Fetching the sense data clears the condition the command failed with.
+            let command = [Operation::REQUEST_SENSE, 0, 0, 0, SENSE_LEN, 0];
+            self.command(xhc, &command, true, SENSE_LEN as usize)?;
+        }
+        Err("mass storage device is not ready.")
+    }
+
+    /// Returns the number of blocks and the block size.
+    fn read_capacity(&mut self, xhc: &mut Controller) -> Result<(u64, usize)> {
+        let command = [Operation::READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        if self.command(xhc, &command, true, 8)? < 8 {
+            return Err("short capacity data.");
+        }
+        let data = self.data.as_slice();
+        let last_lba = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let block_size = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        // The device needs READ CAPACITY(16) and 16-byte reads and writes.
+        if last_lba == u32::MAX {
+            return Err("disk is too large.");
+        }
+        Ok((last_lba as u64 + 1, block_size as usize))
+    }
+
+    /// Returns the first block of a transfer of `len` bytes at `lba` if it fits on the disk.
+    fn check_range(&self, lba: u64, len: usize) -> Result<u64> {
+        if len % self.block_size != 0 {
+            return Err("buffer is not a multiple of the block size.");
+        }
+        match lba.checked_add((len / self.block_size) as u64) {
+            Some(end) if end <= self.num_blocks => Ok(lba),
+            _ => Err("block is out of range."),
+        }
+    }
+
+    fn chunk_size(&self) -> usize {
+        DATA_BUFFER_SIZE / self.block_size * self.block_size
+    }
+}
+
+/// READ(10) or WRITE(10) of `count` blocks at `lba`.
+fn read_write_10(operation: u8, lba: u64, count: usize) -> [u8; 10] {
+    let [l0, l1, l2, l3] = (lba as u32).to_be_bytes();
+    let [c0, c1] = (count as u16).to_be_bytes();
+    [operation, 0, l0, l1, l2, l3, 0, c0, c1, 0]
+}
+
+impl BlockDevice for UsbDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    fn read(&mut self, lba: u64, buffer: &mut [u8]) -> Result<()> {
+        let mut lba = self.check_range(lba, buffer.len())?;
+        usb::with_xhc(|xhc| {
+            for chunk in buffer.chunks_mut(self.chunk_size()) {
+                let count = chunk.len() / self.block_size;
+                let command = read_write_10(Operation::READ_10, lba, count);
+                if self.command(xhc, &command, true, chunk.len())? < chunk.len() {
+                    return Err("short read.");
+                }
+                chunk.copy_from_slice(&self.data.as_slice()[..chunk.len()]);
+                lba += count as u64;
+            }
+            Ok(())
+        })
+    }
+
+    fn write(&mut self, lba: u64, buffer: &[u8]) -> Result<()> {
+        let mut lba = self.check_range(lba, buffer.len())?;
+        usb::with_xhc(|xhc| {
+            for chunk in buffer.chunks(self.chunk_size()) {
+                let count = chunk.len() / self.block_size;
+                self.data.as_mut_slice()[..chunk.len()].copy_from_slice(chunk);
+                let command = read_write_10(Operation::WRITE_10, lba, count);
+                if self.command(xhc, &command, false, chunk.len())? < chunk.len() {
+                    return Err("short write.");
+                }
+                lba += count as u64;
+            }
+            Ok(())
+        })
+    }
+}
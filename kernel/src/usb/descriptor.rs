@@ -0,0 +1,288 @@
+use bit_field::BitField;
+
+/// [9.4 Standard Device Requests](https://www.usb.org/document-library/usb-20-specification)
+pub struct Request;
+
+#[allow(dead_code)]
+impl Request {
+    pub const GET_STATUS: u8 = 0;
+    pub const CLEAR_FEATURE: u8 = 1;
+    pub const SET_FEATURE: u8 = 3;
+    pub const GET_DESCRIPTOR: u8 = 6;
+    pub const SET_CONFIGURATION: u8 = 9;
+    pub const SET_INTERFACE: u8 = 11;
+}
+
+/// `bmRequestType` of a setup packet.
+pub struct RequestType;
+
+#[allow(dead_code)]
+impl RequestType {
+    pub const DEVICE_TO_HOST: u8 = 1 << 7;
+    pub const STANDARD: u8 = 0 << 5;
+    pub const CLASS: u8 = 1 << 5;
+    pub const DEVICE: u8 = 0;
+    pub const INTERFACE: u8 = 1;
+    pub const ENDPOINT: u8 = 2;
+    pub const OTHER: u8 = 3;
+}
+
+/// [9.3 USB Device Requests](https://www.usb.org/document-library/usb-20-specification)
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SetupData {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+
+impl SetupData {
+    pub fn new(request_type: u8, request: u8, value: u16, index: u16, length: u16) -> Self {
+        Self {
+            request_type,
+            request,
+            value,
+            index,
+            length,
+        }
+    }
+
+    pub fn get_descriptor(descriptor_type: u8, index: u8, length: u16) -> Self {
+        Self::new(
+            RequestType::DEVICE_TO_HOST | RequestType::STANDARD | RequestType::DEVICE,
+            Request::GET_DESCRIPTOR,
+            (descriptor_type as u16) << 8 | index as u16,
+            0,
+            length,
+        )
+    }
+
+    pub fn set_configuration(value: u8) -> Self {
+        Self::new(
+            RequestType::STANDARD | RequestType::DEVICE,
+            Request::SET_CONFIGURATION,
+            value as u16,
+            0,
+            0,
+        )
+    }
+
+    /// CLEAR_FEATURE(ENDPOINT_HALT) for the endpoint at `endpoint_address`.
+    pub fn clear_endpoint_halt(endpoint_address: u8) -> Self {
+        Self::new(
+            RequestType::STANDARD | RequestType::ENDPOINT,
+            Request::CLEAR_FEATURE,
+            0,
+            endpoint_address as u16,
+            0,
+        )
+    }
+
+    pub fn is_in(&self) -> bool {
+        self.request_type & RequestType::DEVICE_TO_HOST != 0
+    }
+
+    pub fn length(&self) -> u16 {
+        self.length
+    }
+
+    pub fn to_u64(self) -> u64 {
+        let mut data = 0u64;
+        data.set_bits(0..8, self.request_type as u64)
+            .set_bits(8..16, self.request as u64)
+            .set_bits(16..32, self.value as u64)
+            .set_bits(32..48, self.index as u64)
+            .set_bits(48..64, self.length as u64);
+        data
+    }
+}
+
+/// [9.4.3 Get Descriptor](https://www.usb.org/document-library/usb-20-specification)
+pub struct DescriptorType;
+
+#[allow(dead_code)]
+impl DescriptorType {
+    pub const DEVICE: u8 = 1;
+    pub const CONFIGURATION: u8 = 2;
+    pub const STRING: u8 = 3;
+    pub const INTERFACE: u8 = 4;
+    pub const ENDPOINT: u8 = 5;
+    pub const HID: u8 = 0x21;
+    pub const REPORT: u8 = 0x22;
+    pub const HUB: u8 = 0x29;
+    pub const SUPERSPEED_HUB: u8 = 0x2a;
+}
+
+/// Descriptors are read straight out of the bytes a device returned.
+pub trait Descriptor: Sized + Copy {
+    const TYPE: u8;
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < size_of::<Self>() || bytes[1] != Self::TYPE {
+            return None;
+        }
+        Some(unsafe { bytes.as_ptr().cast::<Self>().read_unaligned() })
+    }
+}
+
+/// [9.6.1 Device](https://www.usb.org/document-library/usb-20-specification)
+#[allow(dead_code)]
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceDescriptor {
+    pub length: u8,
+    pub descriptor_type: u8,
+    pub usb_release: u16,
+    pub device_class: u8,
+    pub device_sub_class: u8,
+    pub device_protocol: u8,
+    pub max_packet_size0: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub device_release: u16,
+    pub manufacturer: u8,
+    pub product: u8,
+    pub serial_number: u8,
+    pub num_configurations: u8,
+}
+
+impl Descriptor for DeviceDescriptor {
+    const TYPE: u8 = DescriptorType::DEVICE;
+}
+
+/// [9.6.3 Configuration](https://www.usb.org/document-library/usb-20-specification)
+#[allow(dead_code)]
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct ConfigurationDescriptor {
+    pub length: u8,
+    pub descriptor_type: u8,
+    pub total_length: u16,
+    pub num_interfaces: u8,
+    pub configuration_value: u8,
+    pub configuration: u8,
+    pub attributes: u8,
+    pub max_power: u8,
+}
+
+impl Descriptor for ConfigurationDescriptor {
+    const TYPE: u8 = DescriptorType::CONFIGURATION;
+}
+
+/// [9.6.5 Interface](https://www.usb.org/document-library/usb-20-specification)
+#[allow(dead_code)]
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct InterfaceDescriptor {
+    pub length: u8,
+    pub descriptor_type: u8,
+    pub interface_number: u8,
+    pub alternate_setting: u8,
+    pub num_endpoints: u8,
+    pub interface_class: u8,
+    pub interface_sub_class: u8,
+    pub interface_protocol: u8,
+    pub interface: u8,
+}
+
+impl Descriptor for InterfaceDescriptor {
+    const TYPE: u8 = DescriptorType::INTERFACE;
+}
+
+/// [9.6.6 Endpoint](https://www.usb.org/document-library/usb-20-specification)
+#[allow(dead_code)]
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct EndpointDescriptor {
+    pub length: u8,
+    pub descriptor_type: u8,
+    pub endpoint_address: u8,
+    pub attributes: u8,
+    pub max_packet_size: u16,
+    pub interval: u8,
+}
+
+impl Descriptor for EndpointDescriptor {
+    const TYPE: u8 = DescriptorType::ENDPOINT;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferType {
+    Control,
+    Isochronous,
+    Bulk,
+    Interrupt,
+}
+
+impl EndpointDescriptor {
+    pub fn number(&self) -> u8 {
+        self.endpoint_address.get_bits(0..4)
+    }
+
+    pub fn is_in(&self) -> bool {
+        self.endpoint_address.get_bit(7)
+    }
+
+    /// Device Context Index of the endpoint.
+    pub fn dci(&self) -> u8 {
+        self.number() * 2 + self.is_in() as u8
+    }
+
+    pub fn transfer_type(&self) -> TransferType {
+        match self.attributes.get_bits(0..2) {
+            0 => TransferType::Control,
+            1 => TransferType::Isochronous,
+            2 => TransferType::Bulk,
+            _ => TransferType::Interrupt,
+        }
+    }
+
+    /// Packet size without the additional transactions bits of high-bandwidth endpoints.
+    pub fn max_packet_size(&self) -> u16 {
+        { self.max_packet_size }.get_bits(0..11)
+    }
+}
+
+/// [HID 1.11, 6.2.1 HID Descriptor](https://www.usb.org/document-library/device-class-definition-hid-111)
+#[allow(dead_code)]
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct HidDescriptor {
+    pub length: u8,
+    pub descriptor_type: u8,
+    pub hid_release: u16,
+    pub country_code: u8,
+    pub num_descriptors: u8,
+    pub class_descriptor_type: u8,
+    pub class_descriptor_length: u16,
+}
+
+impl Descriptor for HidDescriptor {
+    const TYPE: u8 = DescriptorType::HID;
+}
+
+/// Walks the descriptors packed one after another in a configuration descriptor set.
+pub struct Descriptors<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Descriptors<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+}
+
+impl<'a> Iterator for Descriptors<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = *self.bytes.first()? as usize;
+        if len < 2 || len > self.bytes.len() {
+            return None;
+        }
+        let (descriptor, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(descriptor)
+    }
+}
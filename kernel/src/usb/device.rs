@@ -0,0 +1,134 @@
+use super::{
+    classdriver::ClassDriver,
+    descriptor::{DeviceDescriptor, EndpointDescriptor},
+};
+use crate::Result;
+use alloc::{boxed::Box, vec::Vec};
+use bit_field::BitField;
+
+/// Port speed IDs of the default xHCI speed mapping.
+///
+/// [7.2.2.1.1 Default USB Speed ID Mapping](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/extensible-host-controler-interface-usb-xhci.pdf)
+pub struct Speed;
+
+#[allow(dead_code)]
+impl Speed {
+    pub const FULL: u8 = 1;
+    pub const LOW: u8 = 2;
+    pub const HIGH: u8 = 3;
+    pub const SUPER: u8 = 4;
+    pub const SUPER_PLUS: u8 = 5;
+}
+
+/// Tiers of hubs a route string can describe.
+const MAX_HUB_DEPTH: usize = 5;
+
+/// Where a device is attached to the bus.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Route {
+    pub root_port: u8,
+    /// Hub port numbers below the root port, four bits per tier starting at the root.
+    pub route_string: u32,
+    /// Slot and port of the hub the device is plugged into, unless it is on a root port.
+    pub parent: Option<(u8, u8)>,
+    /// Slot and port of the high-speed hub whose transaction translator serves a low- or
+    /// full-speed device.
+    pub transaction_translator: Option<(u8, u8)>,
+}
+
+impl Route {
+    pub fn root(port: u8) -> Self {
+        Self {
+            root_port: port,
+            ..Default::default()
+        }
+    }
+
+    /// Number of hubs between the root hub and the device.
+    pub fn depth(&self) -> usize {
+        (0..MAX_HUB_DEPTH)
+            .take_while(|tier| self.route_string.get_bits(tier * 4..tier * 4 + 4) != 0)
+            .count()
+    }
+
+    /// Route of a device with `speed` on `port` of the hub that has this route.
+    pub fn child(&self, hub_slot: u8, hub_speed: u8, port: u8, speed: u8) -> Result<Self> {
+        let depth = self.depth();
+        if depth == MAX_HUB_DEPTH {
+            return Err("hubs are nested too deeply.");
+        }
+        let mut route_string = self.route_string;
+        // Ports above 15 cannot be expressed and are clamped, as the spec says.
+        route_string.set_bits(depth * 4..depth * 4 + 4, port.min(15) as u32);
+
+        let behind_tt = matches!(speed, Speed::LOW | Speed::FULL);
+        let transaction_translator = match hub_speed {
+            Speed::HIGH if behind_tt => Some((hub_slot, port)),
+            _ => self.transaction_translator,
+        };
+        Ok(Self {
+            root_port: self.root_port,
+            route_string,
+            parent: Some((hub_slot, port)),
+            transaction_translator,
+        })
+    }
+}
+
+/// A configured USB device and the class drivers bound to its interfaces.
+pub struct Device {
+    pub route: Route,
+    pub speed: u8,
+    pub descriptor: DeviceDescriptor,
+    /// A driver is taken out of its slot while one of its callbacks runs.
+    drivers: Vec<Option<Box<dyn ClassDriver>>>,
+    /// Index into `drivers` for each Device Context Index.
+    owners: [Option<usize>; 32],
+}
+
+impl Device {
+    pub fn new(route: Route, speed: u8, descriptor: DeviceDescriptor) -> Self {
+        Self {
+            route,
+            speed,
+            descriptor,
+            drivers: Vec::new(),
+            owners: [None; 32],
+        }
+    }
+
+    pub fn add_driver(&mut self, driver: Box<dyn ClassDriver>) {
+        let index = self.drivers.len();
+        driver
+            .endpoints()
+            .iter()
+            .for_each(|endpoint| self.owners[endpoint.dci() as usize] = Some(index));
+        self.drivers.push(Some(driver));
+    }
+
+    /// Endpoints of every bound driver.
+    pub fn endpoints(&self) -> Vec<EndpointDescriptor> {
+        self.drivers
+            .iter()
+            .flatten()
+            .flat_map(|driver| driver.endpoints().iter().copied())
+            .collect()
+    }
+
+    pub fn num_drivers(&self) -> usize {
+        self.drivers.len()
+    }
+
+    /// Driver that owns the endpoint at `dci`, by index.
+    pub fn owner(&self, dci: u8) -> Option<usize> {
+        self.owners.get(dci as usize).copied().flatten()
+    }
+
+    pub fn take_driver(&mut self, index: usize) -> Option<Box<dyn ClassDriver>> {
+        self.drivers.get_mut(index)?.take()
+    }
+
+    pub fn put_driver(&mut self, index: usize, driver: Box<dyn ClassDriver>) {
+        self.drivers[index] = Some(driver);
+    }
+}
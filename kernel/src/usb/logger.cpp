@@ -1,5 +0,0 @@
-#include "logger.hpp"
-
-int Log(LogLevel level, const char* format, ...) {
-  return 0;
-}
@@ -0,0 +1,49 @@
+use crate::{Result, paging};
+use alloc::alloc::{alloc_zeroed, dealloc};
+use core::{alloc::Layout, ptr::NonNull};
+
+/// Zeroed memory the controller can access by its physical address.
+///
+/// Heap memory lives in the direct map, which maps physical memory linearly, so every heap
+/// allocation is physically contiguous and its bus address is simply `virt_to_phys` of it.
+/// Aligning to the next power of two of the size keeps a buffer within one page as long as it
+/// fits in one, which satisfies the 64 KiB boundary rule for rings and contexts.
+pub struct DmaBuffer {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+impl DmaBuffer {
+    pub fn new(size: usize, align: usize) -> Result<Self> {
+        let align = align.max(size.next_power_of_two().min(4096)).max(64);
+        let layout = Layout::from_size_align(size.max(1), align).map_err(|_| "bad DMA layout.")?;
+        let ptr = NonNull::new(unsafe { alloc_zeroed(layout) }).ok_or("no enough memory.")?;
+        Ok(Self { ptr, layout })
+    }
+
+    pub fn phys(&self) -> u64 {
+        paging::virt_to_phys(self.ptr.as_ptr() as u64)
+    }
+
+    pub fn as_ptr<T>(&self) -> *mut T {
+        self.ptr.as_ptr() as *mut T
+    }
+
+    pub fn len(&self) -> usize {
+        self.layout.size()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len()) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len()) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
@@ -0,0 +1,790 @@
+use super::{
+    classdriver::{self, ClassDriver, Interface},
+    descriptor::{
+        ConfigurationDescriptor, Descriptor, DescriptorType, DeviceDescriptor, EndpointDescriptor,
+        SetupData, TransferType,
+    },
+    device::{Device, Route, Speed},
+    memory::DmaBuffer,
+};
+use crate::{Result, console::console, paging};
+use alloc::{collections::VecDeque, vec::Vec};
+use bit_field::BitField;
+use context::{DeviceContext, EndpointType, InputContext};
+use core::fmt::Write;
+use registers::*;
+use ring::{EventRing, Ring};
+use trb::{CompletionCode, Trb, TrbType};
+
+mod context;
+mod registers;
+mod ring;
+mod trb;
+
+const COMMAND_RING_LEN: usize = 32;
+const EVENT_RING_LEN: usize = 64;
+const TRANSFER_RING_LEN: usize = 32;
+
+/// Polling iterations before the controller is considered unresponsive.
+const TIMEOUT: usize = 10_000_000;
+
+/// Interrupt moderation interval in 250 ns units.
+const INTERRUPT_MODERATION: u32 = 4000;
+
+/// [7.1 USB Legacy Support Capability](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/extensible-host-controler-interface-usb-xhci.pdf)
+const CAPABILITY_LEGACY_SUPPORT: u32 = 1;
+const LEGACY_BIOS_OWNED: usize = 16;
+const LEGACY_OS_OWNED: usize = 24;
+
+/// State the controller and the driver keep for one enabled device slot.
+struct Slot {
+    device_context: DeviceContext,
+    input_context: InputContext,
+    rings: [Option<Ring>; 32],
+    device: Option<Device>,
+}
+
+/// [xHCI 1.2](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/extensible-host-controler-interface-usb-xhci.pdf)
+pub struct Controller {
+    registers: Registers,
+    max_ports: u8,
+    context_size: usize,
+    /// Device Context Base Address Array.
+    dcbaa: DmaBuffer,
+    _scratchpad_array: DmaBuffer,
+    _scratchpad_buffers: Vec<DmaBuffer>,
+    command_ring: Ring,
+    event_ring: EventRing,
+    /// Events that arrived while waiting for a command or a transfer to complete.
+    pending: VecDeque<Trb>,
+    slots: Vec<Option<Slot>>,
+}
+
+impl Controller {
+    /// Allocates the memory the controller needs. Nothing is written to its registers
+    /// until [`Controller::initialize`].
+    pub fn new(mmio_base: u64) -> Result<Self> {
+        let registers = Registers::new(mmio_base);
+        let max_slots = registers.max_slots() as usize;
+
+        let num_scratchpads = registers.max_scratchpad_buffers();
+        let scratchpad_array = DmaBuffer::new(num_scratchpads.max(1) * 8, 64)?;
+        let mut scratchpad_buffers = Vec::with_capacity(num_scratchpads);
+        for i in 0..num_scratchpads {
+            let buffer = DmaBuffer::new(4096, 4096)?;
+            unsafe { scratchpad_array.as_ptr::<u64>().add(i).write(buffer.phys()) };
+            scratchpad_buffers.push(buffer);
+        }
+
+        let dcbaa = DmaBuffer::new((max_slots + 1) * 8, 64)?;
+        if num_scratchpads > 0 {
+            unsafe { dcbaa.as_ptr::<u64>().write(scratchpad_array.phys()) };
+        }
+
+        Ok(Self {
+            max_ports: registers.max_ports(),
+            context_size: if registers.context_size_64() { 64 } else { 32 },
+            dcbaa,
+            _scratchpad_array: scratchpad_array,
+            _scratchpad_buffers: scratchpad_buffers,
+            command_ring: Ring::new(COMMAND_RING_LEN)?,
+            event_ring: EventRing::new(EVENT_RING_LEN)?,
+            pending: VecDeque::new(),
+            slots: (0..=max_slots).map(|_| None).collect(),
+            registers,
+        })
+    }
+
+    fn wait(&self, done: impl Fn(&Registers) -> bool, error: &'static str) -> Result<()> {
+        for _ in 0..TIMEOUT {
+            if done(&self.registers) {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(error)
+    }
+
+    /// Takes the controller over from the firmware if the firmware still owns it.
+    fn request_ownership(&self) -> Result<()> {
+        let Some(mut offset) = self.registers.extended_capabilities() else {
+            return Ok(());
+        };
+        loop {
+            let capability = self.registers.capability(offset);
+            if capability.get_bits(0..8) == CAPABILITY_LEGACY_SUPPORT {
+                if !capability.get_bit(LEGACY_BIOS_OWNED) {
+                    return Ok(());
+                }
+                let mut request = capability;
+                request.set_bit(LEGACY_OS_OWNED, true);
+                self.registers.set_capability(offset, request);
+                return self.wait(
+                    |r| !r.capability(offset).get_bit(LEGACY_BIOS_OWNED),
+                    "firmware did not release xHC.",
+                );
+            }
+            match capability.get_bits(8..16) {
+                0 => return Ok(()),
+                next => offset += next as u64 * 4,
+            }
+        }
+    }
+
+    pub fn initialize(&mut self) -> Result<()> {
+        self.request_ownership()?;
+
+        let r = &self.registers;
+        let mut command = r.operational(USBCMD);
+        command
+            .set_bit(USBCMD_RUN_STOP, false)
+            .set_bit(USBCMD_INTERRUPTER_ENABLE, false);
+        r.set_operational(USBCMD, command);
+        self.wait(
+            |r| r.operational(USBSTS).get_bit(USBSTS_HC_HALTED),
+            "xHC did not halt.",
+        )?;
+
+        r.set_operational(
+            USBCMD,
+            *r.operational(USBCMD).set_bit(USBCMD_HC_RESET, true),
+        );
+        self.wait(
+            |r| {
+                !r.operational(USBCMD).get_bit(USBCMD_HC_RESET)
+                    && !r.operational(USBSTS).get_bit(USBSTS_CONTROLLER_NOT_READY)
+            },
+            "xHC did not finish reset.",
+        )?;
+        if !r.operational(PAGESIZE).get_bit(0) {
+            return Err("xHC does not support 4 KiB pages.");
+        }
+
+        let r = &self.registers;
+        r.set_operational(CONFIG, r.max_slots() as u32);
+        r.set_operational64(DCBAAP, self.dcbaa.phys());
+        r.set_operational64(CRCR, self.command_ring.dequeue_pointer());
+
+        self.event_ring.install(r);
+        r.set_runtime(IMOD, INTERRUPT_MODERATION);
+        let mut iman = r.runtime(IMAN);
+        iman.set_bit(IMAN_INTERRUPT_PENDING, true)
+            .set_bit(IMAN_INTERRUPT_ENABLE, true);
+        r.set_runtime(IMAN, iman);
+        r.set_operational(
+            USBCMD,
+            *r.operational(USBCMD)
+                .set_bit(USBCMD_INTERRUPTER_ENABLE, true),
+        );
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        let r = &self.registers;
+        r.set_operational(
+            USBCMD,
+            *r.operational(USBCMD).set_bit(USBCMD_RUN_STOP, true),
+        );
+        self.wait(
+            |r| !r.operational(USBSTS).get_bit(USBSTS_HC_HALTED),
+            "xHC did not start.",
+        )
+    }
+
+    /// Enumerates the devices that are already connected to root ports. Devices that are
+    /// plugged in later are picked up from port status change events.
+    pub fn configure_port(&mut self) {
+        for port in 1..=self.max_ports {
+            if self.registers.portsc(port) & PortSc::CURRENT_CONNECT_STATUS == 0 {
+                continue;
+            }
+            if let Err(e) = self.attach_root_port(port) {
+                println!("usb: port {}: {}", port, e);
+            }
+        }
+    }
+
+    pub fn process_event_ring_has_front(&self) -> bool {
+        !self.pending.is_empty() || self.event_ring.front().is_some()
+    }
+
+    pub fn process_event(&mut self) -> Result<()> {
+        let r = &self.registers;
+        r.set_operational(USBSTS, 1 << USBSTS_EVENT_INTERRUPT);
+        r.set_runtime(IMAN, *r.runtime(IMAN).set_bit(IMAN_INTERRUPT_PENDING, true));
+
+        let event = match self.pending.pop_front() {
+            Some(event) => event,
+            None => {
+                let Some(event) = self.event_ring.front() else {
+                    return Ok(());
+                };
+                self.event_ring.pop(&self.registers);
+                event
+            }
+        };
+
+        match event.trb_type() {
+            TrbType::TRANSFER_EVENT => self.on_transfer_event(&event),
+            TrbType::PORT_STATUS_CHANGE_EVENT => self.on_port_status_change(event.port_id()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Pops events until one satisfies `matches`. The others are kept for
+    /// [`Controller::process_event`].
+    fn wait_event(&mut self, matches: impl Fn(&Trb) -> bool) -> Result<Trb> {
+        for _ in 0..TIMEOUT {
+            let Some(event) = self.event_ring.front() else {
+                core::hint::spin_loop();
+                continue;
+            };
+            self.event_ring.pop(&self.registers);
+            if matches(&event) {
+                return Ok(event);
+            }
+            self.pending.push_back(event);
+        }
+        Err("xHC did not answer.")
+    }
+
+    fn command(&mut self, trb: Trb) -> Result<Trb> {
+        let phys = self.command_ring.push(trb);
+        self.registers.ring_doorbell(0, 0);
+        let event = self.wait_event(|event| {
+            event.trb_type() == TrbType::COMMAND_COMPLETION_EVENT && event.parameter == phys
+        })?;
+        if event.completion_code() != CompletionCode::SUCCESS {
+            return Err("xHC command failed.");
+        }
+        Ok(event)
+    }
+
+    fn slot(&mut self, slot_id: u8) -> Result<&mut Slot> {
+        self.slots
+            .get_mut(slot_id as usize)
+            .and_then(|slot| slot.as_mut())
+            .ok_or("no such device slot.")
+    }
+
+    fn ring(&mut self, slot_id: u8, dci: u8) -> Result<&mut Ring> {
+        self.slot(slot_id)?.rings[dci as usize]
+            .as_mut()
+            .ok_or("endpoint is not configured.")
+    }
+
+    /// Clears the halted state of an endpoint after an error and skips the failed TRBs.
+    fn recover_endpoint(&mut self, slot_id: u8, dci: u8) -> Result<()> {
+        self.command(Trb::reset_endpoint(slot_id, dci))?;
+        let dequeue = self.ring(slot_id, dci)?.dequeue_pointer();
+        self.command(Trb::set_tr_dequeue_pointer(slot_id, dci, dequeue))?;
+        Ok(())
+    }
+
+    /// Runs a control transfer on the default endpoint and returns the number of bytes
+    /// transferred in the data stage.
+    fn control_transfer(
+        &mut self,
+        slot_id: u8,
+        setup: SetupData,
+        buffer: Option<&DmaBuffer>,
+    ) -> Result<usize> {
+        let ring = self.ring(slot_id, 1)?;
+        ring.push(Trb::setup_stage(&setup));
+        let data = buffer.map(|buffer| {
+            ring.push(Trb::data_stage(
+                buffer.phys(),
+                setup.length as usize,
+                setup.is_in(),
+            ))
+        });
+        let status = ring.push(Trb::status_stage(data.is_none() || !setup.is_in()));
+        self.registers.ring_doorbell(slot_id, 1);
+
+        let mut len = buffer.map_or(0, |_| setup.length as usize);
+        loop {
+            let event = self.wait_event(|event| {
+                event.trb_type() == TrbType::TRANSFER_EVENT
+                    && event.slot_id() == slot_id
+                    && event.endpoint_id() == 1
+            })?;
+            match event.completion_code() {
+                CompletionCode::SUCCESS if event.parameter == status => return Ok(len),
+                CompletionCode::SUCCESS => {}
+                CompletionCode::SHORT_PACKET if Some(event.parameter) == data => {
+                    len -= event.residual_length().min(len);
+                }
+                _ => {
+                    self.recover_endpoint(slot_id, 1)?;
+                    return Err("control transfer failed.");
+                }
+            }
+        }
+    }
+
+    /// Sends a request without a data stage to the default endpoint.
+    pub fn control(&mut self, slot_id: u8, setup: SetupData) -> Result<()> {
+        self.control_transfer(slot_id, setup, None).map(|_| ())
+    }
+
+    /// Sends a device-to-host request to the default endpoint and returns the data.
+    pub fn control_in(&mut self, slot_id: u8, setup: SetupData) -> Result<Vec<u8>> {
+        let buffer = DmaBuffer::new(setup.length as usize, 64)?;
+        let len = self.control_transfer(slot_id, setup, Some(&buffer))?;
+        Ok(buffer.as_slice()[..len].to_vec())
+    }
+
+    /// Queues a transfer of `len` bytes at `buffer` on the endpoint at `dci`. Completion is
+    /// reported to the class driver that owns the endpoint.
+    pub fn transfer(&mut self, slot_id: u8, dci: u8, buffer: &DmaBuffer, len: usize) -> Result<()> {
+        self.ring(slot_id, dci)?
+            .push(Trb::normal(buffer.phys(), len));
+        self.registers.ring_doorbell(slot_id, dci);
+        Ok(())
+    }
+
+    /// Runs a transfer of `len` bytes at `buffer` on the endpoint at `dci` and waits for it,
+    /// returning the number of bytes transferred. The owner of the endpoint is not notified,
+    /// so it must not have other transfers queued there.
+    pub fn transfer_and_wait(
+        &mut self,
+        slot_id: u8,
+        dci: u8,
+        buffer: &DmaBuffer,
+        len: usize,
+    ) -> Result<usize> {
+        let trb = self
+            .ring(slot_id, dci)?
+            .push(Trb::normal(buffer.phys(), len));
+        self.registers.ring_doorbell(slot_id, dci);
+        let event = self.wait_event(|event| {
+            event.trb_type() == TrbType::TRANSFER_EVENT
+                && event.slot_id() == slot_id
+                && event.endpoint_id() == dci
+                && event.parameter == trb
+        })?;
+        match event.completion_code() {
+            CompletionCode::SUCCESS | CompletionCode::SHORT_PACKET => {
+                Ok(len - event.residual_length().min(len))
+            }
+            _ => {
+                self.recover_endpoint(slot_id, dci)?;
+                Err("transfer failed.")
+            }
+        }
+    }
+
+    fn on_transfer_event(&mut self, event: &Trb) -> Result<()> {
+        let (slot_id, dci) = (event.slot_id(), event.endpoint_id());
+        let transferred = match event.completion_code() {
+            CompletionCode::SUCCESS | CompletionCode::SHORT_PACKET => {
+                // Transfer TRBs keep the requested length, which the event does not repeat.
+                let trb = unsafe { (paging::phys_to_virt(event.parameter) as *const Trb).read() };
+                let requested = trb.status.get_bits(0..17) as usize;
+                Ok(requested - event.residual_length().min(requested))
+            }
+            _ => {
+                self.recover_endpoint(slot_id, dci)?;
+                Err("transfer failed.")
+            }
+        };
+
+        let device = self.slot(slot_id)?.device.as_ref().ok_or("no device.")?;
+        let Some(index) = device.owner(dci) else {
+            return Ok(());
+        };
+        self.with_driver(slot_id, index, |driver, xhc| {
+            driver.on_transfer(xhc, slot_id, dci, transferred)
+        })
+    }
+
+    /// Runs `f` on a class driver of the device in `slot_id`. The driver is taken out of the
+    /// device meanwhile so that it can use the controller.
+    fn with_driver(
+        &mut self,
+        slot_id: u8,
+        index: usize,
+        f: impl FnOnce(&mut dyn ClassDriver, &mut Self) -> Result<()>,
+    ) -> Result<()> {
+        let device = self.slot(slot_id)?.device.as_mut().ok_or("no device.")?;
+        let Some(mut driver) = device.take_driver(index) else {
+            return Ok(());
+        };
+        let result = f(driver.as_mut(), self);
+        // The device may have gone away while the driver ran.
+        if let Some(device) = self
+            .slot(slot_id)
+            .ok()
+            .and_then(|slot| slot.device.as_mut())
+        {
+            device.put_driver(index, driver);
+        }
+        result
+    }
+
+    /// Runs [`ClassDriver::on_timer`] for the drivers of every device and returns the first
+    /// error.
+    pub fn on_timer(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for slot_id in 1..self.slots.len() {
+            let slot_id = slot_id as u8;
+            let mut index = 0;
+            while let Some(device) = self
+                .slot(slot_id)
+                .ok()
+                .and_then(|slot| slot.device.as_ref())
+            {
+                if index >= device.num_drivers() {
+                    break;
+                }
+                result = result.and(
+                    self.with_driver(slot_id, index, |driver, xhc| driver.on_timer(xhc, slot_id)),
+                );
+                index += 1;
+            }
+        }
+        result
+    }
+
+    fn on_port_status_change(&mut self, port: u8) -> Result<()> {
+        let portsc = self.registers.portsc(port);
+        let attached = self.slot_on_root_port(port);
+        match (portsc & PortSc::CURRENT_CONNECT_STATUS != 0, attached) {
+            (true, None) => self.attach_root_port(port).map(|_| ()),
+            (false, Some(slot_id)) => {
+                self.registers
+                    .set_portsc(port, portsc & PortSc::PRESERVE | PortSc::CHANGE_BITS);
+                self.detach(slot_id)
+            }
+            _ => {
+                self.registers
+                    .set_portsc(port, portsc & PortSc::PRESERVE | PortSc::CHANGE_BITS);
+                Ok(())
+            }
+        }
+    }
+
+    fn slot_on_root_port(&self, port: u8) -> Option<u8> {
+        self.find_slot(|route| route.parent.is_none() && route.root_port == port)
+    }
+
+    fn find_slot(&self, matches: impl Fn(&Route) -> bool) -> Option<u8> {
+        self.slots.iter().enumerate().find_map(|(slot_id, slot)| {
+            let device = slot.as_ref()?.device.as_ref()?;
+            matches(&device.route).then_some(slot_id as u8)
+        })
+    }
+
+    /// Resets a root port unless it enabled itself, which USB3 ports do, and returns the
+    /// speed of the device on it.
+    fn reset_root_port(&mut self, port: u8) -> Result<u8> {
+        let portsc = self.registers.portsc(port);
+        if portsc & PortSc::PORT_ENABLED == 0 {
+            self.registers
+                .set_portsc(port, portsc & PortSc::PRESERVE | PortSc::PORT_RESET);
+            self.wait(
+                |r| r.portsc(port) & PortSc::PORT_RESET_CHANGE != 0,
+                "port reset did not finish.",
+            )?;
+        }
+        let portsc = self.registers.portsc(port);
+        self.registers
+            .set_portsc(port, portsc & PortSc::PRESERVE | PortSc::CHANGE_BITS);
+        if portsc & PortSc::PORT_ENABLED == 0 {
+            return Err("port is not enabled.");
+        }
+        Ok(PortSc::speed(portsc))
+    }
+
+    fn attach_root_port(&mut self, port: u8) -> Result<u8> {
+        let speed = self.reset_root_port(port)?;
+        self.attach(Route::root(port), speed)
+    }
+
+    /// Addresses and configures a device that was just reset.
+    fn attach(&mut self, route: Route, speed: u8) -> Result<u8> {
+        let slot_id = self.address_device(&route, speed)?;
+        if let Err(e) = self.configure_device(slot_id, route, speed) {
+            _ = self.detach(slot_id);
+            return Err(e);
+        }
+        Ok(slot_id)
+    }
+
+    /// Attaches the device with `speed` that a hub has just reset on its `port`.
+    pub fn attach_to_hub(&mut self, hub_slot: u8, port: u8, speed: u8) -> Result<u8> {
+        let hub = self.device(hub_slot)?;
+        let route = hub.route.child(hub_slot, hub.speed, port, speed)?;
+        self.attach(route, speed)
+    }
+
+    /// Detaches the device that was on `port` of a hub, if any.
+    pub fn detach_from_hub(&mut self, hub_slot: u8, port: u8) -> Result<()> {
+        match self.find_slot(|route| route.parent == Some((hub_slot, port))) {
+            Some(slot_id) => self.detach(slot_id),
+            None => Ok(()),
+        }
+    }
+
+    pub fn device(&mut self, slot_id: u8) -> Result<&Device> {
+        self.slot(slot_id)?.device.as_ref().ok_or("no device.")
+    }
+
+    /// Tells the controller that the device in `slot_id` is a hub, which it needs to know
+    /// before it can address devices behind it.
+    pub fn set_hub(
+        &mut self,
+        slot_id: u8,
+        num_ports: u8,
+        think_time: u8,
+        multi_tt: bool,
+    ) -> Result<()> {
+        let input = &mut self.slot(slot_id)?.input_context;
+        input.clear_flags();
+        input.add(0);
+        input
+            .slot()
+            .set_hub(true)
+            .set_number_of_ports(num_ports)
+            .set_tt_think_time(think_time)
+            .set_multi_tt(multi_tt);
+        let input_context = input.phys();
+        self.command(Trb::configure_endpoint(input_context, slot_id))?;
+        Ok(())
+    }
+
+    /// Releases the slot of a device that was unplugged or failed to configure, along with
+    /// every device behind it if it is a hub.
+    fn detach(&mut self, slot_id: u8) -> Result<()> {
+        while let Some(child) =
+            self.find_slot(|route| route.parent.is_some_and(|(hub, _)| hub == slot_id))
+        {
+            self.detach(child)?;
+        }
+        self.command(Trb::disable_slot(slot_id))?;
+        unsafe { self.dcbaa.as_ptr::<u64>().add(slot_id as usize).write(0) };
+        self.slots[slot_id as usize] = None;
+        self.pending.retain(|event| {
+            event.trb_type() != TrbType::TRANSFER_EVENT || event.slot_id() != slot_id
+        });
+        println!("usb: slot {} detached", slot_id);
+        Ok(())
+    }
+
+    /// Enables a device slot for the device that was just reset at `route` and assigns it
+    /// an address.
+    fn address_device(&mut self, route: &Route, speed: u8) -> Result<u8> {
+        let slot_id = self.command(Trb::enable_slot())?.slot_id();
+        let mut slot = Slot {
+            device_context: DeviceContext::new(self.context_size)?,
+            input_context: InputContext::new(self.context_size)?,
+            rings: [const { None }; 32],
+            device: None,
+        };
+        let ring = Ring::new(TRANSFER_RING_LEN)?;
+
+        let input = &mut slot.input_context;
+        input.add(0);
+        input.add(1);
+        input
+            .slot()
+            .set_route_string(route.route_string)
+            .set_speed(speed)
+            .set_context_entries(1)
+            .set_root_hub_port(route.root_port);
+        if let Some((hub_slot, hub_port)) = route.transaction_translator {
+            input.slot().set_tt_hub_slot(hub_slot).set_tt_port(hub_port);
+        }
+        input
+            .endpoint(1)
+            .set_endpoint_type(EndpointType::Control)
+            .set_max_packet_size(default_max_packet_size(speed))
+            .set_error_count(3)
+            .set_dequeue_pointer(ring.dequeue_pointer())
+            .set_average_trb_length(8);
+        slot.rings[1] = Some(ring);
+
+        let input_context = slot.input_context.phys();
+        unsafe {
+            self.dcbaa
+                .as_ptr::<u64>()
+                .add(slot_id as usize)
+                .write(slot.device_context.phys())
+        };
+        self.slots[slot_id as usize] = Some(slot);
+
+        if let Err(e) = self.command(Trb::address_device(input_context, slot_id, false)) {
+            _ = self.detach(slot_id);
+            return Err(e);
+        }
+        Ok(slot_id)
+    }
+
+    fn get_descriptor(&mut self, slot_id: u8, descriptor_type: u8, len: u16) -> Result<Vec<u8>> {
+        self.control_in(slot_id, SetupData::get_descriptor(descriptor_type, 0, len))
+    }
+
+    /// Reads the descriptors of an addressed device, binds class drivers to its interfaces
+    /// and selects its first configuration.
+    fn configure_device(&mut self, slot_id: u8, route: Route, speed: u8) -> Result<()> {
+        let head = self.get_descriptor(slot_id, DescriptorType::DEVICE, 8)?;
+        let max_packet_size = match (speed, head.get(7).copied()) {
+            (_, None) => return Err("short device descriptor."),
+            (Speed::SUPER | Speed::SUPER_PLUS, Some(exponent)) => 1 << exponent,
+            (_, Some(size)) => size as u16,
+        };
+        if max_packet_size != default_max_packet_size(speed) {
+            let input = &mut self.slot(slot_id)?.input_context;
+            input.clear_flags();
+            input.add(1);
+            input.endpoint(1).set_max_packet_size(max_packet_size);
+            let input_context = input.phys();
+            self.command(Trb::evaluate_context(input_context, slot_id))?;
+        }
+
+        let bytes = self.get_descriptor(
+            slot_id,
+            DescriptorType::DEVICE,
+            size_of::<DeviceDescriptor>() as u16,
+        )?;
+        let descriptor = DeviceDescriptor::from_bytes(&bytes).ok_or("bad device descriptor.")?;
+        let mut device = Device::new(route, speed, descriptor);
+
+        let bytes = self.get_descriptor(
+            slot_id,
+            DescriptorType::CONFIGURATION,
+            size_of::<ConfigurationDescriptor>() as u16,
+        )?;
+        let configuration =
+            ConfigurationDescriptor::from_bytes(&bytes).ok_or("bad configuration descriptor.")?;
+        let bytes = self.get_descriptor(
+            slot_id,
+            DescriptorType::CONFIGURATION,
+            configuration.total_length,
+        )?;
+
+        Interface::parse_all(&bytes)
+            .iter()
+            .filter_map(classdriver::probe)
+            .for_each(|driver| device.add_driver(driver));
+
+        println!(
+            "usb: slot {} port {} route {:05x}: {:04x}:{:04x}, {} driver(s)",
+            slot_id,
+            route.root_port,
+            route.route_string,
+            { device.descriptor.vendor_id },
+            { device.descriptor.product_id },
+            device.num_drivers()
+        );
+
+        let endpoints = device.endpoints();
+        self.configure_endpoints(slot_id, speed, &endpoints)?;
+        self.control(
+            slot_id,
+            SetupData::set_configuration(configuration.configuration_value),
+        )?;
+
+        let num_drivers = device.num_drivers();
+        self.slot(slot_id)?.device = Some(device);
+        for index in 0..num_drivers {
+            self.with_driver(slot_id, index, |driver, xhc| {
+                if let Err(e) = driver.start(xhc, slot_id) {
+                    println!("usb: slot {}: {}: {}", slot_id, driver.name(), e);
+                }
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Adds a transfer ring for each endpoint and issues a Configure Endpoint command.
+    fn configure_endpoints(
+        &mut self,
+        slot_id: u8,
+        speed: u8,
+        endpoints: &[EndpointDescriptor],
+    ) -> Result<()> {
+        let slot = self.slot(slot_id)?;
+        let input = &mut slot.input_context;
+        input.clear_flags();
+        input.add(0);
+
+        let mut last_dci = 1;
+        for endpoint in endpoints {
+            let dci = endpoint.dci();
+            let ring = Ring::new(TRANSFER_RING_LEN)?;
+            let max_packet_size = endpoint.max_packet_size();
+            let (endpoint_type, average_trb_length) =
+                match (endpoint.transfer_type(), endpoint.is_in()) {
+                    (TransferType::Interrupt, true) => (EndpointType::InterruptIn, max_packet_size),
+                    (TransferType::Interrupt, false) => {
+                        (EndpointType::InterruptOut, max_packet_size)
+                    }
+                    (TransferType::Bulk, true) => (EndpointType::BulkIn, 3072),
+                    (TransferType::Bulk, false) => (EndpointType::BulkOut, 3072),
+                    (TransferType::Isochronous, true) => (EndpointType::IsochIn, max_packet_size),
+                    (TransferType::Isochronous, false) => (EndpointType::IsochOut, max_packet_size),
+                    (TransferType::Control, _) => (EndpointType::Control, 8),
+                };
+
+            input.add(dci);
+            let context = input.endpoint(dci);
+            context
+                .set_endpoint_type(endpoint_type)
+                .set_max_packet_size(max_packet_size)
+                .set_interval(interval(endpoint, speed))
+                .set_max_burst_size(0)
+                .set_error_count(
+                    if endpoint_type == EndpointType::IsochIn
+                        || endpoint_type == EndpointType::IsochOut
+                    {
+                        0
+                    } else {
+                        3
+                    },
+                )
+                .set_dequeue_pointer(ring.dequeue_pointer())
+                .set_average_trb_length(average_trb_length);
+            if matches!(
+                endpoint.transfer_type(),
+                TransferType::Interrupt | TransferType::Isochronous
+            ) {
+                context.set_max_esit_payload(max_packet_size);
+            }
+            slot.rings[dci as usize] = Some(ring);
+            last_dci = last_dci.max(dci);
+        }
+        input.slot().set_context_entries(last_dci);
+
+        let input_context = input.phys();
+        self.command(Trb::configure_endpoint(input_context, slot_id))?;
+        Ok(())
+    }
+}
+
+/// Max packet size of the default endpoint until the device descriptor says otherwise.
+fn default_max_packet_size(speed: u8) -> u16 {
+    match speed {
+        Speed::LOW | Speed::FULL => 8,
+        Speed::HIGH => 64,
+        _ => 512,
+    }
+}
+
+/// Endpoint context interval, as a power of two in 125 us units.
+///
+/// [6.2.3.6 Interval](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/extensible-host-controler-interface-usb-xhci.pdf)
+fn interval(endpoint: &EndpointDescriptor, speed: u8) -> u8 {
+    let interval = endpoint.interval;
+    match (endpoint.transfer_type(), speed) {
+        (TransferType::Control | TransferType::Bulk, _) => 0,
+        // bInterval counts frames of 1 ms.
+        (TransferType::Interrupt, Speed::LOW | Speed::FULL) => {
+            (interval.max(1).ilog2() as u8 + 3).clamp(3, 10)
+        }
+        (TransferType::Isochronous, Speed::FULL) => interval.clamp(1, 16) + 2,
+        // bInterval is already an exponent in 125 us units, plus one.
+        _ => interval.clamp(1, 16) - 1,
+    }
+}
@@ -0,0 +1,199 @@
+use crate::{Result, usb::memory::DmaBuffer};
+use bit_field::BitField;
+
+/// [6.2.2 Slot Context](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/extensible-host-controler-interface-usb-xhci.pdf)
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SlotContext([u32; 8]);
+
+#[allow(dead_code)]
+impl SlotContext {
+    pub fn set_route_string(&mut self, route: u32) -> &mut Self {
+        self.0[0].set_bits(0..20, route);
+        self
+    }
+
+    pub fn set_speed(&mut self, speed: u8) -> &mut Self {
+        self.0[0].set_bits(20..24, speed as u32);
+        self
+    }
+
+    pub fn set_multi_tt(&mut self, multi_tt: bool) -> &mut Self {
+        self.0[0].set_bit(25, multi_tt);
+        self
+    }
+
+    pub fn set_hub(&mut self, hub: bool) -> &mut Self {
+        self.0[0].set_bit(26, hub);
+        self
+    }
+
+    /// Index of the last valid endpoint context.
+    pub fn set_context_entries(&mut self, entries: u8) -> &mut Self {
+        self.0[0].set_bits(27..32, entries as u32);
+        self
+    }
+
+    pub fn context_entries(&self) -> u8 {
+        self.0[0].get_bits(27..32) as u8
+    }
+
+    pub fn set_root_hub_port(&mut self, port: u8) -> &mut Self {
+        self.0[1].set_bits(16..24, port as u32);
+        self
+    }
+
+    pub fn set_number_of_ports(&mut self, ports: u8) -> &mut Self {
+        self.0[1].set_bits(24..32, ports as u32);
+        self
+    }
+
+    /// Slot of the high-speed hub whose transaction translator serves a low/full-speed device.
+    pub fn set_tt_hub_slot(&mut self, slot_id: u8) -> &mut Self {
+        self.0[2].set_bits(0..8, slot_id as u32);
+        self
+    }
+
+    pub fn set_tt_port(&mut self, port: u8) -> &mut Self {
+        self.0[2].set_bits(8..16, port as u32);
+        self
+    }
+
+    pub fn set_tt_think_time(&mut self, think_time: u8) -> &mut Self {
+        self.0[2].set_bits(16..18, think_time as u32);
+        self
+    }
+
+    pub fn usb_device_address(&self) -> u8 {
+        self.0[3].get_bits(0..8) as u8
+    }
+}
+
+/// [6.2.3 Endpoint Context](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/extensible-host-controler-interface-usb-xhci.pdf)
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EndpointContext([u32; 8]);
+
+#[allow(dead_code)]
+impl EndpointContext {
+    pub fn set_interval(&mut self, interval: u8) -> &mut Self {
+        self.0[0].set_bits(16..24, interval as u32);
+        self
+    }
+
+    pub fn set_error_count(&mut self, count: u8) -> &mut Self {
+        self.0[1].set_bits(1..3, count as u32);
+        self
+    }
+
+    pub fn set_endpoint_type(&mut self, endpoint_type: EndpointType) -> &mut Self {
+        self.0[1].set_bits(3..6, endpoint_type as u32);
+        self
+    }
+
+    pub fn set_max_burst_size(&mut self, size: u8) -> &mut Self {
+        self.0[1].set_bits(8..16, size as u32);
+        self
+    }
+
+    pub fn set_max_packet_size(&mut self, size: u16) -> &mut Self {
+        self.0[1].set_bits(16..32, size as u32);
+        self
+    }
+
+    /// `dequeue` carries the dequeue cycle state in bit 0.
+    pub fn set_dequeue_pointer(&mut self, dequeue: u64) -> &mut Self {
+        self.0[2] = dequeue as u32;
+        self.0[3] = (dequeue >> 32) as u32;
+        self
+    }
+
+    pub fn set_average_trb_length(&mut self, len: u16) -> &mut Self {
+        self.0[4].set_bits(0..16, len as u32);
+        self
+    }
+
+    pub fn set_max_esit_payload(&mut self, payload: u16) -> &mut Self {
+        self.0[4].set_bits(16..32, payload as u32);
+        self
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndpointType {
+    IsochOut = 1,
+    BulkOut = 2,
+    InterruptOut = 3,
+    Control = 4,
+    IsochIn = 5,
+    BulkIn = 6,
+    InterruptIn = 7,
+}
+
+/// Contexts are 32 or 64 bytes depending on the controller. With 64-byte contexts only the
+/// first 32 bytes are defined, so both layouts are accessed through the same structs.
+fn context_at<T>(buffer: &DmaBuffer, context_size: usize, index: usize) -> *mut T {
+    unsafe { buffer.as_ptr::<u8>().add(context_size * index).cast::<T>() }
+}
+
+/// Output context the controller keeps the state of a device slot in.
+///
+/// [6.2.1 Device Context](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/extensible-host-controler-interface-usb-xhci.pdf)
+pub struct DeviceContext {
+    buffer: DmaBuffer,
+}
+
+impl DeviceContext {
+    pub fn new(context_size: usize) -> Result<Self> {
+        Ok(Self {
+            buffer: DmaBuffer::new(context_size * 32, 64)?,
+        })
+    }
+
+    pub fn phys(&self) -> u64 {
+        self.buffer.phys()
+    }
+}
+
+/// Input Control Context followed by a Slot Context and 31 Endpoint Contexts.
+///
+/// [6.2.5 Input Context](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/extensible-host-controler-interface-usb-xhci.pdf)
+pub struct InputContext {
+    buffer: DmaBuffer,
+    context_size: usize,
+}
+
+impl InputContext {
+    pub fn new(context_size: usize) -> Result<Self> {
+        Ok(Self {
+            buffer: DmaBuffer::new(context_size * 33, 64)?,
+            context_size,
+        })
+    }
+
+    pub fn phys(&self) -> u64 {
+        self.buffer.phys()
+    }
+
+    /// Clears the add and drop flags so the context can be reused for another command.
+    pub fn clear_flags(&mut self) {
+        let control = unsafe { &mut *context_at::<[u32; 8]>(&self.buffer, self.context_size, 0) };
+        control[0] = 0;
+        control[1] = 0;
+    }
+
+    /// Marks the context at Device Context Index `dci` (0 for the slot) as to be evaluated.
+    pub fn add(&mut self, dci: u8) {
+        let control = unsafe { &mut *context_at::<[u32; 8]>(&self.buffer, self.context_size, 0) };
+        control[1].set_bit(dci as usize, true);
+    }
+
+    pub fn slot(&mut self) -> &mut SlotContext {
+        unsafe { &mut *context_at(&self.buffer, self.context_size, 1) }
+    }
+
+    pub fn endpoint(&mut self, dci: u8) -> &mut EndpointContext {
+        unsafe { &mut *context_at(&self.buffer, self.context_size, 1 + dci as usize) }
+    }
+}
@@ -0,0 +1,181 @@
+use bit_field::BitField;
+
+// [5.3 Host Controller Capability Registers](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/extensible-host-controler-interface-usb-xhci.pdf)
+const CAPLENGTH: u64 = 0x00;
+const HCSPARAMS1: u64 = 0x04;
+const HCSPARAMS2: u64 = 0x08;
+const HCCPARAMS1: u64 = 0x10;
+const DBOFF: u64 = 0x14;
+const RTSOFF: u64 = 0x18;
+
+// 5.4 Host Controller Operational Registers
+pub const USBCMD: u64 = 0x00;
+pub const USBSTS: u64 = 0x04;
+pub const PAGESIZE: u64 = 0x08;
+pub const CRCR: u64 = 0x18;
+pub const DCBAAP: u64 = 0x30;
+pub const CONFIG: u64 = 0x38;
+const PORT_REGISTER_SET: u64 = 0x400;
+
+pub const USBCMD_RUN_STOP: usize = 0;
+pub const USBCMD_HC_RESET: usize = 1;
+pub const USBCMD_INTERRUPTER_ENABLE: usize = 2;
+
+pub const USBSTS_HC_HALTED: usize = 0;
+pub const USBSTS_EVENT_INTERRUPT: usize = 3;
+pub const USBSTS_CONTROLLER_NOT_READY: usize = 11;
+
+// 5.5 Host Controller Runtime Registers, interrupter 0
+const INTERRUPTER_0: u64 = 0x20;
+pub const IMAN: u64 = INTERRUPTER_0;
+pub const IMOD: u64 = INTERRUPTER_0 + 0x04;
+pub const ERSTSZ: u64 = INTERRUPTER_0 + 0x08;
+pub const ERSTBA: u64 = INTERRUPTER_0 + 0x10;
+pub const ERDP: u64 = INTERRUPTER_0 + 0x18;
+
+pub const IMAN_INTERRUPT_PENDING: usize = 0;
+pub const IMAN_INTERRUPT_ENABLE: usize = 1;
+/// Event Handler Busy, cleared by writing 1 along with the dequeue pointer.
+pub const ERDP_EHB: u64 = 1 << 3;
+
+/// [5.4.8 Port Status and Control Register (PORTSC)](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/extensible-host-controler-interface-usb-xhci.pdf)
+pub struct PortSc;
+
+#[allow(dead_code)]
+impl PortSc {
+    pub const CURRENT_CONNECT_STATUS: u32 = 1 << 0;
+    pub const PORT_ENABLED: u32 = 1 << 1;
+    pub const PORT_RESET: u32 = 1 << 4;
+    pub const PORT_POWER: u32 = 1 << 9;
+    pub const CONNECT_STATUS_CHANGE: u32 = 1 << 17;
+    pub const PORT_ENABLED_CHANGE: u32 = 1 << 18;
+    pub const WARM_RESET_CHANGE: u32 = 1 << 19;
+    pub const OVER_CURRENT_CHANGE: u32 = 1 << 20;
+    pub const PORT_RESET_CHANGE: u32 = 1 << 21;
+    pub const LINK_STATE_CHANGE: u32 = 1 << 22;
+    pub const CONFIG_ERROR_CHANGE: u32 = 1 << 23;
+    pub const CHANGE_BITS: u32 = 0x00fe_0000;
+    /// Bits that keep their value when written back. Everything else is either read-only,
+    /// write-1-to-clear, or has a side effect when written as 1.
+    pub const PRESERVE: u32 = 0x0e00_c3e0;
+
+    pub fn speed(portsc: u32) -> u8 {
+        portsc.get_bits(10..14) as u8
+    }
+}
+
+/// Memory mapped registers of one xHC, addressed by offset from each register block.
+pub struct Registers {
+    capability: u64,
+    operational: u64,
+    runtime: u64,
+    doorbell: u64,
+}
+
+impl Registers {
+    pub fn new(mmio_base: u64) -> Self {
+        let capability = mmio_base;
+        let cap_length = unsafe { ((capability + CAPLENGTH) as *const u8).read_volatile() };
+        let read = |offset| unsafe { ((capability + offset) as *const u32).read_volatile() };
+        Self {
+            capability,
+            operational: capability + cap_length as u64,
+            runtime: capability + (read(RTSOFF) & !0x1f) as u64,
+            doorbell: capability + (read(DBOFF) & !0x3) as u64,
+        }
+    }
+
+    fn read32(addr: u64) -> u32 {
+        unsafe { (addr as *const u32).read_volatile() }
+    }
+
+    fn write32(addr: u64, value: u32) {
+        unsafe { (addr as *mut u32).write_volatile(value) };
+    }
+
+    /// 64-bit registers are accessed as two dwords, low first, which every controller accepts.
+    fn write64(addr: u64, value: u64) {
+        Self::write32(addr, value as u32);
+        Self::write32(addr + 4, (value >> 32) as u32);
+    }
+
+    fn read64(addr: u64) -> u64 {
+        Self::read32(addr) as u64 | (Self::read32(addr + 4) as u64) << 32
+    }
+
+    pub fn capability(&self, offset: u64) -> u32 {
+        Self::read32(self.capability + offset)
+    }
+
+    pub fn max_slots(&self) -> u8 {
+        self.capability(HCSPARAMS1).get_bits(0..8) as u8
+    }
+
+    pub fn max_ports(&self) -> u8 {
+        self.capability(HCSPARAMS1).get_bits(24..32) as u8
+    }
+
+    pub fn max_scratchpad_buffers(&self) -> usize {
+        let params = self.capability(HCSPARAMS2);
+        (params.get_bits(21..26) << 5 | params.get_bits(27..32)) as usize
+    }
+
+    /// Whether contexts are 64 bytes rather than 32 bytes.
+    pub fn context_size_64(&self) -> bool {
+        self.capability(HCCPARAMS1).get_bit(2)
+    }
+
+    /// Offset of the first extended capability, in bytes from the capability registers.
+    pub fn extended_capabilities(&self) -> Option<u64> {
+        match self.capability(HCCPARAMS1).get_bits(16..32) {
+            0 => None,
+            dwords => Some(dwords as u64 * 4),
+        }
+    }
+
+    pub fn set_capability(&self, offset: u64, value: u32) {
+        Self::write32(self.capability + offset, value);
+    }
+
+    pub fn operational(&self, offset: u64) -> u32 {
+        Self::read32(self.operational + offset)
+    }
+
+    pub fn set_operational(&self, offset: u64, value: u32) {
+        Self::write32(self.operational + offset, value);
+    }
+
+    pub fn set_operational64(&self, offset: u64, value: u64) {
+        Self::write64(self.operational + offset, value);
+    }
+
+    /// Ports are numbered from 1.
+    pub fn portsc(&self, port: u8) -> u32 {
+        self.operational(PORT_REGISTER_SET + 0x10 * (port as u64 - 1))
+    }
+
+    pub fn set_portsc(&self, port: u8, value: u32) {
+        self.set_operational(PORT_REGISTER_SET + 0x10 * (port as u64 - 1), value);
+    }
+
+    pub fn runtime(&self, offset: u64) -> u32 {
+        Self::read32(self.runtime + offset)
+    }
+
+    pub fn set_runtime(&self, offset: u64, value: u32) {
+        Self::write32(self.runtime + offset, value);
+    }
+
+    pub fn runtime64(&self, offset: u64) -> u64 {
+        Self::read64(self.runtime + offset)
+    }
+
+    pub fn set_runtime64(&self, offset: u64, value: u64) {
+        Self::write64(self.runtime + offset, value);
+    }
+
+    /// Doorbell 0 belongs to the command ring, doorbell `n` to device slot `n`.
+    pub fn ring_doorbell(&self, index: u8, target: u8) {
+        Self::write32(self.doorbell + 4 * index as u64, target as u32);
+    }
+}
@@ -0,0 +1,132 @@
+use super::{
+    registers::{ERDP, ERDP_EHB, ERSTBA, ERSTSZ, Registers},
+    trb::Trb,
+};
+use crate::{Result, usb::memory::DmaBuffer};
+
+/// Command and transfer ring: a single segment closed by a Link TRB that points back to
+/// its start and toggles the producer cycle state.
+///
+/// [4.9.2 Transfer Ring Management](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/extensible-host-controler-interface-usb-xhci.pdf)
+pub struct Ring {
+    buffer: DmaBuffer,
+    len: usize,
+    index: usize,
+    cycle: bool,
+}
+
+impl Ring {
+    pub fn new(len: usize) -> Result<Self> {
+        Ok(Self {
+            buffer: DmaBuffer::new(len * size_of::<Trb>(), 64)?,
+            len,
+            index: 0,
+            cycle: true,
+        })
+    }
+
+    pub fn phys(&self) -> u64 {
+        self.buffer.phys()
+    }
+
+    /// Value for a dequeue pointer register or field: the next free TRB and the cycle state.
+    pub fn dequeue_pointer(&self) -> u64 {
+        self.trb_phys(self.index) | self.cycle as u64
+    }
+
+    fn trb_phys(&self, index: usize) -> u64 {
+        self.phys() + (index * size_of::<Trb>()) as u64
+    }
+
+    /// The cycle bit is written last so that the controller never sees a half-written TRB.
+    fn write(&mut self, mut trb: Trb) {
+        trb.set_cycle(self.cycle);
+        let p = unsafe { self.buffer.as_ptr::<Trb>().add(self.index) };
+        unsafe {
+            (&raw mut (*p).parameter).write_volatile(trb.parameter);
+            (&raw mut (*p).status).write_volatile(trb.status);
+            (&raw mut (*p).control).write_volatile(trb.control);
+        }
+    }
+
+    /// Enqueues `trb` and returns its physical address, which events refer back to.
+    pub fn push(&mut self, trb: Trb) -> u64 {
+        let phys = self.trb_phys(self.index);
+        self.write(trb);
+        self.index += 1;
+        if self.index == self.len - 1 {
+            self.write(Trb::link(self.phys()));
+            self.index = 0;
+            self.cycle = !self.cycle;
+        }
+        phys
+    }
+}
+
+/// Event Ring Segment Table entry.
+#[repr(C)]
+struct SegmentTableEntry {
+    base: u64,
+    size: u32,
+    _reserved: u32,
+}
+
+/// Event ring of one interrupter, consisting of a single segment.
+///
+/// [4.9.4 Event Ring Management](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/extensible-host-controler-interface-usb-xhci.pdf)
+pub struct EventRing {
+    segment: DmaBuffer,
+    table: DmaBuffer,
+    len: usize,
+    index: usize,
+    cycle: bool,
+}
+
+impl EventRing {
+    pub fn new(len: usize) -> Result<Self> {
+        let segment = DmaBuffer::new(len * size_of::<Trb>(), 64)?;
+        let table = DmaBuffer::new(size_of::<SegmentTableEntry>(), 64)?;
+        unsafe {
+            table
+                .as_ptr::<SegmentTableEntry>()
+                .write(SegmentTableEntry {
+                    base: segment.phys(),
+                    size: len as u32,
+                    _reserved: 0,
+                })
+        };
+        Ok(Self {
+            segment,
+            table,
+            len,
+            index: 0,
+            cycle: true,
+        })
+    }
+
+    /// Points interrupter 0 at the ring. Writing ERSTBA last enables it.
+    pub fn install(&self, registers: &Registers) {
+        registers.set_runtime(ERSTSZ, 1);
+        registers.set_runtime64(ERDP, self.segment.phys());
+        registers.set_runtime64(ERSTBA, self.table.phys());
+    }
+
+    /// The oldest event the controller has written, if any.
+    pub fn front(&self) -> Option<Trb> {
+        let p = unsafe { self.segment.as_ptr::<Trb>().add(self.index) };
+        let trb = unsafe { p.read_volatile() };
+        (trb.cycle() == self.cycle).then_some(trb)
+    }
+
+    /// Consumes the front event and tells the controller that its slot is free again.
+    pub fn pop(&mut self, registers: &Registers) {
+        self.index += 1;
+        if self.index == self.len {
+            self.index = 0;
+            self.cycle = !self.cycle;
+        }
+        let dequeue = self.segment.phys() + (self.index * size_of::<Trb>()) as u64;
+        let erdp = registers.runtime64(ERDP) & 0xf & !ERDP_EHB;
+        registers.set_runtime64(ERDP, dequeue | erdp | ERDP_EHB);
+    }
+}
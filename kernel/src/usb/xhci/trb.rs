@@ -0,0 +1,204 @@
+use crate::usb::descriptor::SetupData;
+use bit_field::BitField;
+
+/// [6.4 Transfer Request Block (TRB)](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/extensible-host-controler-interface-usb-xhci.pdf)
+#[repr(C, align(16))]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Trb {
+    pub parameter: u64,
+    pub status: u32,
+    pub control: u32,
+}
+
+/// [6.4.6 TRB Types](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/extensible-host-controler-interface-usb-xhci.pdf)
+pub struct TrbType;
+
+#[allow(dead_code)]
+impl TrbType {
+    pub const NORMAL: u8 = 1;
+    pub const SETUP_STAGE: u8 = 2;
+    pub const DATA_STAGE: u8 = 3;
+    pub const STATUS_STAGE: u8 = 4;
+    pub const LINK: u8 = 6;
+    pub const ENABLE_SLOT_COMMAND: u8 = 9;
+    pub const DISABLE_SLOT_COMMAND: u8 = 10;
+    pub const ADDRESS_DEVICE_COMMAND: u8 = 11;
+    pub const CONFIGURE_ENDPOINT_COMMAND: u8 = 12;
+    pub const EVALUATE_CONTEXT_COMMAND: u8 = 13;
+    pub const RESET_ENDPOINT_COMMAND: u8 = 14;
+    pub const SET_TR_DEQUEUE_POINTER_COMMAND: u8 = 16;
+    pub const TRANSFER_EVENT: u8 = 32;
+    pub const COMMAND_COMPLETION_EVENT: u8 = 33;
+    pub const PORT_STATUS_CHANGE_EVENT: u8 = 34;
+    pub const HOST_CONTROLLER_EVENT: u8 = 37;
+}
+
+/// [6.4.5 TRB Completion Codes](https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/extensible-host-controler-interface-usb-xhci.pdf)
+pub struct CompletionCode;
+
+#[allow(dead_code)]
+impl CompletionCode {
+    pub const SUCCESS: u8 = 1;
+    pub const USB_TRANSACTION_ERROR: u8 = 4;
+    pub const TRB_ERROR: u8 = 5;
+    pub const STALL_ERROR: u8 = 6;
+    pub const SHORT_PACKET: u8 = 13;
+}
+
+const CYCLE: usize = 0;
+const TOGGLE_CYCLE: usize = 1;
+const INTERRUPT_ON_SHORT_PACKET: usize = 2;
+const INTERRUPT_ON_COMPLETION: usize = 5;
+const IMMEDIATE_DATA: usize = 6;
+const BLOCK_SET_ADDRESS_REQUEST: usize = 9;
+const DIRECTION_IN: usize = 16;
+
+/// Transfer Type of a Setup Stage TRB.
+const NO_DATA_STAGE: u32 = 0;
+const OUT_DATA_STAGE: u32 = 2;
+const IN_DATA_STAGE: u32 = 3;
+
+impl Trb {
+    fn new(trb_type: u8) -> Self {
+        let mut control = 0;
+        control.set_bits(10..16, trb_type as u32);
+        Self {
+            control,
+            ..Default::default()
+        }
+    }
+
+    fn with_slot(mut self, slot_id: u8) -> Self {
+        self.control.set_bits(24..32, slot_id as u32);
+        self
+    }
+
+    fn with_interrupt_on_completion(mut self) -> Self {
+        self.control.set_bit(INTERRUPT_ON_COMPLETION, true);
+        self
+    }
+
+    pub fn trb_type(&self) -> u8 {
+        self.control.get_bits(10..16) as u8
+    }
+
+    pub fn cycle(&self) -> bool {
+        self.control.get_bit(CYCLE)
+    }
+
+    pub fn set_cycle(&mut self, cycle: bool) {
+        self.control.set_bit(CYCLE, cycle);
+    }
+
+    pub fn completion_code(&self) -> u8 {
+        self.status.get_bits(24..32) as u8
+    }
+
+    /// Bytes that were not transferred, for a transfer event.
+    pub fn residual_length(&self) -> usize {
+        self.status.get_bits(0..24) as usize
+    }
+
+    pub fn slot_id(&self) -> u8 {
+        self.control.get_bits(24..32) as u8
+    }
+
+    /// Device Context Index of a transfer event.
+    pub fn endpoint_id(&self) -> u8 {
+        self.control.get_bits(16..21) as u8
+    }
+
+    /// Root hub port number of a port status change event.
+    pub fn port_id(&self) -> u8 {
+        self.parameter.get_bits(24..32) as u8
+    }
+
+    pub fn link(segment: u64) -> Self {
+        let mut trb = Self::new(TrbType::LINK);
+        trb.parameter = segment;
+        trb.control.set_bit(TOGGLE_CYCLE, true);
+        trb
+    }
+
+    pub fn enable_slot() -> Self {
+        Self::new(TrbType::ENABLE_SLOT_COMMAND)
+    }
+
+    pub fn disable_slot(slot_id: u8) -> Self {
+        Self::new(TrbType::DISABLE_SLOT_COMMAND).with_slot(slot_id)
+    }
+
+    pub fn address_device(input_context: u64, slot_id: u8, block_set_address: bool) -> Self {
+        let mut trb = Self::new(TrbType::ADDRESS_DEVICE_COMMAND).with_slot(slot_id);
+        trb.parameter = input_context;
+        trb.control
+            .set_bit(BLOCK_SET_ADDRESS_REQUEST, block_set_address);
+        trb
+    }
+
+    pub fn configure_endpoint(input_context: u64, slot_id: u8) -> Self {
+        let mut trb = Self::new(TrbType::CONFIGURE_ENDPOINT_COMMAND).with_slot(slot_id);
+        trb.parameter = input_context;
+        trb
+    }
+
+    pub fn evaluate_context(input_context: u64, slot_id: u8) -> Self {
+        let mut trb = Self::new(TrbType::EVALUATE_CONTEXT_COMMAND).with_slot(slot_id);
+        trb.parameter = input_context;
+        trb
+    }
+
+    pub fn reset_endpoint(slot_id: u8, dci: u8) -> Self {
+        let mut trb = Self::new(TrbType::RESET_ENDPOINT_COMMAND).with_slot(slot_id);
+        trb.control.set_bits(16..21, dci as u32);
+        trb
+    }
+
+    /// `dequeue` carries the dequeue cycle state in bit 0.
+    pub fn set_tr_dequeue_pointer(slot_id: u8, dci: u8, dequeue: u64) -> Self {
+        let mut trb = Self::new(TrbType::SET_TR_DEQUEUE_POINTER_COMMAND).with_slot(slot_id);
+        trb.parameter = dequeue;
+        trb.control.set_bits(16..21, dci as u32);
+        trb
+    }
+
+    /// The eight bytes of the request travel inside the TRB itself.
+    pub fn setup_stage(setup: &SetupData) -> Self {
+        let mut trb = Self::new(TrbType::SETUP_STAGE);
+        trb.parameter = setup.to_u64();
+        trb.status = 8;
+        trb.control.set_bit(IMMEDIATE_DATA, true);
+        let transfer_type = match (setup.length(), setup.is_in()) {
+            (0, _) => NO_DATA_STAGE,
+            (_, true) => IN_DATA_STAGE,
+            (_, false) => OUT_DATA_STAGE,
+        };
+        trb.control.set_bits(16..18, transfer_type);
+        trb
+    }
+
+    pub fn data_stage(buffer: u64, len: usize, dir_in: bool) -> Self {
+        let mut trb = Self::new(TrbType::DATA_STAGE);
+        trb.parameter = buffer;
+        trb.status = len as u32;
+        trb.control
+            .set_bit(DIRECTION_IN, dir_in)
+            .set_bit(INTERRUPT_ON_SHORT_PACKET, dir_in);
+        trb
+    }
+
+    /// The status stage runs in the direction opposite to the data stage, or IN without one.
+    pub fn status_stage(dir_in: bool) -> Self {
+        let mut trb = Self::new(TrbType::STATUS_STAGE).with_interrupt_on_completion();
+        trb.control.set_bit(DIRECTION_IN, dir_in);
+        trb
+    }
+
+    pub fn normal(buffer: u64, len: usize) -> Self {
+        let mut trb = Self::new(TrbType::NORMAL).with_interrupt_on_completion();
+        trb.parameter = buffer;
+        trb.status = len as u32;
+        trb.control.set_bit(INTERRUPT_ON_SHORT_PACKET, true);
+        trb
+    }
+}
@@ -1,13 +1,19 @@
 use core::arch::asm;
 
-unsafe extern "C" {
-    fn SetCSSS(cs: u16, ss: u16);
-}
-
 pub fn halt() {
     unsafe { asm!("hlt") };
 }
 
+pub fn io_out8(addr: u16, data: u8) {
+    unsafe { asm!("out dx, al", in("dx") addr, in("al") data) };
+}
+
+pub fn io_in8(addr: u16) -> u8 {
+    let a;
+    unsafe { asm!("in al, dx", in("dx") addr, out("al") a) };
+    a
+}
+
 pub fn io_out32(addr: u16, data: u32) {
     unsafe { asm!("out dx, eax", in("dx") addr, in("eax") data) };
 }
@@ -26,6 +32,24 @@ pub fn cli() {
     unsafe { asm!("cli") };
 }
 
+pub fn get_rflags() -> u64 {
+    let a;
+    unsafe { asm!("pushfq", "pop {0}", out(reg) a) };
+    a
+}
+
+/// Runs `f` with maskable interrupts disabled and restores the previous interrupt flag.
+pub fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    const INTERRUPT_FLAG: u64 = 1 << 9;
+    let enabled = (get_rflags() & INTERRUPT_FLAG) != 0;
+    cli();
+    let ret = f();
+    if enabled {
+        sti();
+    }
+    ret
+}
+
 pub fn get_cs() -> u16 {
     let a;
     unsafe { asm!("mov {0:x}, cs", out(reg) a) };
@@ -63,6 +87,10 @@ pub fn load_gdt(param: &GdtParam) {
     unsafe { asm!("lgdt [rcx]", in("rcx") param) };
 }
 
+pub fn load_tr(selector: u16) {
+    unsafe { asm!("ltr {0:x}", in(reg) selector) };
+}
+
 pub fn set_ds_all(value: u16) {
     unsafe {
         asm!(
@@ -75,10 +103,59 @@ pub fn set_ds_all(value: u16) {
     };
 }
 
+/// CS can only be loaded by a far transfer, so this returns to the next instruction with
+/// a far return through the new code segment.
 pub fn set_cs_ss(cs: u16, ss: u16) {
-    unsafe { SetCSSS(cs, ss) };
+    unsafe {
+        asm!(
+            "mov ss, {ss:x}",
+            "push {cs}",
+            "lea {rip}, [rip + 2f]",
+            "push {rip}",
+            "retfq",
+            "2:",
+            ss = in(reg) ss,
+            cs = in(reg) cs as u64,
+            rip = lateout(reg) _,
+        )
+    };
 }
 
 pub fn set_cr3(value: u64) {
     unsafe { asm!("mov cr3, {0:r}", in(reg) value) };
 }
+
+pub fn get_cr3() -> u64 {
+    let a;
+    unsafe { asm!("mov {0:r}, cr3", out(reg) a) };
+    a
+}
+
+pub fn get_cr2() -> u64 {
+    let a;
+    unsafe { asm!("mov {0:r}, cr2", out(reg) a) };
+    a
+}
+
+pub fn invlpg(addr: u64) {
+    unsafe { asm!("invlpg [{0:r}]", in(reg) addr) };
+}
+
+/// Flushes all non-global TLB entries by reloading CR3.
+pub fn flush_tlb() {
+    set_cr3(get_cr3());
+}
+
+pub fn cpuid(leaf: u32, sub_leaf: u32) -> (u32, u32, u32, u32) {
+    let r = unsafe { core::arch::x86_64::__cpuid_count(leaf, sub_leaf) };
+    (r.eax, r.ebx, r.ecx, r.edx)
+}
+
+pub fn supports_1gib_pages() -> bool {
+    let (max_extended_leaf, _, _, _) = cpuid(0x8000_0000, 0);
+    if max_extended_leaf < 0x8000_0001 {
+        return false;
+    }
+    let (_, _, _, edx) = cpuid(0x8000_0001, 0);
+    (edx & (1 << 26)) != 0
+}
@@ -57,7 +57,14 @@ pub fn calc_load_address_range(ehdr: &Elf64_Ehdr) -> (u64, u64) {
     (first, last)
 }
 
-pub fn copy_load_segments(system_table: &EFISystemTable, ehdr: &Elf64_Ehdr) -> Result<()> {
+/// Copies every PT_LOAD segment to `load_base + (p_vaddr - virt_base)`, i.e. to the physical
+/// pages backing the image that is linked at `virt_base`.
+pub fn copy_load_segments(
+    system_table: &EFISystemTable,
+    ehdr: &Elf64_Ehdr,
+    load_base: u64,
+    virt_base: u64,
+) -> Result<()> {
     let p_phdr = (ehdr as *const Elf64_Ehdr as usize + ehdr.e_phoff as usize) as *mut Elf64_Phdr;
     let s = unsafe { core::slice::from_raw_parts(p_phdr, ehdr.e_phnum as usize) };
     for phdr in s.iter() {
@@ -67,15 +74,16 @@ pub fn copy_load_segments(system_table: &EFISystemTable, ehdr: &Elf64_Ehdr) -> R
         }
 
         let segm_in_file = ehdr as *const Elf64_Ehdr as usize + phdr.p_offset as usize;
+        let dest = load_base + (phdr.p_vaddr - virt_base);
         (system_table.boot_services.copy_mem)(
-            phdr.p_vaddr as *mut u8,
+            dest as *mut u8,
             segm_in_file as *const u8,
             phdr.p_filesz as usize,
         );
 
         let remain_bytes = phdr.p_memsz - phdr.p_filesz;
         (system_table.boot_services.set_mem)(
-            (phdr.p_vaddr + phdr.p_filesz) as *const u8,
+            (dest + phdr.p_filesz) as *const u8,
             remain_bytes as usize,
             0,
         );
@@ -3,7 +3,6 @@
 #![no_std]
 #![no_main]
 
-use core::arch::asm;
 use core::fmt::Write;
 use core::slice;
 use utf16_lit::utf16_null as w;
@@ -14,9 +13,12 @@ static mut WRITER: Option<EFISimpleTextOutputProtocolWriter<'_>> = None;
 mod macros;
 //mod cube;
 mod elf;
+mod paging;
 mod uefi;
+mod x86;
 
 use elf::{Elf64_Ehdr, calc_load_address_range, copy_load_segments};
+use paging::build_kernel_page_table;
 use share::{
     frame_buffer::{FrameBufferConfig, PixelFormat},
     memory_map::MemoryMap,
@@ -103,27 +105,40 @@ fn efi_main(image_handle: EFIHandle, system_table: &'static EFISystemTable) -> !
     let kernel_base_address = system_table
         .boot_services
         .allocate_pages(
-            EFIAllocateType::AllocateAddress,
+            EFIAllocateType::AllocateAnyPages,
             EFIMemoryType::LoaderData,
             num_pages as usize,
-            first,
+            0,
         )
         .unwrap();
 
     println!("kernel_base_address: 0x{:08x?}", kernel_base_address);
 
-    copy_load_segments(system_table, kernel_ehdr).unwrap();
+    copy_load_segments(system_table, kernel_ehdr, kernel_base_address, first).unwrap();
+
+    let acpi_rsdp = system_table.acpi_rsdp().unwrap();
+    println!("acpi_rsdp: 0x{:08x}", acpi_rsdp);
+
+    let kernel_pml4 = build_kernel_page_table(
+        system_table.boot_services,
+        first,
+        kernel_base_address,
+        num_pages * 0x1000,
+    )
+    .unwrap();
 
     dbg!(kernel_ehdr.entry_addr());
 
     dbg!(graphics_output.mode.info.pixels_per_scan_line);
 
+    // Fetch the memory map again so that it covers the kernel image and page tables.
+    let mut memory_map = system_table.boot_services.get_memory_map().unwrap();
     if system_table
         .boot_services
         .exit_boot_services(image_handle, memory_map.map_key)
         .is_err()
     {
-        let memory_map = system_table.boot_services.get_memory_map().unwrap();
+        memory_map = system_table.boot_services.get_memory_map().unwrap();
         system_table
             .boot_services
             .exit_boot_services(image_handle, memory_map.map_key)
@@ -142,12 +157,14 @@ fn efi_main(image_handle: EFIHandle, system_table: &'static EFISystemTable) -> !
         },
     };
 
+    x86::set_cr3(kernel_pml4);
+
     let entry_point = unsafe {
-        core::mem::transmute::<*const u8, extern "sysv64" fn(&FrameBufferConfig, &MemoryMap)>(
+        core::mem::transmute::<*const u8, extern "sysv64" fn(&FrameBufferConfig, &MemoryMap, u64)>(
             kernel_ehdr.entry_addr() as *const u8,
         )
     };
-    entry_point(&config, &memory_map);
+    entry_point(&config, &memory_map, acpi_rsdp);
 
     // cube::rotate(system_table, frame_buffer);
 
@@ -159,6 +176,6 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
     println!("PANIC!!!!!");
     println!("{info}");
     loop {
-        unsafe { asm!("hlt") };
+        x86::halt();
     }
 }
@@ -0,0 +1,98 @@
+use crate::uefi::{EFIAllocateType, EFIBootServices, EFIMemoryType, Result};
+use share::memory_layout::{DIRECT_MAP_BASE, DIRECT_MAP_SIZE};
+
+const PAGE_SIZE_4K: u64 = 4096;
+const PAGE_SIZE_2M: u64 = 512 * PAGE_SIZE_4K;
+const PRESENT_WRITABLE: u64 = 0x003;
+const HUGE_PAGE: u64 = 0x080;
+const ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// Builds the initial kernel page tables out of `LoaderData` pages so that the kernel can keep
+/// using them after boot services have been exited.
+pub struct PageTableBuilder<'a> {
+    boot_services: &'a EFIBootServices,
+    pml4: u64,
+}
+
+impl<'a> PageTableBuilder<'a> {
+    pub fn new(boot_services: &'a EFIBootServices) -> Result<Self> {
+        let pml4 = allocate_table(boot_services)?;
+        Ok(Self {
+            boot_services,
+            pml4,
+        })
+    }
+
+    pub fn pml4(&self) -> u64 {
+        self.pml4
+    }
+
+    fn next_table(&self, table: u64, index: usize) -> Result<u64> {
+        let entry = entry(table, index);
+        if *entry & 1 == 0 {
+            *entry = allocate_table(self.boot_services)? | PRESENT_WRITABLE;
+        }
+        Ok(*entry & ADDRESS_MASK)
+    }
+
+    pub fn map_4k(&self, virt: u64, phys: u64) -> Result<()> {
+        let pdpt = self.next_table(self.pml4, index(virt, 4))?;
+        let pd = self.next_table(pdpt, index(virt, 3))?;
+        let pt = self.next_table(pd, index(virt, 2))?;
+        *entry(pt, index(virt, 1)) = phys | PRESENT_WRITABLE;
+        Ok(())
+    }
+
+    pub fn map_2m(&self, virt: u64, phys: u64) -> Result<()> {
+        let pdpt = self.next_table(self.pml4, index(virt, 4))?;
+        let pd = self.next_table(pdpt, index(virt, 3))?;
+        *entry(pd, index(virt, 2)) = phys | PRESENT_WRITABLE | HUGE_PAGE;
+        Ok(())
+    }
+}
+
+fn allocate_table(boot_services: &EFIBootServices) -> Result<u64> {
+    let addr = boot_services.allocate_pages(
+        EFIAllocateType::AllocateAnyPages,
+        EFIMemoryType::LoaderData,
+        1,
+        0,
+    )?;
+    (boot_services.set_mem)(addr as *const u8, PAGE_SIZE_4K as usize, 0);
+    Ok(addr)
+}
+
+fn entry(table: u64, index: usize) -> &'static mut u64 {
+    unsafe { &mut *(table as *mut u64).add(index) }
+}
+
+fn index(virt: u64, level: usize) -> usize {
+    ((virt >> (12 + 9 * (level - 1))) & 0x1ff) as usize
+}
+
+/// Maps the low `DIRECT_MAP_SIZE` bytes of physical memory both at identity, so that the loader
+/// keeps running after switching CR3, and at `DIRECT_MAP_BASE`, and maps the kernel image at its
+/// link address.
+pub fn build_kernel_page_table(
+    boot_services: &EFIBootServices,
+    kernel_virt_base: u64,
+    kernel_phys_base: u64,
+    kernel_size: u64,
+) -> Result<u64> {
+    let builder = PageTableBuilder::new(boot_services)?;
+
+    (0..DIRECT_MAP_SIZE)
+        .step_by(PAGE_SIZE_2M as usize)
+        .try_for_each(|phys| {
+            builder.map_2m(phys, phys)?;
+            builder.map_2m(DIRECT_MAP_BASE + phys, phys)
+        })?;
+
+    (0..kernel_size.next_multiple_of(PAGE_SIZE_4K))
+        .step_by(PAGE_SIZE_4K as usize)
+        .try_for_each(|offset| {
+            builder.map_4k(kernel_virt_base + offset, kernel_phys_base + offset)
+        })?;
+
+    Ok(builder.pml4())
+}
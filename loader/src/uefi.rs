@@ -13,6 +13,7 @@ pub struct EFIStatus(pub usize);
 
 #[allow(clippy::upper_case_acronyms)]
 #[repr(C)]
+#[derive(PartialEq, Eq)]
 pub struct GUID {
     data0: u32,
     data1: u16,
@@ -52,6 +53,22 @@ const EFI_FILE_INFO_GUID: GUID = GUID {
     data3: [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
 };
 
+/// [4.6.1. Industry Standard Configuration Tables](https://uefi.org/specs/UEFI/2.11/04_EFI_System_Table.html#industry-standard-configuration-tables)
+const EFI_ACPI_20_TABLE_GUID: GUID = GUID {
+    data0: 0x8868e871,
+    data1: 0xe4f1,
+    data2: 0x11d3,
+    data3: [0xbc, 0x22, 0x00, 0x80, 0xc7, 0x3c, 0x88, 0x81],
+};
+
+/// [4.6.1. Industry Standard Configuration Tables](https://uefi.org/specs/UEFI/2.11/04_EFI_System_Table.html#industry-standard-configuration-tables)
+const ACPI_TABLE_GUID: GUID = GUID {
+    data0: 0xeb9d2d30,
+    data1: 0x2d88,
+    data2: 0x11d3,
+    data3: [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d],
+};
+
 impl EFIStatus {
     pub fn is_success(&self) -> bool {
         self.0.eq(&0)
@@ -125,11 +142,41 @@ pub struct EFISystemTable<'a> {
     std_err: EFIHandle,
     runtime_services: EFIHandle,
     pub boot_services: &'a EFIBootServices,
+    number_of_table_entries: usize,
+    configuration_table: *const EFIConfigurationTable,
+}
+
+impl EFISystemTable<'_> {
+    fn configuration_tables(&self) -> &[EFIConfigurationTable] {
+        unsafe {
+            core::slice::from_raw_parts(self.configuration_table, self.number_of_table_entries)
+        }
+    }
+
+    fn find_configuration_table(&self, guid: &GUID) -> Option<u64> {
+        self.configuration_tables()
+            .iter()
+            .find(|table| table.vendor_guid.eq(guid))
+            .map(|table| table.vendor_table as u64)
+    }
+
+    /// Physical address of the ACPI RSDP, preferring the ACPI 2.0+ entry.
+    pub fn acpi_rsdp(&self) -> Option<u64> {
+        self.find_configuration_table(&EFI_ACPI_20_TABLE_GUID)
+            .or_else(|| self.find_configuration_table(&ACPI_TABLE_GUID))
+    }
+}
+
+/// [4.6. EFI Configuration Table & Properties Table](https://uefi.org/specs/UEFI/2.11/04_EFI_System_Table.html#efi-configuration-table-properties-table)
+#[repr(C)]
+struct EFIConfigurationTable {
+    vendor_guid: GUID,
+    vendor_table: *const u8,
 }
 
 const _: () = {
     use core::mem::offset_of;
-    ["size"][size_of::<EFISystemTable>() - 104];
+    ["size"][size_of::<EFISystemTable>() - 120];
     ["header"][offset_of!(EFISystemTable, header)];
     ["vender"][offset_of!(EFISystemTable, firmware_vendor) - 24];
     ["revision"][offset_of!(EFISystemTable, firmware_revision) - 32];
@@ -137,6 +184,8 @@ const _: () = {
     ["conin"][offset_of!(EFISystemTable, con_in) - 48];
     ["con_out_handle"][offset_of!(EFISystemTable, console_out_handle) - 56];
     ["conout"][offset_of!(EFISystemTable, con_out) - 64];
+    ["boot_services"][offset_of!(EFISystemTable, boot_services) - 96];
+    ["configuration_table"][offset_of!(EFISystemTable, configuration_table) - 112];
 };
 
 /// [12.4.1 EFI_SIMPLE_TEXT_OUTPUT_PROTOCOL](https://uefi.org/specs/UEFI/2.11/12_Protocols_Console_Support.html#efi-simple-text-output-protocol)
@@ -3,3 +3,7 @@ use core::arch::asm;
 pub fn halt() {
     unsafe { asm!("hlt") };
 }
+
+pub fn set_cr3(value: u64) {
+    unsafe { asm!("mov cr3, {0:r}", in(reg) value) };
+}
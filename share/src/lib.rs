@@ -1,4 +1,5 @@
 #![no_std]
 
 pub mod frame_buffer;
+pub mod memory_layout;
 pub mod memory_map;
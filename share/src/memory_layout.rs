@@ -0,0 +1,9 @@
+/// Virtual address the kernel image is linked at. The loader maps it to wherever it placed the
+/// image in physical memory.
+pub const KERNEL_BASE: u64 = 0xffff_ffff_8000_0000;
+
+/// Physical memory is mapped linearly starting at this address.
+pub const DIRECT_MAP_BASE: u64 = 0xffff_8000_0000_0000;
+
+/// Amount of physical memory covered by the direct map the loader sets up.
+pub const DIRECT_MAP_SIZE: u64 = 64 * 1024 * 1024 * 1024;